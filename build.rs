@@ -0,0 +1,94 @@
+//! Checks the built-in syscall tables and turns them into Rust, so a typo in
+//! one fails the build (with the file and line) instead of crashing an
+//! analysis.
+
+use std::collections::HashSet;
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+#[path = "src/syscall_definitions.rs"]
+mod syscall_definitions;
+
+use syscall_definitions::SyscallDefinition;
+
+/// The tables to build, as (CSV file, name of the generated static)
+const TABLES: &[(&str, &str)] = &[
+    ("src/syscalls.csv",     "SYSCALL_TABLE"),
+    ("src/syscalls_x86.csv", "SYSCALL_TABLE_X86"),
+];
+
+/// Parse a table, returning every problem with it (not just the first)
+fn read_table(path: &str) -> Result<Vec<SyscallDefinition>, Vec<String>> {
+    let data = fs::read_to_string(path).map_err(|e| vec![format!("{}: {}", path, e)])?;
+
+    let mut out = vec![];
+    let mut errors = vec![];
+    let mut numbers = HashSet::new();
+
+    for (i, line) in data.lines().enumerate() {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        // The tables don't use quoting, so they can just be split
+        if line.contains('"') {
+            errors.push(format!("{}:{}: quoted fields aren't supported", path, i + 1));
+            continue;
+        }
+
+        match SyscallDefinition::parse(&line.split(',').collect::<Vec<&str>>()) {
+            Ok(syscall) if !numbers.insert(syscall.number) => errors.push(format!("{}:{}: syscall {} is defined more than once", path, i + 1, syscall.number)),
+            Ok(syscall) => out.push(syscall),
+            Err(e) => errors.push(format!("{}:{}: {}", path, i + 1, e)),
+        }
+    }
+
+    match errors.is_empty() {
+        true  => Ok(out),
+        false => Err(errors),
+    }
+}
+
+fn main() {
+    // Without the `syscalls` feature, the tables aren't built in at all
+    if env::var_os("CARGO_FEATURE_SYSCALLS").is_none() {
+        return;
+    }
+
+    println!("cargo:rerun-if-changed=src/syscall_definitions.rs");
+
+    let mut code = String::new();
+    let mut errors = vec![];
+
+    for (path, name) in TABLES {
+        println!("cargo:rerun-if-changed={}", path);
+
+        let syscalls = match read_table(path) {
+            Ok(syscalls) => syscalls,
+            Err(e) => {
+                errors.extend(e);
+                continue;
+            }
+        };
+
+        writeln!(code, "/// Generated from `{}` by build.rs", path).unwrap();
+        writeln!(code, "pub static {}: &[GeneratedSyscall] = &[", name).unwrap();
+        for syscall in syscalls {
+            let params: Vec<String> = syscall.params.iter().map(|p| {
+                format!("({:?}, {}, {:?}, {})", p.field_type, p.is_pointer, p.field_name, p.is_array)
+            }).collect();
+
+            writeln!(code, "    ({}, {:?}, &[{}]),", syscall.number, syscall.name, params.join(", ")).unwrap();
+        }
+        writeln!(code, "];").unwrap();
+    }
+
+    if !errors.is_empty() {
+        panic!("The syscall tables have errors:\n{}", errors.join("\n"));
+    }
+
+    let out = Path::new(&env::var("OUT_DIR").unwrap()).join("syscall_tables.rs");
+    fs::write(out, code).unwrap();
+}
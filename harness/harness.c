@@ -1,3 +1,4 @@
+#include <errno.h>
 #include <stdio.h>
 #include <unistd.h>
 #include <string.h>
@@ -5,18 +6,42 @@
 #include <sys/stat.h>
 #include <stdlib.h>
 
+// Mandrake passes a pipe as this fd for our own messages, so they aren't
+// mixed in with whatever the code writes to stderr
+#define DIAGNOSTICS_FD 3
+
 int main(int argc, char *argv[]){
+  // If we weren't given the pipe (eg, we're being run by hand), just use
+  // stderr
+  FILE *diagnostics = fdopen(DIAGNOSTICS_FD, "w");
+  if(!diagnostics) {
+    diagnostics = stderr;
+  }
+
   if(argc != 2) {
-    printf("Usage: %s <hex code>\n", argv[0]);
+    fprintf(diagnostics, "Usage: %s <hex code>\n", argv[0]);
     exit(1);
   }
 
   // Note: It's important that this uses 0x13370000 * 0xFFFF0000, because
   // The `mandrake` binary requires that
   unsigned char *a = mmap((void*)0x13370000, strlen(argv[1]) / 2, PROT_EXEC |PROT_READ | PROT_WRITE, MAP_ANONYMOUS | MAP_SHARED, -1, 0);
+  if(a == MAP_FAILED) {
+    fprintf(diagnostics, "Couldn't map %zu bytes for the code: %s\n", strlen(argv[1]) / 2, strerror(errno));
+    exit(1);
+  }
+
   int i;
   for(i = 0; i < strlen(argv[1]); i += 2) {
-    sscanf(argv[1] + i, "%2hhx", (char*)&a[i / 2]);
+    if(sscanf(argv[1] + i, "%2hhx", (char*)&a[i / 2]) != 1) {
+      fprintf(diagnostics, "Invalid hex at offset %d\n", i);
+      exit(1);
+    }
+  }
+
+  // The code shouldn't see (or be able to write to) the pipe
+  if(diagnostics != stderr) {
+    fclose(diagnostics);
   }
 
   /* Give it 10 seconds to run before killing the process with SIGALRM */
@@ -5,26 +5,87 @@
 //! then try to parse it either as an instruction or a string. That may or
 //! may not work, and it may or may not produce valid output - we do what we
 //! can!
+use std::collections::HashSet;
 use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
 
 use byteorder::{LittleEndian, WriteBytesExt};
-use iced_x86::{Decoder, DecoderOptions, Formatter, NasmFormatter};
+use iced_x86::{Code, Decoder, DecoderOptions, Formatter, Instruction, InstructionInfoFactory, NasmFormatter};
+use log::{debug, trace};
+use nix::errno::Errno;
 use nix::sys::ptrace::{read, AddressType};
+use nix::sys::uio::{process_vm_readv, IoVec, RemoteIoVec};
 use nix::unistd::Pid;
 use serde::{Serialize, Deserialize};
 
-use crate::syscalls::{SyscallEntry, SYSCALLS};
+use crate::constants::Constants;
+use crate::memory_cache::MemoryCache;
+use crate::syscalls::{Syscall, SyscallAbi, SyscallEntry};
 
-// We initially read this much so we can look for strings and code
-const INITIAL_SNIPPIT_LENGTH: usize = 128;
+/// By default, we read this much (or the snippit length, if it's longer) so
+/// we can look for strings and code
+pub const DEFAULT_ANALYSIS_WINDOW: usize = 128;
 
-const MAX_SYSCALL_MEMORY_SNIPPIT: usize = 8;
+/// By default, show this many bytes of the memory a syscall argument points to
+pub const DEFAULT_SYSCALL_SNIPPIT_LENGTH: usize = 8;
+
+// The biggest sockaddr we decode (a `struct sockaddr_un`)
+const MAX_SOCKADDR_LENGTH: usize = 110;
+
+// The address families we can decode
+const AF_UNIX: u16 = 1;
+const AF_INET: u16 = 2;
+const AF_INET6: u16 = 10;
+
+// The most we'll show of a syscall buffer that has a length
+const MAX_SYSCALL_BUFFER: usize = 4096;
+
+// The longest possible x86 instruction is 15 bytes
+pub(crate) const MAX_INSTRUCTION_LENGTH: usize = 16;
+
+// The most pointers we'll follow from a single value, no matter what depth
+// was requested
+const MAX_DEREF_READS: usize = 16;
+
+// Negative numbers this close to zero are worth showing as signed
+const MIN_INTERESTING_SIGNED: i64 = -0x10000;
+
+// Syscalls return -1 to -4095 for errors (-errno)
+const MIN_ERRNO_RETURN: i64 = -4095;
+
+// Doubles outside this range are probably just noise
+const MIN_INTERESTING_F64: f64 = 1e-6;
+const MAX_INTERESTING_F64: f64 = 1e12;
+
+// At least this much of a string has to be printable for us to call it one
+const MIN_PRINTABLE_FRACTION: f64 = 0.8;
+
+/// The Shannon entropy of some bytes, in bits per byte (0 for nothing, or
+/// for the same byte over and over, up to 8 for random data). Compiled code
+/// is usually around 5 - 6 (short code is lower, since there are fewer bytes
+/// to spread out), so much higher than that suggests it's packed or
+/// encrypted.
+pub fn shannon_entropy(data: &[u8]) -> f64 {
+    let mut counts = [0usize; 256];
+    for byte in data {
+        counts[*byte as usize] += 1;
+    }
+
+    let total = data.len() as f64;
+    counts.iter()
+        .filter(|count| **count > 0)
+        .map(|count| {
+            let p = *count as f64 / total;
+            -p * p.log2()
+        })
+        .sum()
+}
 
 /// A serializable, analyzed value.
 ///
 /// Be careful changing this! Things that consume Mandrake's output depend on
 /// the structure not changing.
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct AnalyzedValue {
     // The value
     pub value: u64,
@@ -41,13 +102,75 @@ pub struct AnalyzedValue {
     // Keep track of what's an instruction pointer (for nicer output)
     pub is_instruction_pointer: bool,
 
+    // For the instruction pointer, how the instruction affects control flow
+    // (eg, "Next", "Call", "Return", "ConditionalBranch")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flow_control: Option<String>,
+
+    // For the instruction pointer, the instruction's encoding (as hex), its
+    // length, and iced's name for the mnemonic (eg, "Mov", "Jne")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instruction_bytes: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instruction_length: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mnemonic: Option<String>,
+
+    // For the instruction pointer, the next few instructions after this one
+    // (with their addresses), as far as they decode
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upcoming: Option<Vec<String>>,
+
+    // If the value points at a pointer, what that points to (and so on, up
+    // to the requested depth)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub points_to: Option<Box<AnalyzedValue>>,
+
+    // Where the value points (eg, "stack", "heap", "libc.so.6", "unmapped"),
+    // see [`crate::memory_map::MemoryMap::classify`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+
+    // Other ways of reading the value, only set when they look meaningful:
+    // small negative numbers, an errno for a syscall-style error return, and
+    // sensible-looking doubles
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub as_signed: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub as_errno: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub as_f64: Option<f64>,
+
+    // Set if the memory ran into an unreadable page, so it's shorter than
+    // the snippit length
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub memory_truncated: bool,
+
+    // The entropy of the memory, in bits per byte (see
+    // [`shannon_entropy`]), if it was asked for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_entropy: Option<f64>,
+
     // Extra info, if we have any
     pub extra: Option<Vec<String>>,
+
+    // When memory is deduplicated, the key of this value's memory in the
+    // output's `memory_pool` (`memory` is None in that case)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_ref: Option<String>,
 }
 
 impl AnalyzedValue {
-    fn syscall_param(pid: Pid, s: &SyscallEntry, r: &AnalyzedValue) -> String {
-        if s.is_array {
+    /// Describe one syscall parameter - `args` are the values of all six
+    /// (for parameters that depend on another one, like a buffer's length).
+    fn syscall_param(memory: &MemoryCache, s: &SyscallEntry, r: &AnalyzedValue, args: &[u64; 6], snippit_length: usize) -> String {
+        if let Some(decoder) = s.decoder {
+            format!("`{}` (0x{:x})", decoder(r.value), r.value)
+        } else if let Some(region) = memory.denied_region(r.value).filter(|_| s.is_pointer || s.is_array) {
+            format!("0x{:08x} (not read, region `{}` isn't allowed)", r.value, region)
+        } else if let Some(length) = s.length_from.and_then(|i| args.get(i)) {
+            Self::syscall_buffer(memory, r.value, *length, s.is_output)
+        } else if s.is_array {
             // Ensure it's a pointer
             if r.value != 0 {
                 // Create a vector of the arguments
@@ -56,7 +179,7 @@ impl AnalyzedValue {
                 // Loop through the arguments
                 for i in 0.. {
                     // Get the address of the next potential string
-                    let addr = Self::get_memory_as_u64(pid, r.value + (i * 8));
+                    let addr = memory.read_u64(r.value + (i * 8));
 
                     // Break on invalid memory
                     let addr = match addr {
@@ -70,7 +193,7 @@ impl AnalyzedValue {
                     }
 
                     // Get the string there
-                    let a = Self::new(pid, addr, false, 0, 0);
+                    let a = Self::new(memory, addr, false, DEFAULT_ANALYSIS_WINDOW, 0, 0, usize::MAX);
 
                     // Break if there's no string
                     let as_string = match a.as_string {
@@ -87,31 +210,46 @@ impl AnalyzedValue {
                 "(Empty array)".to_string()
             }
         } else if s.is_string {
-            match &r.as_string {
+            // The value might not have been analyzed (eg, for the syscall
+            // summary), so read the string ourselves if we have to
+            let as_string = r.as_string.clone()
+                .or_else(|| Self::new(memory, r.value, false, DEFAULT_ANALYSIS_WINDOW, 0, 0, usize::MAX).as_string);
+
+            match as_string {
                 Some(s) => format!("`{}`", &s),
                 None => format!("Invalid string: 0x{:08x}", r.value),
             }
         } else if s.field_type == "struct sockaddr" {
-            let data = Self::new(pid, r.value, false, 10, 0);
-            match data.memory {
-                Some(m) => {
-                    if m[0] == 2 && m[1] == 0 {
-                        let port = (m[2] as u16) << 8 | (m[3] as u16);
-                        let ip = format!("{}.{}.{}.{}", m[4], m[5], m[6], m[7]);
-
-                        format!("IPv4 address: `{}:{}`", ip, port)
-                    } else {
-                        format!("Unknown sockaddr type (not AF_INET): 0x{:04x}", ((m[1] as u16) << 8) | (m[0] as u16))
-                    }
-                },
-                None => format!("Invalid sockaddr pointer: 0x{:08x}", r.value),
+            if r.value == 0 {
+                "(nil)".to_string()
+            } else if s.is_output {
+                format!("0x{:08x} (filled in by the syscall)", r.value)
+            } else {
+                match memory.read(r.value, MAX_SOCKADDR_LENGTH) {
+                    Some((data, _)) => match Self::decode_sockaddr(&data) {
+                        Some(address) => address,
+
+                        // If we can't make sense of it, just show where it is
+                        None => format!("0x{:08x} (unknown sockaddr)", r.value),
+                    },
+                    None => format!("Invalid sockaddr pointer: 0x{:08x}", r.value),
+                }
             }
         } else if s.is_pointer {
             if r.value == 0 {
                 "(nil)".to_string()
             } else {
-                match &r.memory {
-                    Some(mem) => format!("`{}...`", hex::encode(&mem[..MAX_SYSCALL_MEMORY_SNIPPIT])),
+                // The buffer can be cut short by an unreadable page, so show
+                // whatever we have
+                match memory.read(r.value, snippit_length) {
+                    Some((mem, truncated)) => {
+                        let shown: Vec<u8> = mem.iter().take(snippit_length).copied().collect();
+
+                        match truncated {
+                            true  => format!("`{}` (only {} bytes readable)", hex::encode(&shown), shown.len()),
+                            false => format!("`{}...`", hex::encode(&shown)),
+                        }
+                    },
                     None => format!("Invalid memory pointer: 0x{:08x}", r.value),
                 }
             }
@@ -120,134 +258,574 @@ impl AnalyzedValue {
         }
     }
 
-    pub fn syscall_info(pid: Pid, rax: &AnalyzedValue, rdi: &AnalyzedValue, rsi: &AnalyzedValue, rdx: &AnalyzedValue, r10: &AnalyzedValue, r8: &AnalyzedValue, r9: &AnalyzedValue) -> Vec<String> {
-        match SYSCALLS.get(&rax.value) {
-            Some(s) => {
-                let mut out = vec![format!("Syscall: `{}`", s.name)]; // The syscall number
+    /// Decode a `struct sockaddr` (eg, `` `192.168.1.5:4444` (AF_INET) ``), if
+    /// it's a family we understand and there's enough of it.
+    fn decode_sockaddr(data: &[u8]) -> Option<String> {
+        let family = u16::from_le_bytes(data.get(0..2)?.try_into().ok()?);
 
-                if let Some(param) = &s.rdi {
-                    out.push(format!("{} (rdi) = {}", param.field_name, Self::syscall_param(pid, &param, rdi)));
-                }
+        // The port is in network byte order
+        let port = |data: &[u8]| Some(u16::from_be_bytes(data.get(2..4)?.try_into().ok()?));
 
-                if let Some(param) = &s.rsi {
-                    out.push(format!("{} (rsi) = {}", param.field_name, Self::syscall_param(pid, &param, rsi)));
-                }
+        match family {
+            AF_UNIX => {
+                let path = data.get(2..)?;
 
-                if let Some(param) = &s.rdx {
-                    out.push(format!("{} (rdx) = {}", param.field_name, Self::syscall_param(pid, &param, rdx)));
+                match path.first() {
+                    None    => Some("(unnamed) (AF_UNIX)".to_string()),
+                    // Abstract sockets start with a NUL, and aren't terminated
+                    Some(0) => Some(format!("`@{}` (AF_UNIX, abstract)", String::from_utf8_lossy(&path[1..]).trim_end_matches('\0').escape_debug())),
+                    Some(_) => Some(format!("`{}` (AF_UNIX)", String::from_utf8_lossy(path.split(|&b| b == 0).next()?).escape_debug())),
                 }
+            },
+            AF_INET => {
+                let ip: [u8; 4] = data.get(4..8)?.try_into().ok()?;
+                Some(format!("`{}:{}` (AF_INET)", Ipv4Addr::from(ip), port(data)?))
+            },
+            AF_INET6 => {
+                let ip: [u8; 16] = data.get(8..24)?.try_into().ok()?;
+                Some(format!("`[{}]:{}` (AF_INET6)", Ipv6Addr::from(ip), port(data)?))
+            },
+            _ => None,
+        }
+    }
 
-                if let Some(param) = &s.r10 {
-                    out.push(format!("{} (r10) = {}", param.field_name, Self::syscall_param(pid, &param, r10)));
-                }
+    /// Show a syscall's buffer, using its length (up to
+    /// [`MAX_SYSCALL_BUFFER`] bytes): as a string if it's printable, or hex if
+    /// it's not.
+    fn syscall_buffer(memory: &MemoryCache, address: u64, length: u64, is_output: bool) -> String {
+        if address == 0 {
+            return "(nil)".to_string();
+        }
 
-                if let Some(param) = &s.r8 {
-                    out.push(format!("{} (r8) = {}", param.field_name, Self::syscall_param(pid, &param, r8)));
-                }
+        // The syscall hasn't run yet, so there's nothing in it
+        if is_output {
+            return format!("0x{:08x} (up to {} bytes, filled in by the syscall)", address, length);
+        }
 
-                if let Some(param) = &s.r9 {
-                    out.push(format!("{} (r9) = {}", param.field_name, Self::syscall_param(pid, &param, r9)));
-                }
+        if length == 0 {
+            return "`\"\"` (0 bytes)".to_string();
+        }
+
+        let wanted = std::cmp::min(length, MAX_SYSCALL_BUFFER as u64) as usize;
+        let (data, truncated) = match memory.read(address, wanted) {
+            Some(memory) => memory,
+            None => return format!("Invalid memory pointer: 0x{:08x}", address),
+        };
+
+        let printable = std::str::from_utf8(&data).ok()
+            .filter(|s| s.chars().all(|c| !c.is_control() || c == '\n' || c == '\r' || c == '\t'));
+        let shown = match printable {
+            Some(s) => format!("\"{}\"", s.escape_debug()),
+            None    => hex::encode(&data),
+        };
+
+        if truncated {
+            format!("`{}` (only {} of {} bytes readable)", shown, data.len(), length)
+        } else if data.len() as u64 != length {
+            format!("`{}` (first {} of {} bytes)", shown, data.len(), length)
+        } else {
+            format!("`{}` ({} bytes)", shown, length)
+        }
+    }
+
+    /// Describe a syscall and its arguments, showing `snippit_length` bytes
+    /// of any buffers they point to - `args` are the registers that
+    /// `abi` passes the arguments in.
+    pub fn syscall_info(memory: &MemoryCache, abi: SyscallAbi, rax: &AnalyzedValue, args: [&AnalyzedValue; 6], snippit_length: usize) -> Vec<String> {
+        match abi.find(rax.value) {
+            Some(s) => {
+                let mut out = vec![format!("Syscall: `{}`{}", s.name, abi.label())]; // The syscall number
+                out.extend(Self::syscall_arguments(memory, abi, s, args, snippit_length));
 
                 out
             },
-            None => vec![format!("Unknown syscall: `{}`", rax.value)],
+            None => vec![format!("Unknown syscall: `{}`{}", rax.value, abi.label())],
         }
     }
 
-    pub fn new(pid: Pid, value: u64, is_instruction_pointer: bool, snippit_length: usize, minimum_viable_string: usize) -> Self {
-        // Figure out the longest value we need
-        let bytes_to_get: usize = std::cmp::max(INITIAL_SNIPPIT_LENGTH, snippit_length);
+    /// Describe each of a syscall's arguments (eg, ``filename (rdi) = `/etc/passwd` ``)
+    pub fn syscall_arguments(memory: &MemoryCache, abi: SyscallAbi, syscall: &Syscall, args: [&AnalyzedValue; 6], snippit_length: usize) -> Vec<String> {
+        let values = args.map(|arg| arg.value);
 
-        let mut data = match Self::get_memory(pid, value, bytes_to_get) {
-            Some(data) => data,
-            None => {
-                // If we can't get memory, just return the value
-                return AnalyzedValue {
-                    value: value,
-                    memory: None,
-                    as_instruction: None,
-                    as_string: None,
-                    is_instruction_pointer: is_instruction_pointer,
-                    extra: None,
-                };
+        syscall.params().iter().zip(abi.argument_registers()).zip(args).filter_map(|((param, register), arg)| {
+            param.as_ref().map(|param| format!("{} ({}) = {}", param.field_name, register, Self::syscall_param(memory, param, arg, &values, snippit_length)))
+        }).collect()
+    }
+
+    /// Describe a syscall using just the register values (without reading
+    /// any memory) - `args` are the values of the registers that `abi`
+    /// passes the arguments in.
+    pub fn syscall_numbers(abi: SyscallAbi, rax: u64, args: [u64; 6]) -> Vec<String> {
+        match abi.find(rax) {
+            Some(s) => {
+                let mut out = vec![format!("Syscall: `{}`{}", s.name, abi.label())];
+                out.extend(Self::syscall_argument_numbers(abi, s, args));
+
+                out
+            },
+            None => vec![format!("Unknown syscall: `{}`{}", rax, abi.label())],
+        }
+    }
+
+    /// Show each of a syscall's arguments as a number (eg, ``fd (rdi) = `0x00000001` ``)
+    pub fn syscall_argument_numbers(abi: SyscallAbi, syscall: &Syscall, args: [u64; 6]) -> Vec<String> {
+        syscall.params().iter().zip(abi.argument_registers()).zip(args).filter_map(|((param, register), value)| {
+            param.as_ref().map(|param| format!("{} ({}) = `0x{:08x}`", param.field_name, register, value))
+        }).collect()
+    }
+
+    /// A value with no analysis at all (no memory, strings, or instructions).
+    pub fn from_value(value: u64, is_instruction_pointer: bool) -> Self {
+        Self {
+            value,
+            memory: None,
+            as_instruction: None,
+            as_string: None,
+            is_instruction_pointer,
+            flow_control: None,
+            instruction_bytes: None,
+            instruction_length: None,
+            mnemonic: None,
+            upcoming: None,
+            points_to: None,
+            region: None,
+            as_signed: None,
+            as_errno: None,
+            as_f64: None,
+            memory_truncated: false,
+            memory_entropy: None,
+            extra: None,
+            memory_ref: None,
+        }
+    }
+
+    /// Only decode the instruction at an address, without saving memory or
+    /// looking for strings (this is much cheaper than [`AnalyzedValue::new`]).
+    pub fn instruction_only(pid: Pid, value: u64) -> Self {
+        let mut out = Self::from_value(value, true);
+
+        match Self::get_memory(pid, value, MAX_INSTRUCTION_LENGTH) {
+            Some((data, _)) => {
+                if let Some(decoded) = Self::disassemble(&data, value) {
+                    out.as_instruction = Self::format_instruction(&decoded);
+                    out.set_instruction_details(&decoded, &data);
+                }
+            },
+            // It's about to crash, which is reported anyway
+            None => debug!("Couldn't read the instruction at 0x{:08x}", value),
+        }
+
+        out
+    }
+
+    /// Save the details of the instruction at the instruction pointer
+    fn set_instruction_details(&mut self, decoded: &Instruction, data: &[u8]) {
+        let length = std::cmp::min(decoded.len(), data.len());
+
+        self.flow_control       = Some(format!("{:?}", decoded.flow_control()));
+        self.instruction_bytes  = Some(hex::encode(&data[..length]));
+        self.instruction_length = Some(length);
+        self.mnemonic           = Some(format!("{:?}", decoded.mnemonic()));
+    }
+
+    /// Get the length of the instruction at an address, if it decodes.
+    pub fn instruction_length(pid: Pid, value: u64) -> Option<usize> {
+        let (data, _) = Self::get_memory(pid, value, MAX_INSTRUCTION_LENGTH)?;
+
+        Self::disassemble(&data, value).map(|decoded| decoded.len())
+    }
+
+    /// Get the (full, 64-bit) general purpose registers that the instruction
+    /// at an address reads or writes, including for memory operands (eg,
+    /// `["rax", "rsp"]` for `mov eax, [rsp+8]`).
+    pub(crate) fn used_registers(memory: &MemoryCache, address: u64) -> Option<Vec<String>> {
+        let (data, _) = memory.read(address, MAX_INSTRUCTION_LENGTH)?;
+
+        Self::instruction_registers(&data, address)
+    }
+
+    /// Get the (full, 64-bit) general purpose registers that an instruction's
+    /// bytes read or write (see [`Self::used_registers`]).
+    pub(crate) fn instruction_registers(data: &[u8], address: u64) -> Option<Vec<String>> {
+        let decoded = Self::disassemble(data, address)?;
+
+        let mut factory = InstructionInfoFactory::new();
+        let info = factory.info(&decoded);
+
+        Some(info.used_registers().iter()
+            .map(|used| used.register().full_register())
+            .filter(|register| register.is_gpr64())
+            .map(|register| format!("{:?}", register).to_lowercase())
+            .collect())
+    }
+
+    /// Decode a single instruction, if anything decodes at all.
+    pub(crate) fn disassemble(data: &[u8], address: u64) -> Option<Instruction> {
+        let mut decoder = Decoder::with_ip(64, data, address, DecoderOptions::NONE);
+
+        match decoder.can_decode() {
+            true  => Some(decoder.decode()),
+            false => None,
+        }
+    }
+
+    /// Decode up to `count` instructions, starting at the start of `data`,
+    /// and format them with their addresses. Stops at the first thing that
+    /// doesn't decode (including an instruction that runs off the end).
+    fn disassemble_upcoming(data: &[u8], address: u64, count: usize) -> Vec<String> {
+        let mut decoder = Decoder::with_ip(64, data, address, DecoderOptions::NONE);
+        let mut out: Vec<String> = vec![];
+
+        while out.len() < count && decoder.can_decode() {
+            let decoded = decoder.decode();
+            if decoded.code() == Code::INVALID {
+                break;
             }
+
+            match Self::format_instruction(&decoded) {
+                Some(instruction) => out.push(format!("0x{:08x} {}", decoded.ip(), instruction)),
+                None => break,
+            }
+        }
+
+        out
+    }
+
+    /// Format a decoded instruction, unless it's invalid.
+    pub(crate) fn format_instruction(decoded: &Instruction) -> Option<String> {
+        let mut output = String::new();
+        NasmFormatter::new().format(decoded, &mut output);
+
+        if output == "(bad)" {
+            None
+        } else {
+            Some(output)
+        }
+    }
+
+    /// Analyze a value, reading `analysis_window` bytes of memory to look for
+    /// strings and code (at least enough for the snippit and an instruction)
+    /// and saving `snippit_length` of them.
+    pub fn new(memory: &MemoryCache, value: u64, is_instruction_pointer: bool, analysis_window: usize, snippit_length: usize, minimum_viable_string: usize, max_string_length: usize) -> Self {
+        Self::new_with_upcoming(memory, value, is_instruction_pointer, analysis_window, snippit_length, minimum_viable_string, max_string_length, 0)
+    }
+
+    /// Like [`AnalyzedValue::new`], but for the instruction pointer, also
+    /// decode up to `upcoming` instructions after the current one (from the
+    /// memory we already read).
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_with_upcoming(memory: &MemoryCache, value: u64, is_instruction_pointer: bool, analysis_window: usize, snippit_length: usize, minimum_viable_string: usize, max_string_length: usize, upcoming: usize) -> Self {
+        // Figure out the longest value we need (we always need enough to
+        // decode an instruction)
+        let bytes_to_get: usize = analysis_window.max(snippit_length).max(MAX_INSTRUCTION_LENGTH);
+
+        // Don't touch memory the user told us to stay away from
+        if let Some(region) = memory.denied_region(value) {
+            debug!("Not reading 0x{:08x} (region `{}` isn't allowed)", value, region);
+            let mut out = Self::from_value(value, is_instruction_pointer);
+            out.set_interpretations();
+            out.extra = Some(vec![format!("Memory not read (region `{}` isn't allowed)", region)]);
+            return out;
+        }
+
+        let (mut data, truncated) = match memory.read(value, bytes_to_get) {
+            Some(memory) => memory,
+            // If we can't get memory, just return the value (and whatever
+            // else it could mean) - that's normal for a value that isn't a
+            // pointer (and the instruction pointer is about to crash)
+            None => {
+                match is_instruction_pointer {
+                    true  => debug!("Couldn't read the instruction at 0x{:08x}", value),
+                    false => trace!("0x{:08x} isn't readable memory", value),
+                }
+
+                let mut out = Self::from_value(value, is_instruction_pointer);
+                out.set_interpretations();
+                return out;
+            },
         };
 
         // Try and decode from assembly - decode with the full data length
-        let mut decoder = Decoder::with_ip(64, &data, value as u64, DecoderOptions::NONE);
-        let as_instruction = match decoder.can_decode() {
-            true => {
-                let mut output = String::new();
-                let decoded = decoder.decode();
-
-                if is_instruction_pointer {
-                    data.truncate(decoded.len());
+        let decoded = Self::disassemble(&data, value);
+        let as_instruction = decoded.as_ref().and_then(Self::format_instruction);
+
+        // Only save the instruction details if it's actually going to run
+        let mut out = Self::from_value(value, is_instruction_pointer);
+        out.set_interpretations();
+
+        // It only matters that the read was cut short if we got less than the
+        // user asked for
+        out.memory_truncated = truncated && data.len() < snippit_length;
+
+        if is_instruction_pointer {
+            if let Some(decoded) = &decoded {
+                if upcoming > 0 && decoded.code() != Code::INVALID && decoded.len() < data.len() {
+                    let upcoming = Self::disassemble_upcoming(&data[decoded.len()..], decoded.next_ip(), upcoming);
+                    if !upcoming.is_empty() {
+                        out.upcoming = Some(upcoming);
+                    }
                 }
-                NasmFormatter::new().format(&decoded, &mut output);
 
-                if output == "(bad)" {
-                    None
-                } else {
-                    Some(output)
-                }
+                data.truncate(decoded.len());
+                out.set_instruction_details(decoded, &data);
             }
-            false => None,
-        };
+        }
 
         // Try and interpret as a string - this is also done with the full-length value
-        let string_data: Vec<u8> = data.clone().into_iter().take_while(|d| *d != 0).collect();
-        let as_string = match std::str::from_utf8(&string_data) {
-            Ok(s)  => {
-                if s.len() > minimum_viable_string {
-                    Some(s.to_string())
-                } else {
-                    None
-                }
-            },
-            Err(_) => None,
-        };
+        let as_string = Self::extract_string(&data, minimum_viable_string, max_string_length);
 
         // Truncate it to the actual size they asked for (after checking for instructions)
         data.truncate(snippit_length);
 
-        Self {
-            value: value,
-            memory: Some(data),
-            as_instruction: as_instruction,
-            as_string: as_string,
-            is_instruction_pointer: is_instruction_pointer,
-
-            // We need all the registers to figure out syscall details, so mark
-            // this as None for now
-            extra: None,
+        // We need all the registers to figure out syscall details, so extra
+        // stays None for now
+        out.memory = Some(data);
+        out.as_instruction = as_instruction;
+        out.as_string = as_string;
+
+        out
+    }
+
+    /// Follow a chain of pointers from this value, up to `depth` levels,
+    /// analyzing each target and saving it as `points_to`. This stops at a
+    /// NULL or unreadable pointer, or one we've already seen (a cycle).
+    pub fn follow_pointers(&mut self, memory: &MemoryCache, depth: usize, analysis_window: usize, snippit_length: usize, minimum_viable_string: usize, max_string_length: usize) {
+        let mut visited: HashSet<u64> = HashSet::new();
+        visited.insert(self.value);
+
+        let mut current: &mut AnalyzedValue = self;
+        for _ in 0..std::cmp::min(depth, MAX_DEREF_READS) {
+            let target = match memory.read_u64(current.value) {
+                Some(target) => target,
+                None => break,
+            };
+
+            if target == 0 || !visited.insert(target) || memory.read_u64(target).is_none() {
+                break;
+            }
+
+            let analyzed = Self::new(memory, target, false, analysis_window, snippit_length, minimum_viable_string, max_string_length);
+            current = &mut **current.points_to.insert(Box::new(analyzed));
+        }
+    }
+
+    /// Add a note to `extra` if the value, or the start of its memory, is a
+    /// well-known constant.
+    pub fn annotate_constants(&mut self, constants: &Constants) {
+        let mut annotations: Vec<String> = vec![];
+
+        if let Some(label) = constants.get(self.value) {
+            annotations.push(format!("Constant: {} (0x{:x})", label, self.value));
+        }
+
+        if let Some(memory) = &self.memory {
+            // Check both the first dword and the first qword
+            for length in [4, 8] {
+                if let Some(bytes) = memory.get(..length) {
+                    let mut buffer = [0u8; 8];
+                    buffer[..length].copy_from_slice(bytes);
+                    let value = u64::from_le_bytes(buffer);
+
+                    if let Some(label) = constants.get(value) {
+                        annotations.push(format!("Memory starts with constant: {} (0x{:x})", label, value));
+                    }
+                }
+            }
+        }
+
+        if !annotations.is_empty() {
+            self.extra.get_or_insert_with(Vec::new).extend(annotations);
+        }
+    }
+
+    /// Roughly how much memory this value (and everything it points to) is
+    /// using.
+    pub fn approximate_size(&self) -> usize {
+        let string = |s: &Option<String>| s.as_ref().map_or(0, String::len);
+        let strings = |s: &Option<Vec<String>>| s.as_ref().map_or(0, |s| s.iter().map(String::len).sum());
+
+        std::mem::size_of::<Self>()
+            + self.memory.as_ref().map_or(0, Vec::len)
+            + string(&self.as_instruction)
+            + string(&self.as_string)
+            + string(&self.flow_control)
+            + string(&self.instruction_bytes)
+            + string(&self.mnemonic)
+            + strings(&self.upcoming)
+            + string(&self.region)
+            + string(&self.as_errno)
+            + strings(&self.extra)
+            + string(&self.memory_ref)
+            + self.points_to.as_ref().map_or(0, |points_to| points_to.approximate_size())
+    }
+
+    /// Run a function on this value and on everything it points to.
+    pub(crate) fn for_each(&self, f: &mut impl FnMut(&AnalyzedValue)) {
+        f(self);
+
+        if let Some(points_to) = &self.points_to {
+            points_to.for_each(f);
+        }
+    }
+
+    /// Like [`AnalyzedValue::for_each`], but can change the values.
+    pub(crate) fn for_each_mut(&mut self, f: &mut impl FnMut(&mut AnalyzedValue)) {
+        f(self);
+
+        if let Some(points_to) = &mut self.points_to {
+            points_to.for_each_mut(f);
         }
     }
 
-    fn get_memory(pid: Pid, addr: u64, snippit_length: usize) -> Option<Vec<u8>> {
+    /// Work out the entropy of the memory (and the memory of anything it
+    /// points to).
+    pub fn set_memory_entropy(&mut self) {
+        self.for_each_mut(&mut |value| {
+            value.memory_entropy = value.memory.as_deref().filter(|memory| !memory.is_empty()).map(shannon_entropy);
+        });
+    }
+
+    /// Fill in the signed, errno, and floating point interpretations of the
+    /// value, if they're interesting.
+    fn set_interpretations(&mut self) {
+        let signed = self.value as i64;
+        if (MIN_INTERESTING_SIGNED..0).contains(&signed) {
+            self.as_signed = Some(signed);
+
+            if signed >= MIN_ERRNO_RETURN {
+                let errno = Errno::from_i32(-signed as i32);
+                if errno != Errno::UnknownErrno {
+                    self.as_errno = Some(format!("{:?} ({})", errno, errno.desc()));
+                }
+            }
+        }
+
+        let float = f64::from_bits(self.value);
+        if float.is_normal() && (MIN_INTERESTING_F64..MAX_INTERESTING_F64).contains(&float.abs()) {
+            self.as_f64 = Some(float);
+        }
+    }
+
+    /// Interpret memory as a NUL-terminated string (bounded by the memory we
+    /// have), if it's valid UTF-8, longer than `minimum_viable_string`
+    /// characters, and mostly printable. Anything non-printable is escaped,
+    /// and it's cut off after `max_string_length` characters.
+    fn extract_string(data: &[u8], minimum_viable_string: usize, max_string_length: usize) -> Option<String> {
+        let string_data: Vec<u8> = data.iter().copied().take_while(|d| *d != 0).collect();
+        let s = std::str::from_utf8(&string_data).ok()?;
+
+        let length = s.chars().count();
+        if length <= minimum_viable_string {
+            return None;
+        }
+
+        let is_printable = |c: &char| !c.is_control() || *c == '\n' || *c == '\r' || *c == '\t';
+        let printable = s.chars().filter(is_printable).count();
+        if (printable as f64) < (length as f64) * MIN_PRINTABLE_FRACTION {
+            return None;
+        }
+
+        Some(s.chars().take(max_string_length).map(|c| {
+            match is_printable(&c) {
+                true  => c.to_string(),
+                false => c.escape_default().to_string(),
+            }
+        }).collect())
+    }
+
+    /// Read up to `snippit_length` bytes. If we hit unreadable memory part
+    /// way through, we return what we got, and the flag is set to say it's
+    /// been cut short (nothing readable at all returns None).
+    pub(crate) fn get_memory(pid: Pid, addr: u64, snippit_length: usize) -> Option<(Vec<u8>, bool)> {
+        // Reading it all in one syscall is much faster than peeking a word at
+        // a time, but it can fail where ptrace works (eg, if Yama blocks it,
+        // or the memory isn't readable by the process itself)
+        match Self::get_memory_vm_readv(pid, addr, snippit_length) {
+            Some(memory) => Some(memory),
+            None => {
+                trace!("process_vm_readv() couldn't read 0x{:08x}, trying ptrace", addr);
+                Self::get_memory_peek(pid, addr, snippit_length)
+            },
+        }
+    }
+
+    /// Read memory with a single `process_vm_readv()` call
+    pub(crate) fn get_memory_vm_readv(pid: Pid, addr: u64, snippit_length: usize) -> Option<(Vec<u8>, bool)> {
+        let mut data = vec![0u8; snippit_length];
+
+        let length = process_vm_readv(
+            pid,
+            &[IoVec::from_mut_slice(&mut data)],
+            &[RemoteIoVec { base: addr as usize, len: snippit_length }],
+        ).ok()?;
+
+        if length == 0 {
+            return None;
+        }
+        data.truncate(length);
+
+        Some((data, length < snippit_length))
+    }
+
+    /// Read memory one word at a time with `ptrace()`
+    fn get_memory_peek(pid: Pid, addr: u64, snippit_length: usize) -> Option<(Vec<u8>, bool)> {
+        // Read aligned words, so we get right up to the end of a page
+        let start = addr & !7;
+        let skip = (addr - start) as usize;
+
         let mut data: Vec<u8> = vec![];
+        let mut truncated = false;
 
-        for i in 0..((snippit_length + 7) / 8) {
-            let this_chunk = match read(pid, (addr as usize + (i * 8)) as AddressType) {
+        while data.len() < skip + snippit_length {
+            let this_chunk = match read(pid, (start as usize + data.len()) as AddressType) {
                 Ok(chunk) => chunk,
-                // If the memory isn't readable, just return None
-                Err(_e) => return None,
+                // If the memory isn't readable, keep what we have so far
+                Err(_e) => {
+                    truncated = true;
+                    break;
+                },
             };
 
             // I don't think this can actually fail
             data.write_i64::<LittleEndian>(this_chunk).unwrap();
         }
 
-        Some(data)
+        if data.len() <= skip {
+            return None;
+        }
+
+        data.drain(..skip);
+        data.truncate(snippit_length);
+
+        Some((data, truncated))
     }
 
-    fn get_memory_as_u64(pid: Pid, addr: u64) -> Option<u64> {
+    pub(crate) fn get_memory_as_u64(pid: Pid, addr: u64) -> Option<u64> {
         match read(pid, addr as AddressType) {
             Ok(d) => Some(d as u64),
             Err(_e) => None,
         }
     }
 
+    /// Read a NUL-terminated string, up to `max_length` bytes (invalid UTF-8
+    /// is replaced).
+    pub(crate) fn get_memory_as_c_string(pid: Pid, addr: u64, max_length: usize) -> Option<String> {
+        let mut data: Vec<u8> = vec![];
+
+        while data.len() < max_length {
+            let chunk = Self::get_memory_as_u64(pid, addr + data.len() as u64)?;
+
+            for b in chunk.to_le_bytes() {
+                if b == 0 || data.len() >= max_length {
+                    return Some(String::from_utf8_lossy(&data).to_string());
+                }
+                data.push(b);
+            }
+        }
+
+        Some(String::from_utf8_lossy(&data).to_string())
+    }
+
 }
 
 impl fmt::Display for AnalyzedValue {
@@ -260,3 +838,154 @@ impl fmt::Display for AnalyzedValue {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use nix::libc;
+    use nix::sys::mman::{mmap, mprotect, munmap, MapFlags, ProtFlags};
+    use nix::sys::ptrace;
+    use nix::sys::signal::{kill, raise, Signal};
+    use nix::sys::wait::{waitpid, WaitStatus};
+    use nix::unistd::{fork, sysconf, ForkResult, SysconfVar};
+
+    /// A page of known bytes with nothing mapped after it, in a stopped child
+    /// that we're tracing (it's killed when this is dropped)
+    struct TracedPage {
+        pid: Pid,
+        page: u64,
+        page_size: usize,
+    }
+
+    impl TracedPage {
+        /// `protection` is what the page is left as once it's filled in
+        fn new(protection: ProtFlags) -> Self {
+            let page_size = sysconf(SysconfVar::PAGE_SIZE).unwrap().unwrap() as usize;
+
+            // Map two pages, fill in the first, then unmap the second so the
+            // first one runs right up to a hole; the child gets the same
+            // layout when it's forked
+            let page = unsafe {
+                mmap(std::ptr::null_mut(), page_size * 2, ProtFlags::PROT_READ | ProtFlags::PROT_WRITE, MapFlags::MAP_PRIVATE | MapFlags::MAP_ANONYMOUS, -1, 0).unwrap()
+            };
+            unsafe {
+                let bytes = std::slice::from_raw_parts_mut(page as *mut u8, page_size);
+                bytes.iter_mut().enumerate().for_each(|(i, b)| *b = i as u8);
+                munmap(page.add(page_size), page_size).unwrap();
+                mprotect(page, page_size, protection).unwrap();
+            }
+
+            let pid = match unsafe { fork() }.unwrap() {
+                ForkResult::Child => {
+                    ptrace::traceme().unwrap();
+                    raise(Signal::SIGSTOP).unwrap();
+                    unsafe { libc::_exit(0) };
+                },
+                ForkResult::Parent { child } => child,
+            };
+
+            match waitpid(pid, None).unwrap() {
+                WaitStatus::Stopped(_, Signal::SIGSTOP) => (),
+                status => panic!("Child didn't stop: {:?}", status),
+            }
+
+            Self { pid, page: page as u64, page_size }
+        }
+
+        /// What's at `offset` into the page
+        fn expected(&self, offset: usize, length: usize) -> Vec<u8> {
+            (offset..offset + length).map(|i| i as u8).collect()
+        }
+    }
+
+    impl Drop for TracedPage {
+        fn drop(&mut self) {
+            kill(self.pid, Signal::SIGKILL).ok();
+            waitpid(self.pid, None).ok();
+            unsafe { munmap(self.page as *mut libc::c_void, self.page_size).ok() };
+        }
+    }
+
+    #[test]
+    fn get_memory_reads_whole_buffer() {
+        let traced = TracedPage::new(ProtFlags::PROT_READ);
+
+        let expected = Some((traced.expected(100, 64), false));
+        assert_eq!(expected, AnalyzedValue::get_memory(traced.pid, traced.page + 100, 64));
+        assert_eq!(expected, AnalyzedValue::get_memory_vm_readv(traced.pid, traced.page + 100, 64));
+        assert_eq!(expected, AnalyzedValue::get_memory_peek(traced.pid, traced.page + 100, 64));
+    }
+
+    #[test]
+    fn get_memory_stops_at_unmapped_page() {
+        let traced = TracedPage::new(ProtFlags::PROT_READ);
+
+        // 13 bytes before the hole (and an unaligned address, for peek)
+        let offset = traced.page_size - 13;
+        let expected = Some((traced.expected(offset, 13), true));
+        assert_eq!(expected, AnalyzedValue::get_memory(traced.pid, traced.page + offset as u64, 64));
+        assert_eq!(expected, AnalyzedValue::get_memory_vm_readv(traced.pid, traced.page + offset as u64, 64));
+
+        // The fallback has to come up with exactly the same thing
+        assert_eq!(expected, AnalyzedValue::get_memory_peek(traced.pid, traced.page + offset as u64, 64));
+    }
+
+    #[test]
+    fn get_memory_unmapped() {
+        let traced = TracedPage::new(ProtFlags::PROT_READ);
+        let hole = traced.page + traced.page_size as u64;
+
+        assert_eq!(None, AnalyzedValue::get_memory(traced.pid, hole, 64));
+        assert_eq!(None, AnalyzedValue::get_memory_vm_readv(traced.pid, hole, 64));
+        assert_eq!(None, AnalyzedValue::get_memory_peek(traced.pid, hole + 3, 64));
+    }
+
+    #[test]
+    fn get_memory_falls_back_to_peek() {
+        // process_vm_readv() can't read memory the process itself can't, but
+        // ptrace can
+        let traced = TracedPage::new(ProtFlags::PROT_NONE);
+
+        assert_eq!(None, AnalyzedValue::get_memory_vm_readv(traced.pid, traced.page + 100, 64));
+        assert_eq!(Some((traced.expected(100, 64), false)), AnalyzedValue::get_memory(traced.pid, traced.page + 100, 64));
+
+        let offset = traced.page_size - 13;
+        assert_eq!(Some((traced.expected(offset, 13), true)), AnalyzedValue::get_memory(traced.pid, traced.page + offset as u64, 64));
+    }
+
+    #[test]
+    fn memory_cache_reads_whole_pages() {
+        let traced = TracedPage::new(ProtFlags::PROT_READ);
+        let memory = MemoryCache::new(traced.pid);
+
+        // One process_vm_readv() gets the whole page...
+        assert_eq!(Some((traced.expected(100, 64), false)), memory.read(traced.page + 100, 64));
+        assert_eq!(Some((traced.expected(2000, 64), false)), memory.read(traced.page + 2000, 64));
+        assert_eq!(1, memory.reads());
+
+        // ...and the hole after it costs a failed read and a failed peek
+        let offset = traced.page_size - 13;
+        assert_eq!(Some((traced.expected(offset, 13), true)), memory.read(traced.page + offset as u64, 64));
+        assert_eq!(3, memory.reads());
+    }
+
+    #[test]
+    fn memory_cache_peeks_only_what_it_needs() {
+        let traced = TracedPage::new(ProtFlags::PROT_NONE);
+        let memory = MemoryCache::new(traced.pid);
+
+        // process_vm_readv() fails, then 100..164 is nine aligned words
+        assert_eq!(Some((traced.expected(100, 64), false)), memory.read(traced.page + 100, 64));
+        assert_eq!(10, memory.reads());
+
+        // Reading any of it again is free
+        assert_eq!(Some((traced.expected(120, 16), false)), memory.read(traced.page + 120, 16));
+        assert_eq!(10, memory.reads());
+
+        // Up to the hole, it's two more words and a failed peek
+        let offset = traced.page_size - 13;
+        assert_eq!(Some((traced.expected(offset, 13), true)), memory.read(traced.page + offset as u64, 64));
+        assert_eq!(14, memory.reads());
+    }
+}
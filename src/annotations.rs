@@ -0,0 +1,138 @@
+//! Works out what to mark in a static analysis tool from a finished trace:
+//! which instructions ran, and comments for each syscall and the crash.
+//!
+//! Addresses are offsets from where the code was loaded (see
+//! [`MandrakeOutput::image_base`]), so a script can add them to wherever the
+//! tool loaded it - that way it still lines up when the code was loaded
+//! somewhere else (ASLR). Anything outside the code (like a syscall in libc)
+//! can't be expressed that way, so it's kept separately, by address.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::mandrake::REGISTERS;
+use crate::mandrake_output::{HistoryEntry, MandrakeOutput};
+
+/// What a trace did to the code, ready to be written out for a tool.
+#[derive(Debug, Clone, Default)]
+pub struct TraceAnnotations {
+    /// Where the code was loaded in the trace
+    pub image_base: u64,
+
+    /// Each instruction that ran (by offset), and how many times it was logged
+    pub executed: BTreeMap<u64, usize>,
+
+    /// How long each instruction that ran was, by offset (if it was decoded)
+    pub lengths: BTreeMap<u64, usize>,
+
+    /// Comments for syscalls and the crash, by offset
+    pub comments: Vec<(u64, String)>,
+
+    /// Where each syscall in the code was made, by offset
+    pub syscalls: BTreeSet<u64>,
+
+    /// The first instruction that was logged
+    pub start: Option<u64>,
+
+    /// The last instruction in the code before it crashed (the crash itself
+    /// can be somewhere else, like after a bad `ret`)
+    pub crash: Option<u64>,
+
+    /// Comments for things that happened outside the code, by address
+    pub outside: Vec<(u64, String)>,
+}
+
+/// Describe the registers at a history entry, like `rax=0x1 rbx=0x0 ...`
+fn describe_registers(entry: &HistoryEntry) -> String {
+    REGISTERS.iter().filter_map(|register| {
+        entry.registers.get(*register).map(|value| format!("{}=0x{:x}", register, value.value))
+    }).collect::<Vec<String>>().join(" ")
+}
+
+impl TraceAnnotations {
+    pub fn new(output: &MandrakeOutput) -> Self {
+        // We need every register at every step
+        let mut output = output.clone();
+        output.densify();
+
+        let mut out = Self {
+            image_base: output.image_base.unwrap_or(0),
+            ..Default::default()
+        };
+
+        for entry in &output.history {
+            let rip = match entry.registers.get("rip") {
+                Some(rip) => rip,
+                None => continue,
+            };
+
+            if let Some(offset) = Self::offset(&output, Some(entry), rip.value) {
+                *out.executed.entry(offset).or_insert(0) += 1;
+
+                if let Some(length) = rip.instruction_length {
+                    out.lengths.insert(offset, length);
+                }
+            }
+        }
+
+        out.start = output.starting_address.and_then(|address| {
+            Self::offset(&output, output.history.first(), address)
+        });
+
+        for syscall in &output.syscalls {
+            let entry = output.history.iter().find(|entry| entry.index == syscall.index);
+
+            let mut comment = vec![format!("Mandrake: {} (instruction {})", syscall.name, syscall.index)];
+            comment.extend(syscall.arguments.iter().cloned());
+            if let Some(returned) = &syscall.returned {
+                comment.push(format!("Returned: {}", returned));
+            }
+            if let Some(entry) = entry {
+                comment.push(describe_registers(entry));
+            }
+
+            match Self::offset(&output, entry, syscall.address) {
+                Some(offset) => {
+                    out.comments.push((offset, comment.join("\n")));
+                    out.syscalls.insert(offset);
+                },
+                None => out.outside.push((syscall.address, comment.join("\n"))),
+            }
+        }
+
+        if let Some(reason) = output.exit_reason.as_ref().filter(|reason| reason.is_crash()) {
+            let last = output.history.iter().rev().find_map(|entry| {
+                entry.registers.get("rip")
+                    .and_then(|rip| Self::offset(&output, Some(entry), rip.value))
+                    .map(|offset| (offset, entry))
+            });
+
+            if let Some((offset, entry)) = last {
+                out.comments.push((offset, format!("Mandrake: {}\n{}", reason, describe_registers(entry))));
+                out.crash = Some(offset);
+            }
+        }
+
+        out
+    }
+
+    /// Where an address is, relative to the start of the analyzed code
+    /// (`None` if it's outside of it, like in a library).
+    fn offset(output: &MandrakeOutput, entry: Option<&HistoryEntry>, address: u64) -> Option<u64> {
+        let base = output.image_base?;
+
+        // Raw code only has the bytes the harness loaded, and for an ELF, the
+        // region says whether it's in the executable (it's only known for
+        // fully analyzed instructions)
+        let in_image = match output.code_length {
+            0 => entry.and_then(|entry| entry.registers.get("rip"))
+                .and_then(|rip| rip.region.as_deref())
+                .is_some_and(|region| region.starts_with("self:")),
+            _ => output.is_in_code(address),
+        };
+
+        match in_image && address >= base {
+            true  => Some(address - base),
+            false => None,
+        }
+    }
+}
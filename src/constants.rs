@@ -0,0 +1,115 @@
+//! Well-known constants, which can identify what code is doing.
+//!
+//! Seeing `0x67452301` in a register is a pretty good hint that something is
+//! calculating an MD5 or SHA-1 hash. We ship a small table of those, and the
+//! user can add their own from a CSV file of `value,label` lines (the value
+//! can be decimal or hex with a `0x` prefix).
+
+use std::collections::HashMap;
+
+use simple_error::{bail, SimpleError, SimpleResult};
+
+const BUILTIN_CONSTANTS: &[(u64, &str)] = &[
+    // MD5 / SHA-1
+    (0x67452301, "MD5 / SHA-1 initial hash value (A)"),
+    (0xefcdab89, "MD5 / SHA-1 initial hash value (B)"),
+    (0x98badcfe, "MD5 / SHA-1 initial hash value (C)"),
+    (0x10325476, "MD5 / SHA-1 initial hash value (D)"),
+    (0xc3d2e1f0, "SHA-1 initial hash value (E)"),
+    (0xd76aa478, "MD5 round constant (T[1])"),
+
+    // SHA-2
+    (0x6a09e667, "SHA-256 initial hash value (H0)"),
+    (0xbb67ae85, "SHA-256 initial hash value (H1)"),
+    (0x3c6ef372, "SHA-256 initial hash value (H2)"),
+    (0xa54ff53a, "SHA-256 initial hash value (H3)"),
+    (0x510e527f, "SHA-256 initial hash value (H4)"),
+    (0x9b05688c, "SHA-256 initial hash value (H5)"),
+    (0x1f83d9ab, "SHA-256 initial hash value (H6)"),
+    (0x5be0cd19, "SHA-256 initial hash value (H7)"),
+    (0x428a2f98, "SHA-256 round constant (K[0])"),
+    (0x6a09e667f3bcc908, "SHA-512 initial hash value (H0)"),
+
+    // CRC
+    (0xedb88320, "CRC-32 polynomial (reversed)"),
+    (0x04c11db7, "CRC-32 polynomial"),
+    (0x82f63b78, "CRC-32C polynomial (reversed)"),
+
+    // Ciphers
+    (0x9e3779b9, "TEA / XTEA delta (golden ratio)"),
+    (0x61707865, "Salsa20 / ChaCha constant (\"expa\")"),
+    (0x3320646e, "Salsa20 / ChaCha constant (\"nd 3\")"),
+    (0x79622d32, "Salsa20 / ChaCha constant (\"2-by\")"),
+    (0x6b206574, "Salsa20 / ChaCha constant (\"te k\")"),
+
+    // Non-cryptographic hashes and random number generators
+    (0x811c9dc5, "FNV-1 32-bit offset basis"),
+    (0x01000193, "FNV-1 32-bit prime"),
+    (0xcbf29ce484222325, "FNV-1 64-bit offset basis"),
+    (0x00000100000001b3, "FNV-1 64-bit prime"),
+    (0x41c64e6d, "glibc rand() LCG multiplier"),
+    (0x5851f42d4c957f2d, "PCG / Knuth MMIX LCG multiplier"),
+
+    // File formats
+    (0x7f454c46, "ELF magic (\"\\x7fELF\", big-endian)"),
+    (0x464c457f, "ELF magic (\"\\x7fELF\")"),
+    (0xcafebabe, "Java class / Mach-O fat binary magic"),
+    (0xfeedface, "Mach-O 32-bit magic"),
+    (0xfeedfacf, "Mach-O 64-bit magic"),
+
+    // Markers
+    (0xdeadbeef, "Debug marker (0xdeadbeef)"),
+];
+
+/// A table of constants to look for, and what they mean.
+#[derive(Debug, Clone)]
+pub struct Constants {
+    constants: HashMap<u64, String>,
+}
+
+impl Default for Constants {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+impl Constants {
+    /// The constants we know about out of the box
+    pub fn builtin() -> Self {
+        Self {
+            constants: BUILTIN_CONSTANTS.iter().map(|(value, label)| (*value, label.to_string())).collect(),
+        }
+    }
+
+    /// Add (or replace) constants from a CSV file of `value,label` lines.
+    pub fn with_file(mut self, path: &str) -> SimpleResult<Self> {
+        let mut rdr = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .comment(Some(b'#'))
+            .from_path(path)
+            .map_err(|e| SimpleError::new(format!("Couldn't read constants file {}: {}", path, e)))?;
+
+        for result in rdr.records() {
+            let record = result.map_err(|e| SimpleError::new(format!("Couldn't read constants file {}: {}", path, e)))?;
+
+            let (value, label) = match (record.get(0), record.get(1)) {
+                (Some(value), Some(label)) => (value.trim(), label.trim()),
+                _ => bail!("Constants must look like VALUE,LABEL: {:?}", record),
+            };
+
+            let value = match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+                Some(hex) => u64::from_str_radix(hex, 16),
+                None      => value.parse(),
+            }.map_err(|e| SimpleError::new(format!("Invalid constant {}: {}", value, e)))?;
+
+            self.constants.insert(value, label.to_string());
+        }
+
+        Ok(self)
+    }
+
+    /// Look up a value
+    pub fn get(&self, value: u64) -> Option<&str> {
+        self.constants.get(&value).map(|label| label.as_str())
+    }
+}
@@ -0,0 +1,150 @@
+//! A cheap record of every instruction that ran, including the ones that
+//! aren't visible (and so never make it into the history).
+//!
+//! Instructions that run one after another (each starting where the last one
+//! ended) are saved as a single run, and a run that repeats right away (like
+//! a loop) just has its count bumped, so it's usually far smaller than one
+//! address per step.
+//!
+//! For a known stretch of code (like raw code in the harness), it can also
+//! mark exactly which bytes were part of an instruction that ran (see
+//! [`ExecutedBytes`]).
+
+use serde::{Serialize, Deserialize};
+
+fn default_repeats() -> usize {
+    1
+}
+
+/// A stretch of instructions that ran one after another.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct CoverageRun {
+    /// The first instruction
+    pub start: u64,
+
+    /// The last instruction (not the end of it)
+    pub end: u64,
+
+    /// How many instructions ran from `start` to `end`
+    pub instructions: usize,
+
+    /// How many times in a row the run ran
+    #[serde(default = "default_repeats")]
+    pub repeats: usize,
+}
+
+/// Builds the coverage one instruction at a time.
+#[derive(Debug, Clone, Default)]
+pub struct CoverageRecorder {
+    runs: Vec<CoverageRun>,
+
+    /// The run that's still growing
+    current: Option<CoverageRun>,
+
+    /// Where the last instruction ended (`None` if we don't know its length)
+    next: Option<u64>,
+}
+
+impl CoverageRecorder {
+    /// Add an instruction that ran; `length` is its length, if it decoded.
+    pub fn record(&mut self, address: u64, length: Option<usize>) {
+        match &mut self.current {
+            Some(current) if self.next == Some(address) => {
+                current.end = address;
+                current.instructions += 1;
+            },
+            _ => {
+                self.finish_run();
+                self.current = Some(CoverageRun { start: address, end: address, instructions: 1, repeats: 1 });
+            },
+        }
+
+        self.next = length.map(|length| address + length as u64);
+    }
+
+    /// Save the run that's growing, or count it again if it's the same as
+    /// the last one
+    fn finish_run(&mut self) {
+        if let Some(run) = self.current.take() {
+            match self.runs.last_mut() {
+                Some(last) if (last.start, last.end, last.instructions) == (run.start, run.end, run.instructions) => last.repeats += 1,
+                _ => self.runs.push(run),
+            }
+        }
+    }
+
+    /// Get every run, in the order they ran.
+    pub fn finish(mut self) -> Vec<CoverageRun> {
+        self.finish_run();
+        self.runs
+    }
+}
+
+/// Which bytes of some code were part of an instruction that ran, marked as
+/// each instruction runs (so it's exactly what the CPU ran, even if the code
+/// changed itself).
+#[derive(Debug, Clone)]
+pub struct ExecutedBytes {
+    base: u64,
+    executed: Vec<bool>,
+}
+
+impl ExecutedBytes {
+    /// Nothing has run yet in the `length` bytes of code at `base`.
+    pub fn new(base: u64, length: usize) -> Self {
+        Self {
+            base,
+            executed: vec![false; length],
+        }
+    }
+
+    /// Mark an instruction that ran; `length` is its length, if it decoded
+    /// (otherwise only its first byte is marked). Anything outside the code
+    /// is ignored.
+    pub fn record(&mut self, address: u64, length: Option<usize>) {
+        let end = address.saturating_add(length.unwrap_or(1).max(1) as u64);
+        let code_end = self.base + self.executed.len() as u64;
+        if end <= self.base || address >= code_end {
+            return;
+        }
+
+        let start = (address.max(self.base) - self.base) as usize;
+        let end = (end.min(code_end) - self.base) as usize;
+        self.executed[start..end].iter_mut().for_each(|byte| *byte = true);
+    }
+
+    /// Whether the byte at this offset (from the start of the code) ran
+    pub fn contains(&self, offset: usize) -> bool {
+        self.executed.get(offset).copied().unwrap_or(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn executed_bytes_marks_whole_instructions() {
+        let mut executed = ExecutedBytes::new(0x1000, 16);
+        executed.record(0x1000, Some(5));
+        executed.record(0x100a, None);
+
+        let marked: Vec<usize> = (0..16).filter(|&offset| executed.contains(offset)).collect();
+        assert_eq!(marked, vec![0, 1, 2, 3, 4, 10]);
+    }
+
+    #[test]
+    fn executed_bytes_ignores_what_is_outside() {
+        let mut executed = ExecutedBytes::new(0x1000, 16);
+
+        // Instructions that hang over either end only count for the code
+        executed.record(0xffe, Some(4));
+        executed.record(0x100e, Some(4));
+        executed.record(0x2000, Some(4));
+        executed.record(0x0, None);
+
+        let marked: Vec<usize> = (0..16).filter(|&offset| executed.contains(offset)).collect();
+        assert_eq!(marked, vec![0, 1, 14, 15]);
+        assert!(!executed.contains(16));
+    }
+}
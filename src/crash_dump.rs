@@ -0,0 +1,144 @@
+//! Saves a crashed process's memory, for post-mortem analysis.
+//!
+//! When the process dies on a fatal signal, it's still stopped (and
+//! readable) for a moment before it's killed. We use that moment to copy out
+//! every writable or anonymous mapping - the stack, the heap, `.data`, and
+//! anything it `mmap`ed - since that's where the state that led to the crash
+//! lives. Read-only file mappings are skipped; they're the same as the file.
+//!
+//! Each region is written to `<start>-<end>.bin` (in hex) in the dump
+//! directory, and `manifest.json` says what each file is.
+
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use nix::sys::signal::Signal;
+use nix::unistd::Pid;
+use serde::{Deserialize, Serialize};
+use simple_error::{SimpleError, SimpleResult};
+
+use crate::analyzed_value::AnalyzedValue;
+use crate::memory_map::{MemoryMap, Region};
+
+/// How much memory to read at a time
+const CHUNK_SIZE: usize = 1024 * 1024;
+
+/// The most we'll save of any one region (some programs reserve huge
+/// anonymous mappings that they never touch)
+const MAX_REGION_SIZE: u64 = 256 * 1024 * 1024;
+
+/// The name of the file that describes a dump
+pub const MANIFEST_NAME: &str = "manifest.json";
+
+/// One saved region of memory
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DumpedRegion {
+    pub start: u64,
+    pub end: u64,
+
+    /// The permissions, such as `rw-p`
+    pub permissions: String,
+
+    /// The file or special region (eg, `[stack]`), if there is one
+    pub path: Option<String>,
+
+    /// What the memory map calls this region (eg, `stack` or `self:.data`)
+    pub region: String,
+
+    /// The file the bytes are in (relative to the manifest), if any could be
+    /// read
+    pub file: Option<String>,
+
+    /// How many bytes were saved, starting at `start` (this is less than the
+    /// region's size if part of it couldn't be read, or it was too big)
+    pub bytes_saved: u64,
+}
+
+/// Describes everything in a crash dump
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CrashDumpManifest {
+    pub pid: i32,
+
+    /// The signal that crashed the process (eg, `SIGSEGV`)
+    pub signal: String,
+
+    /// Where the process crashed
+    pub rip: u64,
+
+    pub regions: Vec<DumpedRegion>,
+}
+
+/// Save the writable and anonymous memory of a crashed (but still stopped)
+/// process to a directory, and returns the path to the manifest.
+pub fn write_crash_dump(pid: Pid, dir: &Path, signal: Signal, rip: u64) -> SimpleResult<PathBuf> {
+    let map = MemoryMap::read(pid)
+        .ok_or_else(|| SimpleError::new("Couldn't read the memory map"))?;
+
+    fs::create_dir_all(dir)
+        .map_err(|e| SimpleError::new(format!("Couldn't create {}: {}", dir.display(), e)))?;
+
+    let regions = map.regions.iter()
+        .filter(|r| r.is_writable() || r.path.is_none())
+        .map(|r| dump_region(pid, dir, &map, r))
+        .collect::<SimpleResult<Vec<DumpedRegion>>>()?;
+
+    let manifest = CrashDumpManifest {
+        pid: pid.as_raw(),
+        signal: signal.to_string(),
+        rip,
+        regions,
+    };
+
+    let manifest_path = dir.join(MANIFEST_NAME);
+    let json = serde_json::to_string_pretty(&manifest)
+        .map_err(|e| SimpleError::new(format!("Couldn't serialize the crash dump manifest: {}", e)))?;
+    fs::write(&manifest_path, json)
+        .map_err(|e| SimpleError::new(format!("Couldn't write {}: {}", manifest_path.display(), e)))?;
+
+    Ok(manifest_path)
+}
+
+/// Save as much of one region as we can read
+fn dump_region(pid: Pid, dir: &Path, map: &MemoryMap, region: &Region) -> SimpleResult<DumpedRegion> {
+    let name = format!("{:016x}-{:016x}.bin", region.start, region.end);
+    let path = dir.join(&name);
+    let end = std::cmp::min(region.end, region.start.saturating_add(MAX_REGION_SIZE));
+
+    let mut file: Option<File> = None;
+    let mut address = region.start;
+
+    // Read until we hit the end, or something unreadable (like a guard page)
+    while address < end {
+        let length = std::cmp::min(CHUNK_SIZE as u64, end - address) as usize;
+
+        let data = match AnalyzedValue::get_memory(pid, address, length) {
+            Some((data, _)) if !data.is_empty() => data,
+            _ => break,
+        };
+
+        // Only create the file once there's something to put in it
+        let out = match &mut file {
+            Some(out) => out,
+            None => file.insert(File::create(&path)
+                .map_err(|e| SimpleError::new(format!("Couldn't create {}: {}", path.display(), e)))?),
+        };
+        out.write_all(&data)
+            .map_err(|e| SimpleError::new(format!("Couldn't write {}: {}", path.display(), e)))?;
+
+        address += data.len() as u64;
+        if data.len() < length {
+            break;
+        }
+    }
+
+    Ok(DumpedRegion {
+        start: region.start,
+        end: region.end,
+        permissions: region.permissions.clone(),
+        path: region.path.clone(),
+        region: map.classify(region.start),
+        file: file.map(|_| name),
+        bytes_saved: address - region.start,
+    })
+}
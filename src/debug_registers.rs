@@ -0,0 +1,79 @@
+//! Reads the x86 debug registers (DR0 - DR7).
+//!
+//! These hold the hardware breakpoints, which anti-debugging code likes to
+//! check or overwrite. DR0 - DR3 are breakpoint addresses, DR6 is the status
+//! (which breakpoint fired), and DR7 is the control register that enables
+//! each breakpoint and says what it triggers on. DR4 and DR5 are obsolete,
+//! and always read as zero.
+
+use std::mem::offset_of;
+
+use nix::errno::Errno;
+use nix::libc;
+use nix::unistd::Pid;
+
+/// The names of the debug registers, in order
+pub const DEBUG_REGISTERS: &[&str] = &["dr0", "dr1", "dr2", "dr3", "dr4", "dr5", "dr6", "dr7"];
+
+// Where the debug registers live in the `user` area that PTRACE_PEEKUSER reads
+const DEBUGREG_OFFSET: usize = offset_of!(libc::user, u_debugreg);
+
+// The layout of DR7 - each breakpoint has a local and global enable bit
+// starting at bit 0, and a condition and length starting at bit 16
+const DR7_CONTROL_SHIFT: u64 = 16;
+const DR7_CONTROL_SIZE: u64 = 4;
+
+/// Read DR0 - DR7 from a stopped process (None if any of them can't be read)
+pub fn read_debug_registers(pid: Pid) -> Option<[u64; 8]> {
+    let mut out = [0u64; 8];
+
+    for (i, register) in out.iter_mut().enumerate() {
+        let offset = DEBUGREG_OFFSET + i * std::mem::size_of::<u64>();
+
+        // nix doesn't wrap PTRACE_PEEKUSER, and -1 is a valid value, so we
+        // have to check errno to find out if it failed
+        Errno::clear();
+        let value = unsafe {
+            libc::ptrace(libc::PTRACE_PEEKUSER, pid.as_raw(), offset as *mut libc::c_void, std::ptr::null_mut::<libc::c_void>())
+        };
+        if value == -1 && Errno::last() != Errno::UnknownErrno {
+            return None;
+        }
+
+        *register = value as u64;
+    }
+
+    Some(out)
+}
+
+/// Describe which hardware breakpoints DR7 enables, and what they trigger
+/// on (eg, `DR0: local, on write, 4 bytes`)
+pub fn decode_dr7(dr7: u64) -> Vec<String> {
+    (0..4).filter_map(|i| {
+        let local  = dr7 & (1 << (i * 2)) != 0;
+        let global = dr7 & (1 << (i * 2 + 1)) != 0;
+
+        let scope = match (local, global) {
+            (false, false) => return None,
+            (true, false)  => "local",
+            (false, true)  => "global",
+            (true, true)   => "local + global",
+        };
+
+        let control = dr7 >> (DR7_CONTROL_SHIFT + i * DR7_CONTROL_SIZE);
+        let condition = match control & 0x3 {
+            0 => "on execute",
+            1 => "on write",
+            2 => "on I/O",
+            _ => "on read/write",
+        };
+        let length = match (control >> 2) & 0x3 {
+            0 => 1,
+            1 => 2,
+            2 => 8,
+            _ => 4,
+        };
+
+        Some(format!("DR{}: {}, {}, {} byte{}", i, scope, condition, length, if length == 1 { "" } else { "s" }))
+    }).collect()
+}
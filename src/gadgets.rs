@@ -0,0 +1,79 @@
+//! Static search for ROP gadgets.
+//!
+//! Unlike the rest of Mandrake, this doesn't run anything - we just try to
+//! disassemble from every offset in a buffer, and keep the runs of
+//! instructions that end in a `ret` (or an indirect `jmp`).
+use std::fmt;
+
+use iced_x86::{Code, FlowControl};
+use serde::{Serialize, Deserialize};
+
+use crate::analyzed_value::AnalyzedValue;
+
+/// A sequence of instructions that ends by returning (or jumping) somewhere
+/// the attacker controls.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Gadget {
+    pub address: u64,
+
+    /// The instructions, including the final `ret` / `jmp`
+    pub instructions: Vec<String>,
+
+    /// The gadget's encoding, as hex
+    pub bytes: String,
+}
+
+impl fmt::Display for Gadget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{:08x}: {}", self.address, self.instructions.join("; "))
+    }
+}
+
+/// Find every gadget of up to `max_length` instructions in a buffer that's
+/// loaded at `base`.
+pub fn find_gadgets(bytes: &[u8], base: u64, max_length: usize) -> Vec<Gadget> {
+    (0..bytes.len())
+        .filter_map(|offset| gadget_at(bytes, base, offset, max_length))
+        .collect()
+}
+
+/// Disassemble from an offset, and see if it ends in a gadget.
+fn gadget_at(bytes: &[u8], base: u64, offset: usize, max_length: usize) -> Option<Gadget> {
+    let mut instructions: Vec<String> = vec![];
+    let mut position = offset;
+
+    while instructions.len() < max_length {
+        let decoded = AnalyzedValue::disassemble(&bytes[position..], base + position as u64)?;
+        if decoded.code() == Code::INVALID {
+            return None;
+        }
+
+        instructions.push(AnalyzedValue::format_instruction(&decoded)?);
+        position += decoded.len();
+
+        match decoded.flow_control() {
+            // These end the gadget
+            FlowControl::Return | FlowControl::IndirectBranch => {
+                return Some(Gadget {
+                    address: base + offset as u64,
+                    instructions,
+                    bytes: hex::encode(&bytes[offset..position]),
+                });
+            },
+
+            // Keep going through anything that falls through to the next
+            // instruction
+            FlowControl::Next => (),
+
+            // Anything else (calls, branches, interrupts) goes somewhere we
+            // don't control
+            _ => return None,
+        }
+
+        if position >= bytes.len() {
+            return None;
+        }
+    }
+
+    None
+}
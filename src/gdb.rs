@@ -0,0 +1,126 @@
+//! Writes a GDB script that picks up where a trace left off.
+//!
+//! The script starts the program the same way the trace did (same arguments,
+//! same standard in), and sets breakpoints where the trace started, at each
+//! syscall, and at the crash, with `display`s for the registers the crashing
+//! instruction used. Breakpoints are relative to `$base`, which the script
+//! works out from the running process, so they still line up when the
+//! executable is loaded somewhere else (ASLR) - see [`crate::annotations`].
+//!
+//! Syscalls outside the executable (eg, in libc) are listed in a commented-out
+//! section at the end, since they can't be placed.
+
+use std::fmt::Write;
+
+use crate::analyzed_value::AnalyzedValue;
+use crate::annotations::TraceAnnotations;
+use crate::mandrake_output::MandrakeOutput;
+
+/// Quote an argument for the shell that GDB starts the program with
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// The registers the last instruction that can be decoded used (for a crash,
+/// that's the instruction that crashed, or the `ret` / `jmp` that went
+/// somewhere bad).
+fn crash_registers(output: &MandrakeOutput) -> Vec<String> {
+    let mut output = output.clone();
+    output.densify();
+
+    output.history.iter().rev().find_map(|entry| {
+        let rip = entry.registers.get("rip")?;
+        let bytes = hex::decode(rip.instruction_bytes.as_ref()?).ok()?;
+
+        AnalyzedValue::instruction_registers(&bytes, rip.value)
+    }).unwrap_or_default()
+}
+
+/// Build the script for a trace. `stdin_path` is where the standard in was
+/// saved, if there was any.
+pub fn gdb_script(output: &MandrakeOutput, stdin_path: Option<&str>) -> String {
+    let annotations = TraceAnnotations::new(output);
+
+    // Raw code always runs in the harness, which loads it at the same place
+    let is_code = output.code_length > 0;
+
+    let mut out = String::new();
+    writeln!(out, "# Reproduces a Mandrake trace (generated by Mandrake)").unwrap();
+    match is_code {
+        true  => writeln!(out, "# Run it with `gdb -x <this file> --args <harness> <code as hex>`").unwrap(),
+        false => writeln!(out, "# Run it with `gdb -x <this file> <binary>`").unwrap(),
+    }
+    writeln!(out, "# Breakpoints are relative to the executable's base ($base), which was 0x{:x} in the trace", annotations.image_base).unwrap();
+    writeln!(out, "set pagination off").unwrap();
+    writeln!(out, "set confirm off").unwrap();
+    writeln!(out).unwrap();
+
+    // Stop at the very first instruction, so the breakpoints can be set
+    // before anything runs
+    let mut start = vec!["starti".to_string()];
+    if let Some(argv) = &output.argv {
+        start.extend(argv.iter().skip(1).map(|arg| quote(arg)));
+    }
+    if let Some(stdin_path) = stdin_path {
+        start.push(format!("< {}", quote(stdin_path)));
+    }
+    writeln!(out, "{}", start.join(" ")).unwrap();
+    writeln!(out).unwrap();
+
+    match is_code {
+        true => writeln!(out, "set $base = 0x{:x}", annotations.image_base).unwrap(),
+        false => {
+            // The lowest mapping of the executable, the same way Mandrake
+            // found it
+            writeln!(out, "python").unwrap();
+            writeln!(out, "import os").unwrap();
+            writeln!(out, "path = os.path.realpath(gdb.current_progspace().filename)").unwrap();
+            writeln!(out, "mappings = gdb.execute(\"info proc mappings\", to_string=True).splitlines()").unwrap();
+            writeln!(out, "starts = [int(line.split()[0], 16) for line in mappings if line.strip().startswith(\"0x\") and os.path.realpath(line.split()[-1]) == path]").unwrap();
+            writeln!(out, "gdb.execute(\"set $base = 0x%x\" % min(starts))").unwrap();
+            writeln!(out, "end").unwrap();
+        },
+    }
+    writeln!(out).unwrap();
+
+    if let Some(start) = annotations.start {
+        writeln!(out, "# Where the trace started").unwrap();
+        writeln!(out, "break *($base + 0x{:x})", start).unwrap();
+    }
+
+    if !annotations.syscalls.is_empty() {
+        writeln!(out, "# Syscalls").unwrap();
+        for offset in &annotations.syscalls {
+            writeln!(out, "break *($base + 0x{:x})", offset).unwrap();
+        }
+    }
+
+    if let Some(crash) = annotations.crash {
+        if let Some(reason) = &output.exit_reason {
+            writeln!(out, "# The crash ({})", reason).unwrap();
+        }
+        writeln!(out, "break *($base + 0x{:x})", crash).unwrap();
+    }
+    writeln!(out).unwrap();
+
+    writeln!(out, "display/i $pc").unwrap();
+    if annotations.crash.is_some() {
+        for register in crash_registers(output) {
+            writeln!(out, "display/x ${}", register).unwrap();
+        }
+    }
+    writeln!(out).unwrap();
+    writeln!(out, "continue").unwrap();
+
+    if !annotations.outside.is_empty() {
+        writeln!(out).unwrap();
+        writeln!(out, "# Outside the executable (at their addresses in the trace):").unwrap();
+        for (address, comment) in &annotations.outside {
+            for line in comment.lines() {
+                writeln!(out, "# 0x{:x}: {}", address, line).unwrap();
+            }
+        }
+    }
+
+    out
+}
@@ -0,0 +1,63 @@
+//! Writes a Ghidra script that paints a trace onto a static analysis.
+//!
+//! The script colours every instruction that ran, and adds a comment with the
+//! registers at each syscall and at the crash (if there was one). The
+//! addresses are added to the program's image base when the script runs (see
+//! [`crate::annotations`]), so it still lines up when the code was loaded
+//! somewhere else (ASLR).
+//!
+//! It's generated from a finished [`MandrakeOutput`], so it works just as well
+//! on a saved trace.
+
+use std::fmt::Write;
+
+use crate::annotations::TraceAnnotations;
+use crate::mandrake_output::MandrakeOutput;
+
+/// The colour executed instructions are painted (light green)
+const EXECUTED_COLOR: (u8, u8, u8) = (0xb4, 0xee, 0xb4);
+
+/// Quote a string for Python (a JSON string is a valid unicode literal in
+/// both Python 2, which Ghidra uses, and Python 3)
+fn quote(s: &str) -> String {
+    format!("u{}", serde_json::to_string(s).unwrap())
+}
+
+/// Build the script (a Python GhidraScript) for a trace.
+pub fn ghidra_script(output: &MandrakeOutput) -> String {
+    let annotations = TraceAnnotations::new(output);
+
+    let mut out = String::new();
+    writeln!(out, "# Paints a Mandrake trace onto the current program (generated by Mandrake)").unwrap();
+    writeln!(out, "# @category Mandrake").unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "from java.awt import Color").unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "# Offsets from where the code was loaded (0x{:x} in the trace)", annotations.image_base).unwrap();
+    writeln!(out, "EXECUTED = [").unwrap();
+    for offset in annotations.executed.keys() {
+        writeln!(out, "    0x{:x},", offset).unwrap();
+    }
+    writeln!(out, "]").unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "COMMENTS = [").unwrap();
+    for (offset, comment) in &annotations.comments {
+        writeln!(out, "    (0x{:x}, {}),", offset, quote(comment)).unwrap();
+    }
+    writeln!(out, "]").unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "base = currentProgram.getImageBase()").unwrap();
+    writeln!(out, "color = Color(0x{:02x}, 0x{:02x}, 0x{:02x})", EXECUTED_COLOR.0, EXECUTED_COLOR.1, EXECUTED_COLOR.2).unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "for offset in EXECUTED:").unwrap();
+    writeln!(out, "    setBackgroundColor(base.add(offset), color)").unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "for (offset, comment) in COMMENTS:").unwrap();
+    writeln!(out, "    address = base.add(offset)").unwrap();
+    writeln!(out, "    existing = getPreComment(address)").unwrap();
+    writeln!(out, "    setPreComment(address, comment if existing is None else existing + \"\\n\" + comment)").unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "print(\"Mandrake: painted %d instructions and added %d comments\" % (len(EXECUTED), len(COMMENTS)))").unwrap();
+
+    out
+}
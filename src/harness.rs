@@ -0,0 +1,71 @@
+//! Finds the harness that raw code runs in.
+//!
+//! The harness is a small executable (`harness/harness`, built from
+//! `harness/harness.c`) that maps the code and calls it. A copy is built into
+//! Mandrake, so raw code can be analyzed without fetching it separately - it's
+//! written to a temporary directory when it's needed, and deleted once the
+//! [`Harness`] is dropped.
+
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use simple_error::{SimpleError, SimpleResult};
+use tempfile::TempDir;
+
+/// Where the harness is, relative to a checkout of the codebase
+pub const DEFAULT_HARNESS_PATH: &str = "./harness/harness";
+
+/// The harness built into Mandrake
+pub const EMBEDDED_HARNESS: &[u8] = include_bytes!("../harness/harness");
+
+/// A harness to run raw code in: either one on disk, or a temporary copy of
+/// the built-in one.
+#[derive(Debug)]
+pub enum Harness {
+    Path(PathBuf),
+
+    /// The built-in harness, written as `harness` in a temporary directory
+    /// (so it runs with the same name as the real one)
+    Embedded(TempDir),
+}
+
+impl Harness {
+    /// Write the built-in harness somewhere it can be run from.
+    pub fn embedded() -> SimpleResult<Self> {
+        let directory = tempfile::Builder::new()
+            .prefix("mandrake-")
+            .tempdir()
+            .map_err(|e| SimpleError::new(format!("Couldn't create a temporary directory for the harness: {}", e)))?;
+
+        // Written and closed before it's run, otherwise running it fails with
+        // ETXTBSY
+        let path = directory.path().join("harness");
+        fs::write(&path, EMBEDDED_HARNESS)
+            .map_err(|e| SimpleError::new(format!("Couldn't write the built-in harness to {:?}: {}", path, e)))?;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o700))
+            .map_err(|e| SimpleError::new(format!("Couldn't make the built-in harness executable: {}", e)))?;
+
+        Ok(Self::Embedded(directory))
+    }
+
+    /// Pick the harness to use: `path` if there is one, otherwise
+    /// [`DEFAULT_HARNESS_PATH`] if it's there, or else the built-in one.
+    /// `embedded` always uses the built-in one.
+    pub fn find(path: Option<&str>, embedded: bool) -> SimpleResult<Self> {
+        match (path, embedded) {
+            (_, true)          => Self::embedded(),
+            (Some(path), _)    => Ok(Self::Path(PathBuf::from(path))),
+            (None, _) if Path::new(DEFAULT_HARNESS_PATH).exists() => Ok(Self::Path(PathBuf::from(DEFAULT_HARNESS_PATH))),
+            (None, _)          => Self::embedded(),
+        }
+    }
+
+    /// Where the harness is (valid for as long as this is around)
+    pub fn path(&self) -> PathBuf {
+        match self {
+            Self::Path(path)          => path.clone(),
+            Self::Embedded(directory) => directory.path().join("harness"),
+        }
+    }
+}
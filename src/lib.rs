@@ -1,5 +1,21 @@
 pub mod analyzed_value;
+pub mod annotations;
+pub mod constants;
+pub mod coverage;
+pub mod crash_dump;
+pub mod debug_registers;
+pub mod gadgets;
+pub mod gdb;
+pub mod ghidra;
+pub mod harness;
 pub mod mandrake_output;
 pub mod mandrake;
+pub mod memory_cache;
+pub mod memory_map;
+pub mod radare2;
+pub mod selftest;
 pub mod visibility_configuration;
+pub mod symbols;
+pub mod syscall_definitions;
 pub mod syscalls;
+pub mod syscall_flags;
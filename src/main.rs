@@ -1,50 +1,62 @@
-use std::fmt;
-use std::path::Path;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 use std::str::FromStr;
 
-use simple_error::{SimpleError, bail};
-use clap::Parser;
+use simple_error::{SimpleError, SimpleResult, bail};
+use clap::{ArgMatches, FromArgMatches, IntoApp, Parser};
 use clap_num::maybe_hex;
+use serde::Deserialize;
+use nix::sys::signal::Signal;
 
 // Import from the library
-use mandrake::mandrake::Mandrake;
+use mandrake::mandrake::{parse_signal, AnalyzeMode, DumpRegion, FdMapping, Mandrake, Phase, SignalInjection, TraceDetail, DEFAULT_EXEC_ARG_LENGTH, DEFAULT_HOLD_TIMEOUT, DEFAULT_INPUT_HEX_LIMIT, DEFAULT_MAX_TRACE_BYTES, REGISTERS};
+use mandrake::constants::Constants;
+use mandrake::gadgets::{find_gadgets, Gadget};
+use mandrake::gdb::gdb_script;
+use mandrake::ghidra::ghidra_script;
+use mandrake::harness::Harness;
+use mandrake::radare2::radare2_script;
+use mandrake::selftest::{check_environment, run_cases};
+use mandrake::symbols::{section_bytes, symbol_bytes};
+use mandrake::mandrake_output::{FormatOptions, MandrakeOutput, OutputFormat, DEFAULT_PICKLE_PROTOCOL};
+use mandrake::memory_map::RegionFilter;
+use mandrake::syscalls::load_syscalls_csv;
 use mandrake::visibility_configuration::VisibilityConfiguration;
 
-#[derive(Debug)]
-enum OutputFormat {
-    JSON,
-    YAML,
-    PLAINTEXT,
-    PICKLE,
-}
+/// Parse a byte or a string of bytes as hex (eg, "0a", "0x0a", or "000a0d")
+fn parse_bytes(input: &str) -> SimpleResult<Vec<u8>> {
+    let hex = input.strip_prefix("0x").unwrap_or(input);
 
-impl FromStr for OutputFormat {
-    type Err = SimpleError;
+    match hex.len() {
+        1 | 2 => u8::from_str_radix(hex, 16).map(|byte| vec![byte]).map_err(|e| SimpleError::new(format!("Invalid byte {}: {}", input, e))),
+        _     => hex::decode(hex).map_err(|e| SimpleError::new(format!("Invalid bytes {}: {}", input, e))),
+    }
+}
 
-    fn from_str(input: &str) -> Result<OutputFormat, Self::Err> {
-        match &input.to_lowercase()[..] {
-            "json"   => Ok(OutputFormat::JSON),
-            "yaml"   => Ok(OutputFormat::YAML),
-            "pickle" => Ok(OutputFormat::PICKLE),
-            "plaintext" | "text" => Ok(OutputFormat::PLAINTEXT),
+/// Another format to write the same output in (see --also-format).
+#[derive(Debug)]
+struct ExtraOutput {
+    format: OutputFormat,
 
-            _       => bail!("Unknown format: {}", input),
-        }
-    }
+    /// Where to write it (stdout if it's not set)
+    path: Option<String>,
 }
 
-impl fmt::Display for OutputFormat {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            Self::JSON      => write!(f, "JSON"),
-            Self::YAML      => write!(f, "YAML"),
-            Self::PICKLE    => write!(f, "PICKLE"),
-            Self::PLAINTEXT => write!(f, "PLAINTEXT"),
+impl FromStr for ExtraOutput {
+    type Err = SimpleError;
+
+    /// Parse `FORMAT` or `FORMAT=PATH`
+    fn from_str(input: &str) -> Result<ExtraOutput, Self::Err> {
+        match input.split_once('=') {
+            Some((format, path)) => Ok(ExtraOutput { format: format.parse()?, path: Some(path.to_string()) }),
+            None => Ok(ExtraOutput { format: input.parse()?, path: None }),
         }
     }
 }
 
-
 #[derive(Parser, Debug)]
 #[clap(about, version, author)]
 struct Elf {
@@ -55,6 +67,10 @@ struct Elf {
     #[clap(long)]
     stdin_data: Option<String>,
 
+    /// Open a file and give it to the ELF as another file descriptor, as FD=PATH (eg, "3=input.bin"); a named pipe blocks until something opens the other end (can be used more than once)
+    #[clap(long = "fd", value_name = "FD=PATH")]
+    fds: Vec<FdMapping>,
+
     /// The ELF executable
     elf: String,
 
@@ -66,11 +82,32 @@ struct Elf {
 #[clap(about, version, author)]
 struct Code {
     /// The code, as a hex string (eg: "4831C0C3")
-    code: String,
+    #[clap(required_unless_present = "from-elf", conflicts_with = "from-elf")]
+    code: Option<String>,
+
+    /// Take the code from this ELF instead (with --section or --symbol), and run it in the harness without the loader or libc
+    #[clap(long)]
+    from_elf: Option<String>,
+
+    /// The section of the --from-elf ELF to run (eg, ".text")
+    #[clap(long, requires = "from-elf", conflicts_with = "symbol")]
+    section: Option<String>,
+
+    /// The function (or other symbol) in the --from-elf ELF to run
+    #[clap(long, requires = "from-elf")]
+    symbol: Option<String>,
 
-    /// The path to the required harness
-    #[clap(long, default_value_t = String::from("./harness/harness"))]
-    harness: String,
+    /// How many bytes of the --section or --symbol to run (by default, all of it)
+    #[clap(long, requires = "from-elf", parse(try_from_str=maybe_hex))]
+    length: Option<usize>,
+
+    /// The path to the harness (by default, ./harness/harness, or the one built into Mandrake if that isn't there)
+    #[clap(long)]
+    harness: Option<String>,
+
+    /// Use the harness built into Mandrake, even if there's one at ./harness/harness
+    #[clap(long, conflicts_with = "harness")]
+    embedded_harness: bool,
 
     /// If set, doesn't hide instructions executed outside of the harness
     /// (helpful if, say, you're analyzing shellcode that allocates memory)
@@ -78,8 +115,9 @@ struct Code {
     show_everything: bool,
 }
 
+/// The things we can analyze
 #[derive(clap::Subcommand, Debug)]
-enum Action {
+enum Target {
     /// Analyze raw machine code using a harness
     Code(Code),
 
@@ -87,26 +125,439 @@ enum Action {
     Elf(Elf),
 }
 
+#[derive(Parser, Debug)]
+#[clap(about, version, author)]
+struct Bench {
+    /// The number of times to run the analysis
+    #[clap(long, default_value_t = 10)]
+    iterations: usize,
+
+    #[clap(subcommand)]
+    target: Target,
+}
+
+#[derive(Parser, Debug)]
+#[clap(about, version, author)]
+struct Verify {
+    /// The saved JSON output to compare against
+    #[clap(long)]
+    expected: String,
+
+    #[clap(subcommand)]
+    target: Target,
+}
+
+#[derive(Parser, Debug)]
+#[clap(about, version, author)]
+struct Replay {
+    /// The saved JSON output
+    file: String,
+}
+
+#[derive(Parser, Debug)]
+#[clap(about, version, author)]
+struct Gadgets {
+    /// The file to search (it's treated as raw machine code, so addresses are the base + the offset into the file)
+    file: String,
+
+    /// The address the file is loaded at
+    #[clap(long, default_value_t = 0, parse(try_from_str=maybe_hex))]
+    base: u64,
+
+    /// The most instructions in a gadget (including the ret / jmp)
+    #[clap(long, default_value_t = 5)]
+    max_length: usize,
+}
+
+#[derive(Parser, Debug)]
+#[clap(about, version, author)]
+struct Selftest {
+    /// The path to the harness to test with (by default, ./harness/harness, or the one built into Mandrake if that isn't there)
+    #[clap(long)]
+    harness: Option<String>,
+
+    /// Test with the harness built into Mandrake, even if there's one at ./harness/harness
+    #[clap(long, conflicts_with = "harness")]
+    embedded_harness: bool,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Action {
+    #[clap(flatten)]
+    Analyze(Target),
+
+    /// Run an analysis several times and report how long it takes (the output is discarded)
+    Bench(Bench),
+
+    /// Run an analysis and check that it matches saved (JSON) output, exiting with an error if it doesn't
+    Verify(Verify),
+
+    /// Load saved (JSON) output instead of running anything, and write it out again (eg, in another format, or with --emit-ghidra)
+    Replay(Replay),
+
+    /// Find ROP gadgets in a file, without running anything
+    Gadgets(Gadgets),
+
+    /// Check that Mandrake works here: the environment (eg, ptrace permissions), then some cases with known results
+    Selftest(Selftest),
+}
+
+/// Options that can be loaded from a --config file.
+///
+/// The names match the commandline options (eg, "snippit-length"), and
+/// anything that's set on the commandline overrides the file.
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct ConfigFile {
+    output_format:         Option<String>,
+    output_file:           Option<String>,
+    also_format:           Option<Vec<String>>,
+    emit_ghidra:           Option<String>,
+    emit_r2:               Option<String>,
+    emit_gdb:              Option<String>,
+    pickle_protocol:       Option<u8>,
+    pickle_as_script:      Option<bool>,
+    snippit_length:        Option<usize>,
+    rip_snippit_length:    Option<usize>,
+    data_snippit_length:   Option<usize>,
+    stack_snippit_length:  Option<usize>,
+    analysis_window:       Option<usize>,
+    syscall_snippit_length: Option<usize>,
+    minimum_viable_string: Option<usize>,
+    max_string_length:     Option<usize>,
+    exec_arg_length:       Option<usize>,
+    max_trace_bytes:       Option<usize>,
+    input_hex_limit:       Option<usize>,
+    max_instructions:      Option<usize>,
+    cpu_time_limit:        Option<u64>,
+    progress:              Option<bool>,
+    time_breakdown:        Option<bool>,
+    detect_loops:          Option<bool>,
+    loop_repeats:          Option<usize>,
+    ignore_stdout:         Option<bool>,
+    ignore_stderr:         Option<bool>,
+    follow_exec_syscalls:  Option<bool>,
+    stop_at_syscall:       Option<String>,
+    deliver_signal:        Option<Vec<String>>,
+    fatal_signal:          Option<Vec<String>>,
+    inject_signal:         Option<Vec<String>>,
+    dump_region:           Option<Vec<String>>,
+    dump_dir:              Option<String>,
+    skip_syscall_detail:   Option<Vec<String>>,
+    trace_detail:          Option<String>,
+    analyze:               Option<String>,
+    registers:             Option<Vec<String>>,
+    proc_status:           Option<bool>,
+    no_memory:             Option<bool>,
+    no_full_coverage:      Option<bool>,
+    show_gaps:             Option<bool>,
+    bad_bytes:             Option<Vec<String>>,
+    fail_on_bad_bytes:     Option<bool>,
+    dedup_memory:          Option<bool>,
+    delta_history:         Option<bool>,
+    collapse_loops:        Option<bool>,
+    sample_rate:           Option<usize>,
+    only_mnemonics:        Option<Vec<String>>,
+    tail:                  Option<usize>,
+    repeat:                Option<usize>,
+    capture_writes:        Option<bool>,
+    resolve_imports:       Option<bool>,
+    allow_region:          Option<Vec<String>>,
+    deny_region:           Option<Vec<String>>,
+    debug_regs:            Option<bool>,
+    memory_entropy:        Option<bool>,
+    crash_dump:            Option<String>,
+    hold_on_crash:         Option<bool>,
+    hold_timeout:          Option<u64>,
+    int3_stops:            Option<bool>,
+    capture_envp:          Option<bool>,
+    disable_aslr:          Option<bool>,
+    deterministic:         Option<bool>,
+    keep_env:              Option<Vec<String>>,
+    upcoming_instructions: Option<usize>,
+    deref_depth:           Option<usize>,
+    phase:                 Option<Vec<String>>,
+    constants_file:        Option<String>,
+    syscalls_csv:          Option<String>,
+
+    /// Only used when analyzing an ELF file
+    visibility:            Option<VisibilityConfiguration>,
+}
+
+/// Copy a value from the config file into the arguments, unless the user
+/// set it on the commandline.
+macro_rules! merge_config {
+    ($args:ident, $matches:ident, $config:ident, $field:ident) => {
+        if $matches.occurrences_of(stringify!($field).replace('_', "-").as_str()) == 0 {
+            if let Some(value) = $config.$field {
+                $args.$field = value.into();
+            }
+        }
+    };
+}
+
+/// Load a --config file, and use it to fill in anything that wasn't set on
+/// the commandline.
+fn apply_config_file(args: &mut Args, matches: &ArgMatches, path: &str) -> SimpleResult<()> {
+    let config = std::fs::read_to_string(path)
+        .map_err(|e| SimpleError::new(format!("Couldn't read config file {}: {}", path, e)))?;
+    let config: ConfigFile = serde_json::from_str(&config)
+        .map_err(|e| SimpleError::new(format!("Couldn't parse config file {}: {}", path, e)))?;
+
+    // These are parsed from strings, so they need special handling
+    if matches.occurrences_of("output-format") == 0 {
+        if let Some(output_format) = &config.output_format {
+            args.output_format = output_format.parse()?;
+        }
+    }
+
+    if matches.occurrences_of("also-format") == 0 {
+        if let Some(also_format) = &config.also_format {
+            args.also_format = also_format.iter().map(|f| f.parse()).collect::<SimpleResult<Vec<ExtraOutput>>>()?;
+        }
+    }
+
+    if matches.occurrences_of("bad-bytes") == 0 {
+        if let Some(bad_bytes) = &config.bad_bytes {
+            args.bad_bytes = bad_bytes.iter().map(|b| parse_bytes(b)).collect::<SimpleResult<Vec<Vec<u8>>>>()?;
+        }
+    }
+
+    if matches.occurrences_of("trace-detail") == 0 {
+        if let Some(trace_detail) = &config.trace_detail {
+            args.trace_detail = trace_detail.parse()?;
+        }
+    }
+
+    if matches.occurrences_of("analyze") == 0 {
+        if let Some(analyze) = &config.analyze {
+            args.analyze = analyze.parse()?;
+        }
+    }
+
+    if matches.occurrences_of("phase") == 0 {
+        if let Some(phases) = &config.phase {
+            args.phase = phases.iter().map(|p| p.parse()).collect::<SimpleResult<Vec<Phase>>>()?;
+        }
+    }
+
+    if matches.occurrences_of("deliver-signal") == 0 {
+        if let Some(signals) = &config.deliver_signal {
+            args.deliver_signal = signals.iter().map(|s| parse_signal(s)).collect::<SimpleResult<Vec<Signal>>>()?;
+        }
+    }
+
+    if matches.occurrences_of("fatal-signal") == 0 {
+        if let Some(signals) = &config.fatal_signal {
+            args.fatal_signal = signals.iter().map(|s| parse_signal(s)).collect::<SimpleResult<Vec<Signal>>>()?;
+        }
+    }
+
+    if matches.occurrences_of("inject-signal") == 0 {
+        if let Some(injections) = &config.inject_signal {
+            args.inject_signal = injections.iter().map(|i| i.parse()).collect::<SimpleResult<Vec<SignalInjection>>>()?;
+        }
+    }
+
+    if matches.occurrences_of("dump-region") == 0 {
+        if let Some(regions) = &config.dump_region {
+            args.dump_region = regions.iter().map(|r| r.parse()).collect::<SimpleResult<Vec<DumpRegion>>>()?;
+        }
+    }
+
+    merge_config!(args, matches, config, output_file);
+    merge_config!(args, matches, config, emit_ghidra);
+    merge_config!(args, matches, config, emit_r2);
+    merge_config!(args, matches, config, emit_gdb);
+    merge_config!(args, matches, config, pickle_protocol);
+    merge_config!(args, matches, config, pickle_as_script);
+    merge_config!(args, matches, config, snippit_length);
+    merge_config!(args, matches, config, rip_snippit_length);
+    merge_config!(args, matches, config, data_snippit_length);
+    merge_config!(args, matches, config, stack_snippit_length);
+    merge_config!(args, matches, config, analysis_window);
+    merge_config!(args, matches, config, syscall_snippit_length);
+    merge_config!(args, matches, config, minimum_viable_string);
+    merge_config!(args, matches, config, max_string_length);
+    merge_config!(args, matches, config, exec_arg_length);
+    merge_config!(args, matches, config, max_trace_bytes);
+    merge_config!(args, matches, config, input_hex_limit);
+    merge_config!(args, matches, config, max_instructions);
+    merge_config!(args, matches, config, cpu_time_limit);
+    merge_config!(args, matches, config, progress);
+    merge_config!(args, matches, config, time_breakdown);
+    merge_config!(args, matches, config, detect_loops);
+    merge_config!(args, matches, config, loop_repeats);
+    merge_config!(args, matches, config, ignore_stdout);
+    merge_config!(args, matches, config, ignore_stderr);
+    merge_config!(args, matches, config, follow_exec_syscalls);
+    merge_config!(args, matches, config, stop_at_syscall);
+    merge_config!(args, matches, config, skip_syscall_detail);
+    merge_config!(args, matches, config, registers);
+    merge_config!(args, matches, config, proc_status);
+    merge_config!(args, matches, config, no_memory);
+    merge_config!(args, matches, config, no_full_coverage);
+    merge_config!(args, matches, config, show_gaps);
+    merge_config!(args, matches, config, fail_on_bad_bytes);
+    merge_config!(args, matches, config, dedup_memory);
+    merge_config!(args, matches, config, delta_history);
+    merge_config!(args, matches, config, collapse_loops);
+    merge_config!(args, matches, config, sample_rate);
+    merge_config!(args, matches, config, only_mnemonics);
+    merge_config!(args, matches, config, tail);
+    merge_config!(args, matches, config, repeat);
+    merge_config!(args, matches, config, capture_writes);
+    merge_config!(args, matches, config, resolve_imports);
+    merge_config!(args, matches, config, allow_region);
+    merge_config!(args, matches, config, deny_region);
+    merge_config!(args, matches, config, debug_regs);
+    merge_config!(args, matches, config, memory_entropy);
+    merge_config!(args, matches, config, crash_dump);
+    merge_config!(args, matches, config, dump_dir);
+    merge_config!(args, matches, config, hold_on_crash);
+    merge_config!(args, matches, config, hold_timeout);
+    merge_config!(args, matches, config, int3_stops);
+    merge_config!(args, matches, config, capture_envp);
+    merge_config!(args, matches, config, disable_aslr);
+    merge_config!(args, matches, config, deterministic);
+    merge_config!(args, matches, config, keep_env);
+    merge_config!(args, matches, config, upcoming_instructions);
+    merge_config!(args, matches, config, deref_depth);
+    merge_config!(args, matches, config, constants_file);
+    merge_config!(args, matches, config, syscalls_csv);
+
+    // Visibility options don't have defaults, so anything that's unset on the
+    // commandline can come from the file
+    let target = match &mut args.action {
+        Action::Analyze(target) => target,
+        Action::Bench(bench) => &mut bench.target,
+        Action::Verify(verify) => &mut verify.target,
+        Action::Replay(_) | Action::Gadgets(_) | Action::Selftest(_) => return Ok(()),
+    };
+
+    if let (Target::Elf(elf_args), Some(visibility)) = (target, config.visibility) {
+        elf_args.visibility_configuration.fill_from(visibility);
+    }
+
+    Ok(())
+}
+
 /// Mandrake is an open-source machine code analyzer / instrumenter written in Rust.
 #[derive(Parser, Debug)]
 #[clap(name = "Mandrake", about, version, author)]
 struct Args {
-    /// The output format ("JSON", "YAML", "Plaintext", or "Pickle")
+    /// Load options from a JSON file (options on the commandline take precedence)
+    #[clap(long)]
+    config: Option<String>,
+
+    /// The output format ("JSON", "YAML", "Plaintext", "Pickle", "summary-line" for a single pass/fail line, or "indented" for the instructions indented by call depth)
     #[clap(short, long, default_value_t = OutputFormat::JSON)]
     output_format: OutputFormat,
 
+    /// Show more about what's happening on stderr: -v for progress through the trace, -vv for every ptrace operation, -vvv for every instruction (RUST_LOG works too)
+    #[clap(short, long, parse(from_occurrences), global = true)]
+    verbose: u64,
+
+    /// Only show errors on stderr
+    #[clap(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Write the output to this file instead of stdout (required for raw Pickle output)
+    #[clap(long)]
+    output_file: Option<String>,
+
+    /// Also write the output in another format, as FORMAT=FILE (eg, "json=trace.json"), or just FORMAT for stdout, without running the analysis again (can be used more than once)
+    #[clap(long, multiple_occurrences = true)]
+    also_format: Vec<ExtraOutput>,
+
+    /// Write a Ghidra script (Python) to this file that colours the instructions that ran, and comments the registers at each syscall and crash
+    #[clap(long, global = true)]
+    emit_ghidra: Option<String>,
+
+    /// Write a radare2 / rizin script to this file that comments the instructions that ran (with size hints), the syscalls, and the crash (load it with `r2 -i FILE BINARY`)
+    #[clap(long, global = true)]
+    emit_r2: Option<String>,
+
+    /// Write a GDB script to this file that re-runs the program with the same arguments and standard in (saved next to it, as FILE.stdin), with breakpoints at the start, each syscall, and the crash (run it with `gdb -x FILE BINARY`)
+    #[clap(long, global = true)]
+    emit_gdb: Option<String>,
+
+    /// The pickle protocol version to use with the Pickle output format (2 or 3)
+    #[clap(long, default_value_t = DEFAULT_PICKLE_PROTOCOL)]
+    pickle_protocol: u8,
+
+    /// Output Pickle as a Python script that loads the data (the old behaviour), instead of raw pickle bytes
+    #[clap(long)]
+    pickle_as_script: bool,
+
     /// The amount of context memory to read
     #[clap(short, long, default_value_t = 64, parse(try_from_str=maybe_hex))]
     snippit_length: usize,
 
-    /// The number of consecutive ASCII bytes to be considered a string
+    /// How much memory to save at rip, if it should be different from --snippit-length (eg, 16 is enough to decode an instruction)
+    #[clap(long, alias = "rip-snippet", parse(try_from_str=maybe_hex))]
+    rip_snippit_length: Option<usize>,
+
+    /// How much memory to save at the registers other than rip, rsp, and rbp, if it should be different from --snippit-length (eg, more to catch the strings rsi and rdi point to)
+    #[clap(long, alias = "data-snippet", parse(try_from_str=maybe_hex))]
+    data_snippit_length: Option<usize>,
+
+    /// How much memory to save at rsp and rbp, if it should be different from --snippit-length (eg, more to see several stack slots)
+    #[clap(long, alias = "stack-snippet", parse(try_from_str=maybe_hex))]
+    stack_snippit_length: Option<usize>,
+
+    /// How much memory to read at each value when looking for strings and code (defaults to 128, or the snippit length if that's longer); strings and instructions are only found within this window, but only --snippit-length bytes of it are saved, so a smaller window is faster but finds fewer strings
+    #[clap(long, alias = "scan-length", parse(try_from_str=maybe_hex))]
+    analysis_window: Option<usize>,
+
+    /// How many bytes to show from the buffer each syscall argument points to
+    #[clap(long, default_value_t = 8, parse(try_from_str=maybe_hex))]
+    syscall_snippit_length: usize,
+
+    /// The number of consecutive characters to be considered a string
     #[clap(short, long, default_value_t = 6, parse(try_from_str=maybe_hex))]
     minimum_viable_string: usize,
 
+    /// The maximum number of characters to save from a string
+    #[clap(long, default_value_t = 128, parse(try_from_str=maybe_hex))]
+    max_string_length: usize,
+
+    /// The maximum number of bytes to save from each argument / environment variable passed to a program (by execve, or to an ELF)
+    #[clap(long, default_value_t = DEFAULT_EXEC_ARG_LENGTH, parse(try_from_str=maybe_hex))]
+    exec_arg_length: usize,
+
+    /// Once the history is using about this many bytes of memory, stop saving memory snippits (including the ones already saved), then if it grows past it again, only save rip (the output's "shed" says what was left out and when)
+    #[clap(long, default_value_t = DEFAULT_MAX_TRACE_BYTES, parse(try_from_str=maybe_hex))]
+    max_trace_bytes: usize,
+
+    /// Include the code (or the ELF's stdin) in the output's "input" as hex if it's no bigger than this many bytes; bigger ones only get their SHA-256 and length
+    #[clap(long, default_value_t = DEFAULT_INPUT_HEX_LIMIT, parse(try_from_str=maybe_hex))]
+    input_hex_limit: usize,
+
     /// The maximum number of instructions to read before stopping (to prevent infinite loops)
     #[clap(short='i', long, default_value_t = 1024, parse(try_from_str=maybe_hex))]
     max_instructions: usize,
 
+    /// Stop once the code has used this many milliseconds of CPU time (unlike a wall-clock timeout, this isn't affected by how busy the system is)
+    #[clap(long, parse(try_from_str=maybe_hex))]
+    cpu_time_limit: Option<u64>,
+
+    /// Show how the trace is going on stderr (instructions executed and logged, the speed, where it is, and how long it's been), updated every second
+    #[clap(long)]
+    progress: bool,
+
+    /// Measure where the time tracing goes, and save it in "time_breakdown": visible vs. hidden code, and each module (eg, how much was spent in libc), to help choose what to hide
+    #[clap(long)]
+    time_breakdown: bool,
+
+    /// Stop when the code gets back to exactly the same state (rip, rsp, and the main registers) more than --loop-repeats times, since it's stuck (off by default, since code waiting on something outside of it looks stuck too)
+    #[clap(long)]
+    detect_loops: bool,
+
+    /// With --detect-loops, how many times the same state can repeat before it's called an infinite loop
+    #[clap(long, default_value_t = 100, parse(try_from_str=maybe_hex))]
+    loop_repeats: usize,
+
     /// Don't save output from stdout
     #[clap(long)]
     ignore_stdout: bool,
@@ -119,17 +570,518 @@ struct Args {
     #[clap(long)]
     follow_exec_syscalls: bool,
 
+    /// Stop right before the first syscall with this name executes (eg, "open" or "sys_open")
+    #[clap(long)]
+    stop_at_syscall: Option<String>,
+
+    /// Pass these signals to the code (eg, "SIGSEGV" or "segv") so its own handlers run, and keep tracing, instead of ending the trace; each one is logged in the history (can be used more than once)
+    #[clap(long, use_delimiter = true, multiple_occurrences = true, parse(try_from_str = parse_signal))]
+    deliver_signal: Vec<Signal>,
+
+    /// End the trace when these signals arrive, even ones that are normally handled (SIGALRM, which is the harness's timeout, and SIGCHLD) or passed with --deliver-signal (can be used more than once)
+    #[clap(long, use_delimiter = true, multiple_occurrences = true, parse(try_from_str = parse_signal))]
+    fatal_signal: Vec<Signal>,
+
+    /// Send a signal when the code reaches an instruction, as SIGNAL@INDEX (eg, "SIGUSR1@100" to send it before instruction 100 runs), and trace its handler; each one is logged in the history and listed in "injected_signals", with where the handler started (can be used more than once)
+    #[clap(long, use_delimiter = true, multiple_occurrences = true)]
+    inject_signal: Vec<SignalInjection>,
+
+    /// Don't decode the arguments of these syscalls (eg, "futex,clock_gettime"), just show their raw values; they're still logged, and this saves a lot of time for syscalls that are made constantly (can be used more than once)
+    #[clap(long, use_delimiter = true, multiple_occurrences = true)]
+    skip_syscall_detail: Vec<String>,
+
+    /// How much to analyze at each step: "full" (everything), "registers" (register values + the instruction), or "minimal" (just rip + the instruction)
+    #[clap(long, default_value_t = TraceDetail::Full)]
+    trace_detail: TraceDetail,
+
+    /// Which registers to fully analyze at each step: "all", or "smart" (only the registers the instruction at rip uses - the others are saved as bare values, without memory or strings)
+    #[clap(long, default_value_t = AnalyzeMode::All)]
+    analyze: AnalyzeMode,
+
+    /// Only fully analyze these registers, comma-separated (eg, "rax,rdi,rsi"); rip is always analyzed, and the rest are saved as bare values
+    #[clap(long, use_delimiter = true)]
+    registers: Option<Vec<String>>,
+
+    /// Save selected fields from /proc/<pid>/status (memory usage, threads, signal masks) when the analysis ends
+    #[clap(long)]
+    proc_status: bool,
+
+    /// Don't save memory snippits in the output (strings and instructions are still analyzed)
+    #[clap(long)]
+    no_memory: bool,
+
+    /// Don't save "full_coverage", the compact record of every instruction that was traced (including ones that weren't visible or logged)
+    #[clap(long)]
+    no_full_coverage: bool,
+
+    /// When instructions aren't logged (hidden by the visibility options, or not sampled), mark the next entry with how many were skipped ("skipped_since_last") and the address of the last one ("came_from")
+    #[clap(long)]
+    show_gaps: bool,
+
+    /// Bytes that raw code can't have, as hex (eg, "00,0a,0d" or "000a0d" for code that has to get through a string copy); where they are, and whether they're in code that ran, is saved in "badchar_report"
+    #[clap(long, alias = "bad-chars", use_delimiter = true, default_value = "00", parse(try_from_str = parse_bytes))]
+    bad_bytes: Vec<Vec<u8>>,
+
+    /// Exit with an error (after writing the output as usual) if raw code has any of the --bad-bytes, so it can gate a build
+    #[clap(long, alias = "fail-on-bad-chars")]
+    fail_on_bad_bytes: bool,
+
+    /// Store each distinct memory snippit once, in a top-level "memory_pool", and reference it by hash
+    #[clap(long)]
+    dedup_memory: bool,
+
+    /// Run the analysis this many times, and report (on stderr) whether every run produced the same trace, or where they first differed; the first run's output is saved as usual
+    #[clap(long, default_value_t = 1)]
+    repeat: usize,
+
+    /// Only log every Nth instruction (every instruction still runs one at a time, and int3s / syscalls are still handled)
+    #[clap(long, default_value_t = 1)]
+    sample_rate: usize,
+
+    /// Only log instructions with these mnemonics (eg, "syscall,call"); every instruction still runs and is stepped through, the rest just aren't saved in the history (can be used more than once)
+    #[clap(long, use_delimiter = true, multiple_occurrences = true)]
+    only_mnemonics: Vec<String>,
+
+    /// Only keep the last N logged instructions (eg, the lead up to a crash) instead of all of them; raise --max-instructions to let longer runs reach the end
+    #[clap(long, parse(try_from_str=maybe_hex))]
+    tail: Option<usize>,
+
+    /// Only save the registers that changed since the previous history entry (plus rip); the first entry is a full snapshot
+    #[clap(long)]
+    delta_history: bool,
+
+    /// Collapse runs of the same instruction (like `rep movsb`, or a jump to itself) into one history entry with a repeat_count, as long as each register stays the same or changes by the same amount every time
+    #[clap(long)]
+    collapse_loops: bool,
+
+    /// Stop the analysis when an int3 executes, instead of treating it as "stop logging and continue"
+    #[clap(long)]
+    int3_stops: bool,
+
+    /// Save the environment that an ELF sees on its stack
+    #[clap(long)]
+    capture_envp: bool,
+
+    /// Turn off address space randomization for the code, so addresses are the same every run (useful with --repeat and verify)
+    #[clap(long)]
+    disable_aslr: bool,
+
+    /// Run the code the same way every time, so runs can be compared: no inherited file descriptors (past stdin / stdout / stderr), an empty environment (except --keep-env), the program run by its full path with its file name as argv[0], and no ASLR
+    #[clap(long)]
+    deterministic: bool,
+
+    /// With --deterministic, keep this environment variable (can be used more than once)
+    #[clap(long, use_delimiter = true, multiple_occurrences = true)]
+    keep_env: Vec<String>,
+
+    /// Save the data passed to each write syscall in that instruction's "extra", so output lines up with the code that produced it
+    #[clap(long)]
+    capture_writes: bool,
+
+    /// Only read memory from these regions, comma-separated: region names like the "region" field shows (eg, "stack,heap,self:.data"), or part of the mapped path (can be used more than once)
+    #[clap(long, use_delimiter = true, multiple_occurrences = true)]
+    allow_region: Vec<String>,
+
+    /// Never read memory from these regions (same format as --allow-region; eg, "/dev/" to avoid device mappings)
+    #[clap(long, use_delimiter = true, multiple_occurrences = true)]
+    deny_region: Vec<String>,
+
+    /// When an ELF jumps or calls through its GOT (eg, from the PLT), note which imported function it's going to (like "-> memcpy@GLIBC_2.14") in that instruction's "extra"
+    #[clap(long)]
+    resolve_imports: bool,
+
+    /// Also save the debug registers (dr0 - dr7) at each step, with DR7's hardware breakpoints decoded; handy for anti-debugging code, but slower
+    #[clap(long)]
+    debug_regs: bool,
+
+    /// Also save the entropy (in bits per byte, up to 8) of each memory snippit in the history, to spot packed or encrypted buffers; raw code always has its entropy saved in "code_entropy"
+    #[clap(long)]
+    memory_entropy: bool,
+
+    /// If the code crashes, save its writable and anonymous memory (stack, heap, data, mmap'd regions) to this directory, one file per region plus a manifest.json
+    #[clap(long)]
+    crash_dump: Option<String>,
+
+    /// When the trace ends, save this memory in "dumped_regions", as ADDRESS:LENGTH (eg, "0x13370000:256") or REGISTER:LENGTH, optionally with an offset (eg, "rsp:256" or "rsp-0x10:64"), of at most 64MB; as much as is readable is saved (can be used more than once)
+    #[clap(long, multiple_occurrences = true)]
+    dump_region: Vec<DumpRegion>,
+
+    /// Write the --dump-region memory to files in this directory, instead of saving it in the output as hex
+    #[clap(long)]
+    dump_dir: Option<String>,
+
+    /// If the code crashes, leave it stopped at the crash and print its pid so a debugger can attach (eg, `gdb -p PID`), then carry on when Enter is pressed (or Mandrake gets SIGUSR1, if stdin isn't a terminal)
+    #[clap(long)]
+    hold_on_crash: bool,
+
+    /// With --hold-on-crash, carry on anyway after this many seconds (0 to wait forever)
+    #[clap(long, default_value_t = DEFAULT_HOLD_TIMEOUT)]
+    hold_timeout: u64,
+
+    /// How many instructions after the one at rip to disassemble at each step (0 to disable; only with "full" trace detail)
+    #[clap(long, default_value_t = 5)]
+    upcoming_instructions: usize,
+
+    /// When a register points at a pointer, follow the chain this many levels deep, saving each target as "points_to" (0 to disable; only with "full" trace detail)
+    #[clap(long, default_value_t = 0)]
+    deref_depth: usize,
+
+    /// Start a named phase when an address executes, as NAME@ADDRESS (eg, "payload@0x13370040"); each history entry is labelled with the current phase (can be used more than once)
+    #[clap(long, multiple_occurrences = true)]
+    phase: Vec<Phase>,
+
+    /// A CSV file of extra well-known constants to annotate, as VALUE,LABEL lines (eg, "0x13371337,Our marker")
+    #[clap(long)]
+    constants_file: Option<String>,
+
+    /// A CSV file of extra syscall definitions, in the same format as the built-in syscalls.csv (NUMBER,NAME,PARAM...); these replace built-in syscalls with the same number
+    #[clap(long)]
+    syscalls_csv: Option<String>,
+
     #[clap(subcommand)]
     action: Action,
 }
 
+/// Run the analysis on whatever the user asked for
+fn analyze(mandrake: &Mandrake, target: &Target) -> SimpleResult<MandrakeOutput> {
+    match target {
+        Target::Code(code_args) => {
+            let code = match (&code_args.code, &code_args.from_elf) {
+                (Some(code), _)  => hex::decode(code).map_err(|e| SimpleError::new(format!("Could not decode hex: {}", e)))?,
+                (None, Some(elf)) => code_from_elf(code_args, elf)?,
+                (None, None)      => bail!("No code to analyze"),
+            };
+            let harness = Harness::find(code_args.harness.as_deref(), code_args.embedded_harness)?;
+
+            mandrake.analyze_code(code, &harness.path(), code_args.show_everything)
+        },
+        Target::Elf(elf_args) => {
+            mandrake.analyze_elf(Path::new(&elf_args.elf), elf_args.stdin_data.clone(), &elf_args.fds, elf_args.args.clone(), &elf_args.visibility_configuration)
+        },
+    }
+}
+
+/// Get the code that `mandrake code --from-elf` runs
+fn code_from_elf(code_args: &Code, elf: &str) -> SimpleResult<Vec<u8>> {
+    let data = std::fs::read(elf)
+        .map_err(|e| SimpleError::new(format!("Couldn't read {}: {}", elf, e)))?;
+
+    let mut code = match (&code_args.section, &code_args.symbol) {
+        (Some(section), _) => section_bytes(&data, section)?,
+        (None, Some(symbol)) => symbol_bytes(&data, symbol, code_args.length)?,
+        (None, None) => bail!("--from-elf needs a --section or a --symbol to run"),
+    };
+
+    // A symbol's length is handled when it's read, since it can go past its
+    // size
+    if let Some(length) = code_args.length {
+        if length > code.len() {
+            bail!("Can't run 0x{:x} bytes of {}, it's only 0x{:x} bytes long", length, code_args.section.as_deref().unwrap_or_default(), code.len());
+        }
+        code.truncate(length);
+    }
+
+    Ok(code)
+}
+
+/// Run the analysis `repeat` times, and report whether every run was the same
+/// as the first (which is what's returned)
+fn analyze_repeatedly(mandrake: &Mandrake, target: &Target, repeat: usize) -> SimpleResult<MandrakeOutput> {
+    let first = analyze(mandrake, target)?;
+
+    for run in 2..=repeat {
+        let result = analyze(mandrake, target)?;
+
+        if let Some(difference) = first.first_difference(&result) {
+            eprintln!("Run {} was different from run 1: {}", run, difference);
+            return Ok(first);
+        }
+    }
+
+    if repeat > 1 {
+        eprintln!("All {} runs were identical", repeat);
+    }
+
+    Ok(first)
+}
+
+/// Search a file for gadgets, and format them
+fn run_gadgets(gadgets: &Gadgets, format: &OutputFormat) -> SimpleResult<Vec<u8>> {
+    let bytes = std::fs::read(&gadgets.file)
+        .map_err(|e| SimpleError::new(format!("Couldn't read {}: {}", gadgets.file, e)))?;
+    let found: Vec<Gadget> = find_gadgets(&bytes, gadgets.base, gadgets.max_length);
+
+    let mut out: Vec<u8> = vec![];
+    match format {
+        OutputFormat::JSON => {
+            let json = serde_json::to_string_pretty(&found)
+                .map_err(|e| SimpleError::new(format!("Couldn't serialize to JSON: {}", e)))?;
+            writeln!(out, "{}", json).unwrap();
+        },
+        OutputFormat::YAML => {
+            let yaml = serde_yaml::to_string(&found)
+                .map_err(|e| SimpleError::new(format!("Couldn't serialize to YAML: {}", e)))?;
+            writeln!(out, "{}", yaml).unwrap();
+        },
+        OutputFormat::PLAINTEXT => {
+            for gadget in &found {
+                writeln!(out, "{}", gadget).unwrap();
+            }
+        },
+        OutputFormat::PICKLE => bail!("The Pickle output format isn't supported for gadgets"),
+        OutputFormat::SUMMARY => bail!("The summary-line output format isn't supported for gadgets"),
+        OutputFormat::INDENTED => bail!("The indented output format isn't supported for gadgets"),
+    }
+
+    Ok(out)
+}
+
+/// Check the environment, then run the known-good cases, printing a line for
+/// each. Returns whether everything passed.
+fn run_selftest(selftest: &Selftest) -> bool {
+    let harness = match Harness::find(selftest.harness.as_deref(), selftest.embedded_harness) {
+        Ok(harness) => harness,
+        Err(e) => {
+            println!("Couldn't set up the harness: {}", e);
+            return false;
+        },
+    };
+
+    // Run everything before printing, so the table stays together
+    let environment = check_environment(&harness.path());
+    let cases = run_cases(&harness.path());
+
+    println!("Environment:");
+    for result in &environment {
+        println!("  {}", result);
+    }
+
+    println!();
+    println!("Cases:");
+    for result in &cases {
+        println!("  {}", result);
+    }
+
+    let failed = environment.iter().chain(cases.iter()).filter(|result| !result.passed).count();
+    println!();
+    match failed {
+        0 => println!("All {} checks passed", environment.len() + cases.len()),
+        _ => println!("{} of {} checks failed", failed, environment.len() + cases.len()),
+    }
+
+    failed == 0
+}
+
+/// Run the same analysis several times, and print timing stats
+fn run_bench(mandrake: &Mandrake, bench: &Bench) -> SimpleResult<()> {
+    if bench.iterations == 0 {
+        bail!("--iterations must be at least 1");
+    }
+
+    let mut durations: Vec<Duration> = vec![];
+    let mut instructions: usize = 0;
+
+    for _ in 0..bench.iterations {
+        let start = Instant::now();
+        let result = analyze(mandrake, &bench.target)?;
+        durations.push(start.elapsed());
+
+        instructions += result.instructions_executed;
+    }
+
+    durations.sort();
+    let total: Duration = durations.iter().sum();
+
+    println!("Iterations:            {}", bench.iterations);
+    println!("Instructions per run:  {}", instructions / bench.iterations);
+    println!("Min:                   {:?}", durations[0]);
+    println!("Median:                {:?}", durations[durations.len() / 2]);
+    println!("Max:                   {:?}", durations[durations.len() - 1]);
+    println!("Instructions / second: {:.0}", instructions as f64 / total.as_secs_f64());
+
+    Ok(())
+}
+
+/// Run the analysis and compare it to the saved output, returning the first
+/// difference (if there is one). Things that change from run to run (like
+/// the pid) are ignored - see [`MandrakeOutput::first_difference`].
+fn run_verify(mandrake: &Mandrake, verify: &Verify) -> SimpleResult<Option<String>> {
+    let mut expected = load_output(&verify.expected)?;
+    let mut result = analyze(mandrake, &verify.target)?;
+
+    // Compare the full history, however either one was saved
+    for output in [&mut expected, &mut result] {
+        output.densify();
+        output.inline_memory();
+    }
+
+    // Without this, the stack and library addresses are different every run
+    let difference = expected.first_difference(&result);
+    match (difference, expected.config.disable_aslr) {
+        (Some(difference), false) => Ok(Some(format!("{} (the expected output was saved without --disable-aslr or --deterministic, so addresses can change from run to run)", difference))),
+        (difference, _) => Ok(difference),
+    }
+}
+
+/// Load output that was saved as JSON
+fn load_output(path: &str) -> SimpleResult<MandrakeOutput> {
+    let data = std::fs::read_to_string(path)
+        .map_err(|e| SimpleError::new(format!("Couldn't read {}: {}", path, e)))?;
+
+    let mut output: MandrakeOutput = serde_json::from_str(&data)
+        .map_err(|e| SimpleError::new(format!("Couldn't parse {}: {}", path, e)))?;
+
+    // Put back any memory that was deduplicated, so everything that reads
+    // it sees the snippits
+    output.inline_memory();
+
+    Ok(output)
+}
+
+/// Write the scripts for other tools (Ghidra, radare2) that the user asked for
+fn emit_scripts(r: &MandrakeOutput, ghidra: &Option<String>, r2: &Option<String>, gdb: &Option<String>) -> SimpleResult<()> {
+    if let Some(path) = ghidra {
+        std::fs::write(path, ghidra_script(r))
+            .map_err(|e| SimpleError::new(format!("Couldn't write the Ghidra script to {}: {}", path, e)))?;
+    }
+
+    if let Some(path) = r2 {
+        std::fs::write(path, radare2_script(r))
+            .map_err(|e| SimpleError::new(format!("Couldn't write the radare2 script to {}: {}", path, e)))?;
+    }
+
+    if let Some(path) = gdb {
+        // GDB needs the standard in as a file to redirect from
+        let stdin_path = match &r.stdin {
+            Some(stdin) => {
+                let stdin_path = format!("{}.stdin", path);
+                let data = hex::decode(stdin)
+                    .map_err(|e| SimpleError::new(format!("Couldn't decode the saved standard in: {}", e)))?;
+                std::fs::write(&stdin_path, data)
+                    .map_err(|e| SimpleError::new(format!("Couldn't write the standard in to {}: {}", stdin_path, e)))?;
+
+                Some(stdin_path)
+            },
+            None => None,
+        };
+
+        std::fs::write(path, gdb_script(r, stdin_path.as_deref()))
+            .map_err(|e| SimpleError::new(format!("Couldn't write the GDB script to {}: {}", path, e)))?;
+    }
+
+    Ok(())
+}
+
+/// Write the output in each of the --also-format formats.
+fn write_extra_outputs(r: &MandrakeOutput, extras: &[ExtraOutput], options: &FormatOptions) -> SimpleResult<()> {
+    for extra in extras {
+        let output = r.to_format(&extra.format, options)?;
+        match &extra.path {
+            Some(path) => std::fs::write(path, output)
+                .map_err(|e| SimpleError::new(format!("Couldn't write the {} output to {}: {}", extra.format, path, e)))?,
+            None => std::io::stdout().write_all(&output)
+                .map_err(|e| SimpleError::new(format!("Couldn't write the {} output: {}", extra.format, e)))?,
+        }
+    }
+
+    Ok(())
+}
+
 /// Main intentially does not return an error.
 ///
 /// That means that we're sorta forced to handle all errors cleanly (or
 /// panic :) ).
 fn main() {
-    // Parse the commandline options
-    let args = Args::parse();
+    // Parse the commandline options (we keep the matches around so we can
+    // tell which options were explicitly set)
+    let matches = Args::into_app().get_matches();
+    let mut args = match Args::from_arg_matches(&matches) {
+        Ok(args) => args,
+        Err(e) => e.exit(),
+    };
+
+    // Warnings are shown by default
+    let level = match (args.quiet, args.verbose) {
+        (true, _) => log::LevelFilter::Error,
+        (_, 0)    => log::LevelFilter::Warn,
+        (_, 1)    => log::LevelFilter::Info,
+        (_, 2)    => log::LevelFilter::Debug,
+        _         => log::LevelFilter::Trace,
+    };
+    env_logger::Builder::new()
+        .filter_level(level)
+        .parse_default_env()
+        .init();
+
+    if let Some(config) = args.config.clone() {
+        if let Err(e) = apply_config_file(&mut args, &matches, &config) {
+            eprintln!("{}", e);
+            return;
+        }
+    }
+
+    // Make sure we know all the registers they asked for
+    if let Some(registers) = &args.registers {
+        if let Some(unknown) = registers.iter().find(|r| !REGISTERS.contains(&r.as_str())) {
+            eprintln!("Unknown register: {} (expected one of: {})", unknown, REGISTERS.join(", "));
+            return;
+        }
+    }
+
+    if args.sample_rate == 0 {
+        eprintln!("--sample-rate must be at least 1");
+        return;
+    }
+
+    if args.tail == Some(0) {
+        eprintln!("--tail must be at least 1");
+        return;
+    }
+
+    if args.repeat == 0 {
+        eprintln!("--repeat must be at least 1");
+        return;
+    }
+
+    // Raw pickle data is binary, so don't dump it to the terminal
+    if let OutputFormat::PICKLE = args.output_format {
+        if args.output_file.is_none() && !args.pickle_as_script {
+            eprintln!("The Pickle output format requires --output-file (or --pickle-as-script)");
+            return;
+        }
+    }
+    if !args.pickle_as_script && args.also_format.iter().any(|extra| matches!((&extra.format, &extra.path), (OutputFormat::PICKLE, None))) {
+        eprintln!("The Pickle output format needs a file with --also-format (eg, \"pickle=trace.pickle\")");
+        return;
+    }
+
+    // Load any extra constants on top of the ones we know about
+    let constants = match &args.constants_file {
+        Some(path) => match Constants::builtin().with_file(path) {
+            Ok(constants) => constants,
+            Err(e) => {
+                eprintln!("{}", e);
+                return;
+            }
+        },
+        None => Constants::builtin(),
+    };
+
+    // Load any extra syscalls on top of (or instead of) the built-in ones
+    if let Some(path) = &args.syscalls_csv {
+        if let Err(e) = load_syscalls_csv(Path::new(path)) {
+            eprintln!("{}", e);
+            return;
+        }
+    }
+
+    // On Ctrl-C, stop the analysis at the next step and still print what we
+    // collected (rather than dying and leaving a traced process behind)
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let handler_flag = interrupted.clone();
+    if let Err(e) = ctrlc::set_handler(move || handler_flag.store(true, Ordering::SeqCst)) {
+        eprintln!("Couldn't install a Ctrl-C handler: {}", e);
+    }
 
     // Create an instance of Mandrake with the configurations
     let mandrake = Mandrake::new(
@@ -139,59 +1091,132 @@ fn main() {
         args.ignore_stdout,
         args.ignore_stderr,
         args.follow_exec_syscalls,
-    );
+    )
+    .with_analysis_window(args.analysis_window)
+    .with_syscall_snippit_length(args.syscall_snippit_length)
+    .with_max_string_length(args.max_string_length)
+    .with_exec_arg_length(args.exec_arg_length)
+    .with_max_trace_bytes(args.max_trace_bytes)
+    .with_rip_snippit_length(args.rip_snippit_length)
+    .with_data_snippit_length(args.data_snippit_length)
+    .with_stack_snippit_length(args.stack_snippit_length)
+    .with_input_hex_limit(args.input_hex_limit)
+    .with_stop_at_syscall(args.stop_at_syscall)
+    .with_deliver_signals(args.deliver_signal)
+    .with_fatal_signals(args.fatal_signal)
+    .with_signal_injections(args.inject_signal)
+    .with_skip_syscall_detail(args.skip_syscall_detail)
+    .with_proc_status(args.proc_status)
+    .with_trace_detail(args.trace_detail)
+    .with_analyze_mode(args.analyze)
+    .with_registers(args.registers)
+    .with_capture_envp(args.capture_envp)
+    .with_disable_aslr(args.disable_aslr)
+    .with_deterministic(args.deterministic)
+    .with_keep_env(args.keep_env)
+    .with_no_memory(args.no_memory)
+    .with_full_coverage(!args.no_full_coverage)
+    .with_show_gaps(args.show_gaps)
+    .with_bad_bytes(args.bad_bytes.concat())
+    .with_int3_stops(args.int3_stops)
+    .with_dedup_memory(args.dedup_memory)
+    .with_delta_history(args.delta_history)
+    .with_collapse_loops(args.collapse_loops)
+    .with_sample_rate(args.sample_rate)
+    .with_only_mnemonics(args.only_mnemonics)
+    .with_tail(args.tail)
+    .with_capture_writes(args.capture_writes)
+    .with_resolve_imports(args.resolve_imports)
+    .with_region_filter(Some(RegionFilter::new(args.allow_region, args.deny_region)))
+    .with_debug_registers(args.debug_regs)
+    .with_memory_entropy(args.memory_entropy)
+    .with_crash_dump(args.crash_dump.map(PathBuf::from))
+    .with_dump_regions(args.dump_region)
+    .with_dump_dir(args.dump_dir.map(PathBuf::from))
+    .with_hold_on_crash(args.hold_on_crash)
+    .with_hold_timeout((args.hold_timeout > 0).then(|| Duration::from_secs(args.hold_timeout)))
+    .with_cpu_time_limit(args.cpu_time_limit.map(Duration::from_millis))
+    .with_progress(args.progress)
+    .with_time_breakdown(args.time_breakdown)
+    .with_loop_detection(args.detect_loops.then_some(args.loop_repeats))
+    .with_upcoming_instructions(args.upcoming_instructions)
+    .with_deref_depth(args.deref_depth)
+    .with_phases(args.phase)
+    .with_constants(constants)
+    .with_interrupt_flag(interrupted);
+
+    let format_options = FormatOptions {
+        pickle_protocol: args.pickle_protocol,
+        pickle_as_script: args.pickle_as_script,
+    };
+
+    // For --fail-on-bad-bytes, whether the code had any
+    let mut found_bad_bytes = false;
 
     // Check which subcommand they ran
-    let result = match args.action {
-        Action::Code(code_args) => {
-            match hex::decode(code_args.code) {
-                Ok(code) => mandrake.analyze_code(code, &Path::new(&code_args.harness), code_args.show_everything),
-                Err(e) => Err(SimpleError::new(format!("Could not decode hex: {}", e))),
+    let result = match &args.action {
+        Action::Analyze(target) => analyze_repeatedly(&mandrake, target, args.repeat)
+            .inspect(|r| found_bad_bytes = !r.badchar_report.is_empty())
+            .and_then(|r| emit_scripts(&r, &args.emit_ghidra, &args.emit_r2, &args.emit_gdb).map(|_| r))
+            .and_then(|r| write_extra_outputs(&r, &args.also_format, &format_options).map(|_| r))
+            .and_then(|r| r.to_format(&args.output_format, &format_options)),
+        Action::Replay(replay) => load_output(&replay.file)
+            .and_then(|r| emit_scripts(&r, &args.emit_ghidra, &args.emit_r2, &args.emit_gdb).map(|_| r))
+            .and_then(|r| write_extra_outputs(&r, &args.also_format, &format_options).map(|_| r))
+            .and_then(|r| r.to_format(&args.output_format, &format_options)),
+        Action::Bench(bench) => {
+            if let Err(e) = run_bench(&mandrake, bench) {
+                eprintln!("Benchmark failed: {}", e);
             }
+            return;
         },
-        Action::Elf(elf_args) => {
-            mandrake.analyze_elf(&Path::new(&elf_args.elf), elf_args.stdin_data, elf_args.args, &elf_args.visibility_configuration)
+        Action::Verify(verify) => {
+            match run_verify(&mandrake, verify) {
+                Ok(None) => println!("The output matches {}", verify.expected),
+                Ok(Some(difference)) => {
+                    eprintln!("The output doesn't match {}: {}", verify.expected, difference);
+                    std::process::exit(1);
+                },
+                Err(e) => {
+                    eprintln!("Verification failed: {}", e);
+                    std::process::exit(1);
+                },
+            }
+            return;
+        },
+        Action::Gadgets(gadgets) => run_gadgets(gadgets, &args.output_format),
+        Action::Selftest(selftest) => {
+            if !run_selftest(selftest) {
+                std::process::exit(1);
+            }
+            return;
         },
     };
 
     // Handle errors somewhat more cleanly than just bailing
-    match result {
-        Ok(r)  => match args.output_format {
-            OutputFormat::JSON   => println!("{}", serde_json::to_string_pretty(&r).unwrap()),
-            OutputFormat::YAML   => println!("{}", serde_yaml::to_string(&r).unwrap()),
-            OutputFormat::PICKLE => {
-                println!("import base64");
-                println!("import pickle");
-                println!();
-                println!("pickle.loads(base64.b64decode(\"{}\"))", base64::encode(serde_pickle::to_vec(&r, Default::default()).unwrap()));
-            },
-            OutputFormat::PLAINTEXT => {
-                for entry in r.history {
-                    match entry.get("rip") {
-                        Some(entry) => {
-                            println!("{}", entry);
-                        },
-                        None => {
-                            eprintln!("Missing rip in entry");
-                        },
-                    }
-                }
-
-                if let Some(stdout) = r.stdout {
-                    if stdout != "" {
-                        println!();
-                        println!("Stdout: {}", stdout);
-                    }
-                }
-
-                if let Some(stderr) = r.stderr {
-                    if stderr != "" {
-                        println!();
-                        println!("stderr: {}", stderr);
-                    }
-                }
-            },
+    let output = match result {
+        Ok(output) => output,
+        Err(e) => {
+            eprintln!("Execution failed: {}", e);
+            return;
+        }
+    };
+
+    match args.output_file {
+        Some(output_file) => {
+            if let Err(e) = std::fs::write(&output_file, output) {
+                eprintln!("Couldn't write to output file {}: {}", output_file, e);
+            }
+        },
+        None => {
+            if let Err(e) = std::io::stdout().write_all(&output) {
+                eprintln!("Couldn't write output: {}", e);
+            }
         },
-        Err(e) => eprintln!("Execution failed: {}", e.to_string()),
     };
+
+    if args.fail_on_bad_bytes && found_bad_bytes {
+        eprintln!("The code has bad bytes (see \"badchar_report\")");
+        std::process::exit(1);
+    }
 }
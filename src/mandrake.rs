@@ -1,80 +1,1846 @@
+use std::fmt;
+use std::fs::{self, File};
 use std::io::prelude::*;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::process::CommandExt;
 use std::process::{Command, Stdio, Child};
 use std::collections::HashMap;
-use std::path::Path;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
-use nix::sys::ptrace::{getregs, step, cont, kill};
-use nix::sys::signal::Signal;
-use nix::sys::wait::{wait, WaitStatus};
-use nix::unistd::Pid;
+use iced_x86::{FlowControl, OpKind, Register};
+use log::{debug, info, trace, warn};
+use nix::libc::user_regs_struct;
+use nix::sys::personality::{self, Persona};
+use nix::sys::ptrace::{self, detach, getregs, getsiginfo, step, cont, kill, setoptions, Event, Options};
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::sys::signal::{kill as send_signal, sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use nix::errno::Errno;
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
+use nix::sys::wait::{wait, waitpid, WaitStatus};
+use nix::unistd::{close, dup2, isatty, pipe2, sysconf, Pid, SysconfVar};
 
 use simple_error::{bail, SimpleResult, SimpleError};
 use spawn_ptrace::CommandPtraceSpawn;
 
-use crate::analyzed_value::AnalyzedValue;
-use crate::mandrake_output::MandrakeOutput;
-use crate::visibility_configuration::VisibilityConfiguration;
+use crate::analyzed_value::{shannon_entropy, AnalyzedValue, DEFAULT_ANALYSIS_WINDOW, DEFAULT_SYSCALL_SNIPPIT_LENGTH, MAX_INSTRUCTION_LENGTH};
+use crate::constants::Constants;
+use crate::coverage::{CoverageRecorder, ExecutedBytes};
+use crate::crash_dump::write_crash_dump;
+use crate::debug_registers::{read_debug_registers, decode_dr7, DEBUG_REGISTERS};
+use crate::memory_cache::MemoryCache;
+use crate::memory_map::{MemoryMap, RegionFilter};
+use crate::mandrake_output::{AnalyzedInput, BadByte, DumpedMemory, ExecveArguments, ExitReason, HistoryEntry, InputData, InjectedSignal, MandrakeOutput, OutputConfig, ShedDetail, SignalInfo, SyscallEvent, TimeBreakdown};
+use crate::symbols::Imports;
+use crate::syscalls::{format_syscall_return, syscalls_csv_source, MemoryMapping, Syscall, SyscallAbi};
+use crate::visibility_configuration::{VisibilityConfiguration, HARNESS_CODE_ADDRESS};
+
+/// The registers analyzed at a stop, and the syscall arguments that were
+/// decoded along with them (see [`Mandrake::get_registers_from_pid`])
+type StopRegisters = (HashMap<String, AnalyzedValue>, Option<Vec<String>>);
+
+/// How much analysis to do at each step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceDetail {
+    /// Analyze every register (memory, strings, instructions, syscalls)
+    Full,
+
+    /// Save the register values and the instruction at rip, but don't read
+    /// any other memory
+    Registers,
+
+    /// Only save rip and the instruction there
+    Minimal,
+}
+
+impl FromStr for TraceDetail {
+    type Err = SimpleError;
+
+    fn from_str(input: &str) -> Result<TraceDetail, Self::Err> {
+        match &input.to_lowercase()[..] {
+            "full"      => Ok(TraceDetail::Full),
+            "registers" => Ok(TraceDetail::Registers),
+            "minimal"   => Ok(TraceDetail::Minimal),
+
+            _           => bail!("Unknown trace detail: {}", input),
+        }
+    }
+}
+
+impl fmt::Display for TraceDetail {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Full      => write!(f, "full"),
+            Self::Registers => write!(f, "registers"),
+            Self::Minimal   => write!(f, "minimal"),
+        }
+    }
+}
+
+/// Which registers get a full analysis (memory, strings, etc) at each step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnalyzeMode {
+    /// Every register
+    All,
+
+    /// Only the registers that the instruction at rip uses (the others are
+    /// saved as bare values)
+    Smart,
+}
+
+impl FromStr for AnalyzeMode {
+    type Err = SimpleError;
+
+    fn from_str(input: &str) -> Result<AnalyzeMode, Self::Err> {
+        match &input.to_lowercase()[..] {
+            "all"   => Ok(AnalyzeMode::All),
+            "smart" => Ok(AnalyzeMode::Smart),
+
+            _       => bail!("Unknown analyze mode: {}", input),
+        }
+    }
+}
+
+impl fmt::Display for AnalyzeMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::All   => write!(f, "all"),
+            Self::Smart => write!(f, "smart"),
+        }
+    }
+}
+
+/// A named phase of execution, which starts whenever `address` executes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Phase {
+    pub name: String,
+    pub address: u64,
+}
+
+impl FromStr for Phase {
+    type Err = SimpleError;
+
+    /// Parse `NAME@ADDR` (the address can be hex with a `0x` prefix)
+    fn from_str(input: &str) -> Result<Phase, Self::Err> {
+        let (name, address) = match input.rsplit_once('@') {
+            Some((name, address)) if !name.is_empty() => (name, address),
+            _ => bail!("Phases must look like NAME@ADDRESS: {}", input),
+        };
+
+        let address = match address.strip_prefix("0x").or_else(|| address.strip_prefix("0X")) {
+            Some(hex) => u64::from_str_radix(hex, 16),
+            None      => address.parse(),
+        }.map_err(|e| SimpleError::new(format!("Invalid address for phase {}: {}", name, e)))?;
+
+        Ok(Phase { name: name.to_string(), address })
+    }
+}
+
+/// Parse a signal name for `--deliver-signal` / `--fatal-signal`, with or
+/// without the `SIG` prefix (eg, "SIGSEGV" or "segv").
+pub fn parse_signal(input: &str) -> SimpleResult<Signal> {
+    let name = input.trim().to_uppercase();
+    let name = match name.starts_with("SIG") {
+        true  => name,
+        false => format!("SIG{}", name),
+    };
+
+    match Signal::from_str(&name) {
+        // Single-stepping and breakpoints are SIGTRAPs, so we always need
+        // them for ourselves
+        Ok(Signal::SIGTRAP) => bail!("SIGTRAP is used for tracing, so it can't be delivered or made fatal"),
+        Ok(signal) => Ok(signal),
+        Err(_) => bail!("Unknown signal: {}", input),
+    }
+}
+
+/// A signal to send the process when it reaches an instruction (see
+/// `--inject-signal`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SignalInjection {
+    pub signal: Signal,
+
+    /// The index of the instruction it's sent before (the same as
+    /// `instructions_executed` at that point)
+    pub index: usize,
+}
+
+impl FromStr for SignalInjection {
+    type Err = SimpleError;
+
+    /// Parse `SIGNAL@INDEX` (eg, `SIGUSR1@100`)
+    fn from_str(input: &str) -> Result<SignalInjection, Self::Err> {
+        let (signal, index) = match input.rsplit_once('@') {
+            Some((signal, index)) => (signal, index),
+            None => bail!("Signal injections must look like SIGNAL@INDEX: {}", input),
+        };
+
+        let index = index.parse()
+            .map_err(|e| SimpleError::new(format!("Invalid instruction index for {}: {}", signal, e)))?;
+
+        Ok(SignalInjection { signal: parse_signal(signal)?, index })
+    }
+}
+
+impl fmt::Display for SignalInjection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}@{}", self.signal, self.index)
+    }
+}
+
+/// Parse a number for `--dump-region`, as hex with a `0x` prefix or decimal
+fn parse_number(input: &str) -> SimpleResult<u64> {
+    match input.strip_prefix("0x").or_else(|| input.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None      => input.parse(),
+    }.map_err(|e| SimpleError::new(format!("Invalid number {}: {}", input, e)))
+}
+
+/// Where a `--dump-region` starts: a fixed address, or wherever a register
+/// points (plus or minus an offset) when the trace ends.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DumpBase {
+    Address(u64),
+    Register { name: String, offset: i64 },
+}
+
+/// Memory to save when the trace ends (see `--dump-region`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DumpRegion {
+    pub base: DumpBase,
+    pub length: usize,
+}
+
+impl DumpRegion {
+    /// Work out where the region starts, from the registers at the end (if
+    /// it depends on one, and we could read them)
+    fn address(&self, regs: Option<&user_regs_struct>) -> Option<u64> {
+        match &self.base {
+            DumpBase::Address(address) => Some(*address),
+            DumpBase::Register { name, offset } => register_value(regs?, name).map(|value| value.wrapping_add_signed(*offset)),
+        }
+    }
+}
+
+impl FromStr for DumpRegion {
+    type Err = SimpleError;
+
+    /// Parse `ADDRESS:LENGTH` (eg, `0x13370000:256`) or `REGISTER:LENGTH`,
+    /// where the register can have an offset (eg, `rsp-16:64`)
+    fn from_str(input: &str) -> Result<DumpRegion, Self::Err> {
+        let (base, length) = match input.rsplit_once(':') {
+            Some((base, length)) if !base.is_empty() => (base.trim(), length.trim()),
+            _ => bail!("Dump regions must look like ADDRESS:LENGTH or REGISTER:LENGTH: {}", input),
+        };
+
+        let length = parse_number(length)?;
+        if length == 0 {
+            bail!("Dump regions can't be empty: {}", input);
+        }
+        if length > MAX_DUMP_LENGTH as u64 {
+            bail!("Dump regions can be at most {} bytes (0x{:x}): {}", MAX_DUMP_LENGTH, MAX_DUMP_LENGTH, input);
+        }
+        let length = length as usize;
+
+        if base.starts_with(|c: char| c.is_ascii_digit()) {
+            return Ok(DumpRegion { base: DumpBase::Address(parse_number(base)?), length });
+        }
+
+        let (name, offset) = match base.find(['+', '-']) {
+            Some(i) => {
+                let offset = parse_number(&base[i + 1..])? as i64;
+                (&base[..i], if base[i..].starts_with('-') { -offset } else { offset })
+            },
+            None => (base, 0),
+        };
+
+        let name = name.to_lowercase();
+        if !REGISTERS.contains(&name.as_str()) {
+            bail!("Unknown register in dump region {}: {} (try one of {})", input, name, REGISTERS.join(", "));
+        }
+
+        Ok(DumpRegion { base: DumpBase::Register { name, offset }, length })
+    }
+}
+
+impl fmt::Display for DumpRegion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.base {
+            DumpBase::Address(address)                       => write!(f, "0x{:x}:{}", address, self.length),
+            DumpBase::Register { name, offset: 0 }           => write!(f, "{}:{}", name, self.length),
+            DumpBase::Register { name, offset } if *offset < 0 => write!(f, "{}-0x{:x}:{}", name, offset.unsigned_abs(), self.length),
+            DumpBase::Register { name, offset }              => write!(f, "{}+0x{:x}:{}", name, offset, self.length),
+        }
+    }
+}
+
+/// Get one of the [`REGISTERS`] by name
+fn register_value(regs: &user_regs_struct, name: &str) -> Option<u64> {
+    Some(match name {
+        "rip" => regs.rip,
+        "rax" => regs.rax,
+        "rbx" => regs.rbx,
+        "rcx" => regs.rcx,
+        "rdx" => regs.rdx,
+        "rsi" => regs.rsi,
+        "rdi" => regs.rdi,
+        "rbp" => regs.rbp,
+        "rsp" => regs.rsp,
+        "r8"  => regs.r8,
+        "r9"  => regs.r9,
+        "r10" => regs.r10,
+        "r11" => regs.r11,
+        "r12" => regs.r12,
+        "r13" => regs.r13,
+        "r14" => regs.r14,
+        "r15" => regs.r15,
+        _     => return None,
+    })
+}
+
+/// A file to open and hand to an ELF as a file descriptor other than stdin
+/// (eg, for a program that reads its input from fd 3).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FdMapping {
+    pub fd: RawFd,
+    pub path: PathBuf,
+}
+
+impl FromStr for FdMapping {
+    type Err = SimpleError;
+
+    /// Parse `FD=PATH` (eg, `3=input.bin`)
+    fn from_str(input: &str) -> Result<FdMapping, Self::Err> {
+        let (fd, path) = match input.split_once('=') {
+            Some((fd, path)) if !path.is_empty() => (fd, path),
+            _ => bail!("File descriptors must look like FD=PATH: {}", input),
+        };
+
+        let fd: RawFd = fd.parse().map_err(|e| SimpleError::new(format!("Invalid file descriptor {}: {}", fd, e)))?;
+        if fd < 3 {
+            bail!("File descriptor {} is stdin, stdout, or stderr, which Mandrake sets up itself (use --stdin-data for stdin)", fd);
+        }
+
+        Ok(FdMapping { fd, path: PathBuf::from(path) })
+    }
+}
 
 /// Represents the mandrake configuration.
 #[derive(Debug)]
 pub struct Mandrake {
     snippit_length:          usize,
+    rip_snippit_length:      Option<usize>,
+    data_snippit_length:     Option<usize>,
+    stack_snippit_length:    Option<usize>,
+    analysis_window:         Option<usize>,
+    syscall_snippit_length:  usize,
     minimum_viable_string:   usize,
+    max_string_length:       usize,
+    exec_arg_length:         usize,
+    max_trace_bytes:         usize,
+    input_hex_limit:         usize,
     max_logged_instructions: Option<usize>,
+    cpu_time_limit:          Option<Duration>,
+    progress:                bool,
+    time_breakdown:          bool,
+    loop_repeats:            Option<usize>,
+    deliver_signals:         Vec<Signal>,
+    fatal_signals:           Vec<Signal>,
+    signal_injections:       Vec<SignalInjection>,
     capture_stdout:          bool,
     capture_stderr:          bool,
     follow_exec:             bool,
+    stop_at_syscall:         Option<String>,
+    skip_syscall_detail:     Vec<String>,
+    proc_status:             bool,
+    trace_detail:            TraceDetail,
+    registers:               Option<Vec<String>>,
+    capture_envp:            bool,
+    disable_aslr:            bool,
+    deterministic:           bool,
+    keep_env:                Vec<String>,
+    bad_bytes:               Vec<u8>,
+    no_memory:               bool,
+    full_coverage:           bool,
+    show_gaps:               bool,
+    int3_stops:              bool,
+    dedup_memory:            bool,
+    delta_history:           bool,
+    collapse_loops:          bool,
+    sample_rate:             usize,
+    only_mnemonics:          Vec<String>,
+    tail:                    Option<usize>,
+    capture_writes:          bool,
+    region_filter:           Option<RegionFilter>,
+    resolve_imports:         bool,
+    debug_registers:         bool,
+    memory_entropy:          bool,
+    crash_dump:              Option<PathBuf>,
+    dump_regions:            Vec<DumpRegion>,
+    dump_dir:                Option<PathBuf>,
+    hold_on_crash:           bool,
+    hold_timeout:            Option<Duration>,
+    analyze_mode:            AnalyzeMode,
+    upcoming_instructions:   usize,
+    deref_depth:             usize,
+    phases:                  Vec<Phase>,
+    constants:               Constants,
+    interrupted:             Option<Arc<AtomicBool>>,
+
+    // How many register values were fully analyzed in the current run (see
+    // `MandrakeOutput::deep_analyses`)
+    deep_analyses:           AtomicUsize,
+}
+
+// The most data we'll save from a single write() with --capture-writes
+const MAX_WRITE_CAPTURE: usize = 4096;
+
+/// The registers that Mandrake knows how to analyze
+pub const REGISTERS: &[&str] = &[
+    "rip", "rax", "rbx", "rcx", "rdx", "rsi", "rdi", "rbp", "rsp",
+    "r8", "r9", "r10", "r11", "r12", "r13", "r14", "r15",
+];
+
+// Signals that mean the code crashed, which is when we save a crash dump
+const CRASH_SIGNALS: &[Signal] = &[Signal::SIGSEGV, Signal::SIGBUS, Signal::SIGILL, Signal::SIGFPE, Signal::SIGABRT];
+
+/// The fields from /proc/<pid>/status that we save when --proc-status is on
+const PROC_STATUS_FIELDS: &[&str] = &[
+    "State", "VmPeak", "VmSize", "VmHWM", "VmRSS", "Threads",
+    "SigQ", "SigPnd", "ShdPnd", "SigBlk", "SigIgn", "SigCgt",
+    "voluntary_ctxt_switches", "nonvoluntary_ctxt_switches",
+];
+
+/// Reads the interesting fields from /proc/<pid>/status.
+///
+/// This has to happen while the process is still alive (ie, before it's
+/// killed or reaped), otherwise the file is gone.
+fn read_proc_status(pid: Pid) -> Option<HashMap<String, String>> {
+    let status = std::fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+
+    Some(status.lines().filter_map(|line| {
+        let (key, value) = line.split_once(':')?;
+
+        if PROC_STATUS_FIELDS.contains(&key) {
+            Some((key.to_string(), value.trim().to_string()))
+        } else {
+            None
+        }
+    }).collect())
+}
+
+/// The harness writes its own messages (like a usage error) to this fd, so
+/// they're kept out of the code's stderr - see `harness_stderr` in the output
+pub const HARNESS_DIAGNOSTICS_FD: RawFd = 3;
+
+/// In deterministic mode, the harness runs with glibc's malloc cache off -
+/// otherwise every chunk it frees is tagged with a random key, which the
+/// code can see on the heap
+const HARNESS_DETERMINISTIC_TUNABLES: &str = "glibc.malloc.tcache_count=0";
+
+/// Whether a process has a handler for a signal (from the SigCgt mask in
+/// /proc/<pid>/status), or `None` if we can't tell.
+fn catches_signal(pid: Pid, signal: Signal) -> Option<bool> {
+    let status = std::fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+    let caught = status.lines().find_map(|line| line.strip_prefix("SigCgt:"))?;
+    let caught = u64::from_str_radix(caught.trim(), 16).ok()?;
+
+    Some(caught & (1 << (signal as i32 - 1)) != 0)
+}
+
+/// By default, don't read more than this much of any single argv / envp string
+pub const DEFAULT_EXEC_ARG_LENGTH: usize = 4096;
+
+/// By default, start leaving things out of the history once it's using about
+/// this much memory (256MB)
+pub const DEFAULT_MAX_TRACE_BYTES: usize = 256 * 1024 * 1024;
+
+/// By default, only include the code / stdin in the output's `input` as hex
+/// when it's no bigger than this (64KB) - past that, it's just the hash
+pub const DEFAULT_INPUT_HEX_LIMIT: usize = 64 * 1024;
+
+/// The most memory a single `--dump-region` can save (64MB) - the whole
+/// region is read into memory, so a typo'd length shouldn't be able to ask
+/// for more than the machine has
+pub const MAX_DUMP_LENGTH: usize = 64 * 1024 * 1024;
+
+/// By default, stop holding a crashed process (see `--hold-on-crash`) after
+/// this many seconds, so unattended runs don't hang forever
+pub const DEFAULT_HOLD_TIMEOUT: u64 = 300;
+
+/// Reads the NULL-terminated array of `pointer_size`-byte string pointers at
+/// `addr` (each string cut off at `max_length`), returning the strings and
+/// the address just past the NULL.
+fn read_string_array(pid: Pid, mut addr: u64, pointer_size: usize, max_length: usize) -> SimpleResult<(Vec<String>, u64)> {
+    let mut out: Vec<String> = vec![];
+
+    loop {
+        let ptr = AnalyzedValue::get_memory_as_u64(pid, addr)
+            .ok_or_else(|| SimpleError::new(format!("Couldn't read string pointer at 0x{:x}", addr)))?;
+        let ptr = match pointer_size {
+            4 => ptr & 0xffffffff,
+            _ => ptr,
+        };
+        addr += pointer_size as u64;
+
+        if ptr == 0 {
+            return Ok((out, addr));
+        }
+
+        out.push(AnalyzedValue::get_memory_as_c_string(pid, ptr, max_length)
+            .ok_or_else(|| SimpleError::new(format!("Couldn't read string at 0x{:x}", ptr)))?);
+    }
+}
+
+/// Reads argv and envp from the stack, the way the kernel set it up.
+///
+/// This only works right after execve, when rsp points at argc, followed by
+/// the argv pointers, a NULL, the envp pointers, and another NULL.
+fn read_initial_stack(pid: Pid, max_length: usize) -> SimpleResult<(Vec<String>, Vec<String>)> {
+    let rsp = getregs(pid)
+        .map_err(|e| trace_error("Couldn't read registers", e))?
+        .rsp;
+
+    let (argv, envp_start) = read_string_array(pid, rsp + 8, 8, max_length)?;
+    let (envp, _) = read_string_array(pid, envp_start, 8, max_length)?;
+
+    Ok((argv, envp))
+}
+
+/// The auxiliary vector entry that points at the kernel's 16 random bytes
+/// (which libc uses for the stack canary and pointer guard)
+const AT_RANDOM: u64 = 25;
+
+/// Zeroes the random bytes the kernel gave a process that was just executed,
+/// so the stack canary (and anything else seeded from them) is the same
+/// every run.
+fn clear_random_bytes(pid: Pid) -> SimpleResult<()> {
+    let auxv = std::fs::read(format!("/proc/{}/auxv", pid))
+        .map_err(|e| SimpleError::new(format!("Couldn't read the auxiliary vector: {}", e)))?;
+
+    let address = auxv.chunks_exact(16)
+        .map(|entry| (u64::from_le_bytes(entry[0..8].try_into().unwrap()), u64::from_le_bytes(entry[8..16].try_into().unwrap())))
+        .find(|(key, _)| *key == AT_RANDOM)
+        .map(|(_, value)| value)
+        .ok_or_else(|| SimpleError::new("The process has no AT_RANDOM bytes"))?;
+
+    for offset in [0, 8] {
+        // Safe because it writes a word into the (stopped) child, not us
+        unsafe { ptrace::write(pid, (address + offset) as ptrace::AddressType, std::ptr::null_mut()) }
+            .map_err(|e| trace_error("Couldn't clear the AT_RANDOM bytes", e))?;
+    }
+
+    Ok(())
 }
 
-const EXECVE_NUM: u64 = 59;
+/// Reads everything an `execve` is about to run with (the filename, and all
+/// of argv and envp), while it's still in the process's memory.
+fn read_execve_arguments(pid: Pid, abi: SyscallAbi, regs: &user_regs_struct, max_length: usize) -> SimpleResult<ExecveArguments> {
+    let [filename, argv, envp, ..] = abi.arguments(regs);
+
+    // argv and envp are allowed to be NULL (Linux treats that as empty)
+    let read_array = |addr: u64| match addr {
+        0 => Ok(vec![]),
+        _ => read_string_array(pid, addr, abi.pointer_size(), max_length).map(|(strings, _)| strings),
+    };
+
+    Ok(ExecveArguments {
+        filename: AnalyzedValue::get_memory_as_c_string(pid, filename, max_length)
+            .ok_or_else(|| SimpleError::new(format!("Couldn't read string at 0x{:x}", filename)))?,
+        argv: read_array(argv)?,
+        envp: read_array(envp)?,
+    })
+}
+
+/// Performs a wait() then cont().
+///
+/// Waits for the current operation to complete (which is a step), then
+/// continues execution. If the process ends instead of finishing the step
+/// (say, it was killed), that status is returned so the caller can handle
+/// it.
+fn resume_execution(pid: Pid) -> SimpleResult<Option<WaitStatus>> {
+    debug!("Resuming process {} until the next breakpoint", pid);
+
+    match wait() {
+        Ok(status @ WaitStatus::Exited(_, _)) | Ok(status @ WaitStatus::Signaled(_, _, _)) => return Ok(Some(status)),
+        Ok(_) => (),
+        Err(e) => return Err(trace_error("Couldn't step over breakpoint", e)),
+    };
+
+    ignore_missing_process(cont(pid, None))
+        .map_err(|e| trace_error("Couldn't resume execution after breakpoint", e))?;
+
+    Ok(None)
+}
+
+/// A ptrace call fails with ESRCH when the process was killed out from under
+/// us (for example, by the OOM killer). That's not our error - the next
+/// wait() will tell us what happened to it.
+fn ignore_missing_process(result: nix::Result<()>) -> nix::Result<()> {
+    match result {
+        Err(nix::Error::Sys(Errno::ESRCH)) => Ok(()),
+        result => result,
+    }
+}
+
+/// Set when we get SIGUSR1 while holding a crashed process
+static HOLD_RELEASED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn release_hold(_: nix::libc::c_int) {
+    HOLD_RELEASED.store(true, Ordering::SeqCst);
+}
+
+/// Waits for the user to let go of a held process: Enter on a terminal, or
+/// SIGUSR1 otherwise (or Ctrl-C, either way). Returns false if it timed out
+/// instead.
+fn wait_for_release(terminal: bool, timeout: Option<Duration>, interrupted: Option<&AtomicBool>) -> SimpleResult<bool> {
+    let started = Instant::now();
+
+    let previous = match terminal {
+        true  => None,
+        false => {
+            HOLD_RELEASED.store(false, Ordering::SeqCst);
+            let action = SigAction::new(SigHandler::Handler(release_hold), SaFlags::empty(), SigSet::empty());
+            // Safe because the handler only sets an atomic
+            Some(unsafe { sigaction(Signal::SIGUSR1, &action) }
+                .map_err(|e| SimpleError::new(format!("Couldn't catch SIGUSR1: {}", e)))?)
+        },
+    };
+
+    let released = loop {
+        if interrupted.is_some_and(|interrupted| interrupted.load(Ordering::SeqCst)) {
+            break true;
+        }
+
+        if timeout.is_some_and(|timeout| started.elapsed() >= timeout) {
+            break false;
+        }
+
+        if terminal {
+            let mut fds = [PollFd::new(0, PollFlags::POLLIN)];
+            match poll(&mut fds, HOLD_POLL_INTERVAL.as_millis() as i32) {
+                Ok(0) | Err(nix::Error::Sys(Errno::EINTR)) => (),
+                Ok(_) => {
+                    let mut line = String::new();
+                    let _ = std::io::stdin().read_line(&mut line);
+                    break true;
+                },
+                Err(e) => bail!("Couldn't read stdin: {}", e),
+            }
+        } else if HOLD_RELEASED.load(Ordering::SeqCst) {
+            break true;
+        } else {
+            std::thread::sleep(HOLD_POLL_INTERVAL);
+        }
+    };
+
+    if let Some(previous) = previous {
+        // Safe because it's putting back whatever was there before
+        let _ = unsafe { sigaction(Signal::SIGUSR1, &previous) };
+    }
+
+    Ok(released)
+}
+
+/// How many instructions to run between checks of the CPU time (reading it
+/// for every instruction would be slow)
+pub const CPU_TIME_CHECK_INTERVAL: usize = 1000;
+
+/// How often to update the `--progress` line
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often to check whether a held process has been let go
+const HOLD_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Show how the trace is going on one (overwritten) line of stderr.
+fn print_progress(pid: Pid, memory_map: &mut Option<MemoryMap>, executed: usize, logged: usize, rip: u64, elapsed: Duration) {
+    // The map is only read again when it might have changed, so it's fine to
+    // keep it for the trace to use too
+    if memory_map.is_none() {
+        *memory_map = MemoryMap::read(pid);
+    }
+
+    let location = match memory_map.as_ref().and_then(|map| map.module_offset(rip)) {
+        Some(module_offset) => format!("0x{:08x} ({})", rip, module_offset),
+        None => format!("0x{:08x}", rip),
+    };
+
+    let rate = executed as f64 / elapsed.as_secs_f64().max(0.001);
+
+    // \x1b[K clears whatever's left of a longer previous line
+    eprint!("\r{} executed, {} logged, {:.0}/s, rip {}, {:.1}s\x1b[K", executed, logged, rate, location, elapsed.as_secs_f64());
+}
+
+/// The most states to remember when looking for infinite loops - after that,
+/// they're forgotten and counted again (a stuck loop repeats quickly, so it
+/// still gets caught)
+const MAX_LOOP_STATES: usize = 100_000;
+
+/// Hash the parts of the state that a stuck loop repeats exactly
+fn loop_state(regs: &user_regs_struct) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    (regs.rip, regs.rax, regs.rbx, regs.rcx, regs.rdx, regs.rsi, regs.rdi, regs.rsp).hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Read how much CPU time (user + system) a process has used so far, from
+/// /proc/<pid>/stat.
+fn read_cpu_time(pid: Pid) -> Option<Duration> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+
+    // The name (the second field) can have spaces and parentheses in it, so
+    // start after the last `)` - that makes utime and stime (the 14th and
+    // 15th fields) the 12th and 13th
+    let mut fields = stat.rsplit_once(')')?.1.split_whitespace().skip(11);
+    let utime: u64 = fields.next()?.parse().ok()?;
+    let stime: u64 = fields.next()?.parse().ok()?;
+
+    let ticks_per_second = sysconf(SysconfVar::CLK_TCK).ok()??;
+    Some(Duration::from_millis((utime + stime) * 1000 / ticks_per_second as u64))
+}
+
+/// Read how many processes the OOM killer has killed since boot (from
+/// /proc/vmstat), so we can tell if it was the one that killed ours.
+fn read_oom_kill_count() -> Option<u64> {
+    let vmstat = std::fs::read_to_string("/proc/vmstat").ok()?;
+
+    vmstat.lines()
+        .find_map(|line| line.strip_prefix("oom_kill "))
+        .and_then(|count| count.trim().parse().ok())
+}
+
+/// If the instruction at rip jumps or calls through a GOT slot, say which
+/// imported function it's going to (eg, `-> memcpy@GLIBC_2.14`).
+fn annotate_import(code: &MemoryCache, imports: &Imports, rip: &mut AnalyzedValue) {
+    // Don't bother reading memory for anything that can't be a jump or call
+    match &rip.as_instruction {
+        Some(instruction) if instruction.contains("jmp") || instruction.contains("call") => (),
+        _ => return,
+    };
+
+    let decoded = match code.read(rip.value, MAX_INSTRUCTION_LENGTH).and_then(|(data, _)| AnalyzedValue::disassemble(&data, rip.value)) {
+        Some(decoded) => decoded,
+        None => return,
+    };
+
+    let indirect = matches!(decoded.flow_control(), FlowControl::IndirectBranch | FlowControl::IndirectCall);
+    if !indirect || decoded.op0_kind() != OpKind::Memory {
+        return;
+    }
+
+    // The PLT uses `jmp [rip+X]`, but older (non-PIE) code can use an
+    // absolute address
+    let slot = if decoded.is_ip_rel_memory_operand() {
+        decoded.ip_rel_memory_address()
+    } else if decoded.memory_base() == Register::None && decoded.memory_index() == Register::None {
+        decoded.memory_displacement64()
+    } else {
+        return;
+    };
+
+    if let Some(name) = imports.get(slot) {
+        rip.extra.get_or_insert_with(Vec::new).push(format!("-> {}", name));
+    }
+}
+
+/// Makes sure a traced process is dead and reaped, however the analysis ends
+/// (including when it fails part way through).
+struct TracedProcess {
+    pid: Pid,
+}
+
+impl Drop for TracedProcess {
+    fn drop(&mut self) {
+        // Clear the ptrace options, otherwise the process will stop at the
+        // exit event when we kill it (and never actually die)
+        let _ = setoptions(self.pid, Options::empty());
+
+        // Whatever situation we're in, we need to make sure the process is dead
+        // (We discard errors here, because we don't really care if it was already
+        // killed or failed to kill or whatever)
+        let _ = kill(self.pid);
+
+        // Reap it, so we don't leave a zombie behind (this fails harmlessly
+        // if it already exited and was reaped)
+        let _ = waitpid(self.pid, None);
+    }
+}
+
+/// Fill in the memory region for each value (and anything it points to).
+///
+/// The memory map is cached between calls, but if a readable address isn't
+/// in it (say, the stack grew), we read it again once. Checking if it's
+/// readable touches the memory, so that's skipped if `probe` is off.
+fn classify_regions(pid: Pid, memory_map: &mut Option<MemoryMap>, registers: &mut HashMap<String, AnalyzedValue>, probe: bool) {
+    if memory_map.is_none() {
+        *memory_map = MemoryMap::read(pid);
+    }
+    let mut refreshed = false;
+
+    // The debug registers are saved as bare values, so leave them alone
+    for (_, value) in registers.iter_mut().filter(|(name, _)| !DEBUG_REGISTERS.contains(&name.as_str())) {
+        value.for_each_mut(&mut |value| {
+            let mut region = memory_map.as_ref().map(|m| m.classify(value.value));
+
+            if probe && !refreshed && region.as_deref() == Some("unmapped") && AnalyzedValue::get_memory_as_u64(pid, value.value).is_some() {
+                *memory_map = MemoryMap::read(pid);
+                refreshed = true;
+                region = memory_map.as_ref().map(|m| m.classify(value.value));
+            }
+
+            value.region = region;
+        });
+    }
+}
+
+/// Describes a failed ptrace (or wait) call, keeping its errno - that's how
+/// we can tell a process that's gone (ESRCH) from ptrace being restricted
+/// (EPERM) or a bug on our end (EINVAL, EIO).
+fn trace_error(action: &str, e: nix::Error) -> SimpleError {
+    let errno = match e {
+        nix::Error::Sys(errno) => errno,
+        e => return SimpleError::new(format!("{}: {}", action, e)),
+    };
+
+    let hint = match errno {
+        Errno::EPERM => " - ptrace may be restricted, see /proc/sys/kernel/yama/ptrace_scope",
+        _ => "",
+    };
+
+    SimpleError::new(format!("{}: {} (errno {}: {:?}){}", action, errno.desc(), errno as i32, errno, hint))
+}
+
+/// Reads the details of the signal a process is stopped with (`None` if they
+/// can't be read).
+fn read_siginfo(pid: Pid, signal: Signal) -> Option<SignalInfo> {
+    let info = getsiginfo(pid).ok()?;
+
+    // The fault address is only filled in for signals caused by an
+    // instruction
+    let fault_address = match signal {
+        Signal::SIGSEGV | Signal::SIGBUS | Signal::SIGILL | Signal::SIGFPE => Some(unsafe { info.si_addr() } as u64),
+        _ => None,
+    };
+
+    Some(SignalInfo {
+        code: info.si_code,
+        fault_address,
+    })
+}
+
+/// Resumes a stopped process, either with a single step or by continuing,
+/// optionally delivering a signal to it.
+fn resume(pid: Pid, stepping: bool, signal: Option<Signal>) -> SimpleResult<()> {
+    match stepping {
+        true  => ignore_missing_process(step(pid, signal)).map_err(|e| trace_error("Couldn't step through code", e)),
+        false => ignore_missing_process(cont(pid, signal)).map_err(|e| trace_error("Couldn't resume execution", e)),
+    }
+}
+
+impl Mandrake {
+    pub fn new(snippit_length: usize, minimum_viable_string: usize, max_logged_instructions: Option<usize>, ignore_stdout: bool, ignore_stderr: bool, follow_exec: bool) -> Self {
+        Self {
+            snippit_length,
+            rip_snippit_length:      None,
+            data_snippit_length:     None,
+            stack_snippit_length:    None,
+            analysis_window:         None,
+            syscall_snippit_length:  DEFAULT_SYSCALL_SNIPPIT_LENGTH,
+            minimum_viable_string,
+            max_string_length:       128,
+            exec_arg_length:         DEFAULT_EXEC_ARG_LENGTH,
+            max_trace_bytes:         DEFAULT_MAX_TRACE_BYTES,
+            input_hex_limit:         DEFAULT_INPUT_HEX_LIMIT,
+            max_logged_instructions,
+            cpu_time_limit:          None,
+            progress:                false,
+            time_breakdown:          false,
+            loop_repeats:            None,
+            deliver_signals:         vec![],
+            fatal_signals:           vec![],
+            signal_injections:       vec![],
+            capture_stdout:          !ignore_stdout,
+            capture_stderr:          !ignore_stderr,
+            follow_exec,
+            stop_at_syscall:         None,
+            skip_syscall_detail:     vec![],
+            proc_status:             false,
+            trace_detail:            TraceDetail::Full,
+            registers:               None,
+            capture_envp:            false,
+            disable_aslr:            false,
+            deterministic:           false,
+            keep_env:                vec![],
+            bad_bytes:               vec![0x00],
+            no_memory:               false,
+            full_coverage:           true,
+            show_gaps:               false,
+            int3_stops:              false,
+            dedup_memory:            false,
+            delta_history:           false,
+            collapse_loops:          false,
+            sample_rate:             1,
+            only_mnemonics:          vec![],
+            tail:                    None,
+            capture_writes:          false,
+            crash_dump:              None,
+            dump_regions:            vec![],
+            dump_dir:                None,
+            hold_on_crash:           false,
+            hold_timeout:            Some(Duration::from_secs(DEFAULT_HOLD_TIMEOUT)),
+            region_filter:           None,
+            resolve_imports:         false,
+            debug_registers:         false,
+            memory_entropy:          false,
+            analyze_mode:            AnalyzeMode::All,
+            upcoming_instructions:   5,
+            deref_depth:             0,
+            phases:                  vec![],
+            constants:               Constants::builtin(),
+            interrupted:             None,
+            deep_analyses:           AtomicUsize::new(0),
+        }
+    }
+
+    /// Stop the analysis cleanly (keeping everything collected so far) once
+    /// this flag is set - for example, from a Ctrl-C handler.
+    pub fn with_interrupt_flag(mut self, interrupted: Arc<AtomicBool>) -> Self {
+        self.interrupted = Some(interrupted);
+        self
+    }
+
+    /// Stop once the code has used this much CPU time (user + system), which
+    /// isn't affected by how busy the system is like a wall-clock timeout
+    /// is. It's checked every [`CPU_TIME_CHECK_INTERVAL`] instructions, so
+    /// the code can go a little over.
+    pub fn with_cpu_time_limit(mut self, cpu_time_limit: Option<Duration>) -> Self {
+        self.cpu_time_limit = cpu_time_limit;
+        self
+    }
+
+    /// Show how the trace is going (instructions executed and logged, the
+    /// speed, and where it is) on stderr, updated every second.
+    pub fn with_progress(mut self, progress: bool) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    /// Measure how long tracing each instruction takes, and report where the
+    /// time went (visible vs. hidden code, and by module).
+    pub fn with_time_breakdown(mut self, time_breakdown: bool) -> Self {
+        self.time_breakdown = time_breakdown;
+        self
+    }
+
+    /// Stop if the code gets back to exactly the same state (rip, rsp, and the
+    /// main registers) more than this many times, since it's stuck. Code that
+    /// spins waiting for something outside of it (like a file changing)
+    /// looks stuck too, so this is off by default.
+    pub fn with_loop_detection(mut self, loop_repeats: Option<usize>) -> Self {
+        self.loop_repeats = loop_repeats;
+        self
+    }
+
+    /// Pass these signals to the process (so its handlers run) and keep
+    /// tracing, instead of ending the trace when they arrive.
+    pub fn with_deliver_signals(mut self, deliver_signals: Vec<Signal>) -> Self {
+        self.deliver_signals = deliver_signals;
+        self
+    }
+
+    /// End the trace when these signals arrive, even if Mandrake would
+    /// normally handle them itself (like SIGALRM, the harness's timeout) or
+    /// they're in [`Mandrake::with_deliver_signals`].
+    pub fn with_fatal_signals(mut self, fatal_signals: Vec<Signal>) -> Self {
+        self.fatal_signals = fatal_signals;
+        self
+    }
+
+    /// Send signals to the process when it reaches certain instructions (its
+    /// handler is traced like any other code).
+    pub fn with_signal_injections(mut self, signal_injections: Vec<SignalInjection>) -> Self {
+        self.signal_injections = signal_injections;
+        self
+    }
+
+    /// Whether to pass a signal to the process instead of stopping
+    fn delivers_signal(&self, signal: Signal) -> bool {
+        self.deliver_signals.contains(&signal) && !self.fatal_signals.contains(&signal)
+    }
+
+    /// Check whether we've been asked to stop
+    fn is_interrupted(&self) -> bool {
+        match &self.interrupted {
+            Some(interrupted) => interrupted.load(Ordering::SeqCst),
+            None => false,
+        }
+    }
+
+    /// Look for these well-known constants in values and memory (defaults to
+    /// [`Constants::builtin`]).
+    pub fn with_constants(mut self, constants: Constants) -> Self {
+        self.constants = constants;
+        self
+    }
+
+    /// Label history entries with the most recent phase whose address has
+    /// executed.
+    pub fn with_phases(mut self, phases: Vec<Phase>) -> Self {
+        self.phases = phases;
+        self
+    }
+
+    /// When a value points at another pointer, follow the chain this many
+    /// levels deep (0 disables it; only with full trace detail).
+    pub fn with_deref_depth(mut self, deref_depth: usize) -> Self {
+        self.deref_depth = deref_depth;
+        self
+    }
+
+    /// Read this many bytes at each value to look for strings and code
+    /// (defaults to 128, or the snippit length if that's longer). Only the
+    /// snippit length of it is saved, so a smaller window means fewer reads
+    /// (but strings that start past it aren't found).
+    pub fn with_analysis_window(mut self, analysis_window: Option<usize>) -> Self {
+        self.analysis_window = analysis_window;
+        self
+    }
+
+    /// Save this many bytes at rip (instead of the snippit length), eg just
+    /// enough to decode an instruction.
+    pub fn with_rip_snippit_length(mut self, rip_snippit_length: Option<usize>) -> Self {
+        self.rip_snippit_length = rip_snippit_length;
+        self
+    }
+
+    /// Save this many bytes at the general-purpose registers other than rsp
+    /// and rbp (instead of the snippit length), eg enough to catch the
+    /// strings rsi and rdi point to.
+    pub fn with_data_snippit_length(mut self, data_snippit_length: Option<usize>) -> Self {
+        self.data_snippit_length = data_snippit_length;
+        self
+    }
+
+    /// Save this many bytes at rsp and rbp (instead of the snippit length),
+    /// eg enough to see a few stack slots.
+    pub fn with_stack_snippit_length(mut self, stack_snippit_length: Option<usize>) -> Self {
+        self.stack_snippit_length = stack_snippit_length;
+        self
+    }
+
+    /// How many bytes of memory to save at a register, depending on what it's
+    /// usually used for
+    fn snippit_length_for(&self, register: &str) -> usize {
+        let role = match register {
+            "rip"         => self.rip_snippit_length,
+            "rsp" | "rbp" => self.stack_snippit_length,
+            _             => self.data_snippit_length,
+        };
+
+        role.unwrap_or(self.snippit_length)
+    }
+
+    /// How much memory we actually read at each value
+    fn analysis_window(&self) -> usize {
+        self.analysis_window.unwrap_or_else(|| std::cmp::max(DEFAULT_ANALYSIS_WINDOW, self.snippit_length))
+    }
+
+    /// Show this many bytes of the buffers that syscall arguments point to.
+    pub fn with_syscall_snippit_length(mut self, syscall_snippit_length: usize) -> Self {
+        self.syscall_snippit_length = syscall_snippit_length;
+        self
+    }
+
+    /// Cut strings off after this many characters.
+    pub fn with_max_string_length(mut self, max_string_length: usize) -> Self {
+        self.max_string_length = max_string_length;
+        self
+    }
+
+    /// Cut each argument and environment variable that's passed to a program
+    /// (by `execve`, or to an ELF when it starts) off after this many bytes.
+    pub fn with_exec_arg_length(mut self, exec_arg_length: usize) -> Self {
+        self.exec_arg_length = exec_arg_length;
+        self
+    }
+
+    /// Once the history is using about this much memory, stop saving memory
+    /// snippits (and drop the ones already saved), then if it grows past it
+    /// again, only save rip. What was left out is listed in the output's
+    /// `shed`; the exit reason, syscalls, and output are always kept.
+    pub fn with_max_trace_bytes(mut self, max_trace_bytes: usize) -> Self {
+        self.max_trace_bytes = max_trace_bytes;
+        self
+    }
+
+    /// Include the code and stdin in the output's `input` as hex if they're
+    /// no bigger than this; bigger ones only get their SHA-256 and length.
+    pub fn with_input_hex_limit(mut self, input_hex_limit: usize) -> Self {
+        self.input_hex_limit = input_hex_limit;
+        self
+    }
+
+    /// Also disassemble this many instructions after the one at rip (only
+    /// with full trace detail; 0 disables it).
+    pub fn with_upcoming_instructions(mut self, upcoming_instructions: usize) -> Self {
+        self.upcoming_instructions = upcoming_instructions;
+        self
+    }
+
+    /// Store each distinct memory snippit once, in the output's
+    /// `memory_pool`, and reference it from the values.
+    pub fn with_dedup_memory(mut self, dedup_memory: bool) -> Self {
+        self.dedup_memory = dedup_memory;
+        self
+    }
+
+    /// Choose which registers get a full analysis (defaults to all of them).
+    pub fn with_analyze_mode(mut self, analyze_mode: AnalyzeMode) -> Self {
+        self.analyze_mode = analyze_mode;
+        self
+    }
+
+    /// Save the data passed to each `write` syscall in that instruction's
+    /// `extra`.
+    pub fn with_capture_writes(mut self, capture_writes: bool) -> Self {
+        self.capture_writes = capture_writes;
+        self
+    }
+
+    /// When the code crashes, save its writable and anonymous memory to this
+    /// directory (see [`crate::crash_dump`]).
+    pub fn with_crash_dump(mut self, crash_dump: Option<PathBuf>) -> Self {
+        self.crash_dump = crash_dump;
+        self
+    }
+
+    /// When the trace ends (or just before the process exits, if it exits on
+    /// its own), save these regions of memory in `dumped_regions` - handy for
+    /// code that decodes something into a buffer.
+    pub fn with_dump_regions(mut self, dump_regions: Vec<DumpRegion>) -> Self {
+        self.dump_regions = dump_regions;
+        self
+    }
+
+    /// Write the [`Mandrake::with_dump_regions`] regions to files in this
+    /// directory, instead of saving them in the output as hex.
+    pub fn with_dump_dir(mut self, dump_dir: Option<PathBuf>) -> Self {
+        self.dump_dir = dump_dir;
+        self
+    }
+
+    /// When the code crashes, leave the process stopped at the crash and let
+    /// go of it, so a debugger can attach, until the user presses Enter (or
+    /// sends us SIGUSR1, if stdin isn't a terminal). Writes to stderr and
+    /// reads stdin, so it's only meant for the commandline.
+    pub fn with_hold_on_crash(mut self, hold_on_crash: bool) -> Self {
+        self.hold_on_crash = hold_on_crash;
+        self
+    }
+
+    /// How long to hold a crashed process before carrying on anyway (`None`
+    /// waits forever).
+    pub fn with_hold_timeout(mut self, hold_timeout: Option<Duration>) -> Self {
+        self.hold_timeout = hold_timeout;
+        self
+    }
+
+    /// Only read memory from the regions this filter allows (see
+    /// [`RegionFilter`]); values in other regions are saved without memory,
+    /// with a note in `extra`.
+    pub fn with_region_filter(mut self, region_filter: Option<RegionFilter>) -> Self {
+        self.region_filter = region_filter.filter(|f| !f.is_empty());
+        self
+    }
+
+    /// When an ELF jumps or calls through a GOT slot (eg, from the PLT), note
+    /// which imported function it's going to in that instruction's `extra`.
+    pub fn with_resolve_imports(mut self, resolve_imports: bool) -> Self {
+        self.resolve_imports = resolve_imports;
+        self
+    }
+
+    /// Also save the debug registers (`dr0` - `dr7`), with DR7's hardware
+    /// breakpoints decoded in its `extra`. This costs eight more ptrace
+    /// calls per instruction.
+    pub fn with_debug_registers(mut self, debug_registers: bool) -> Self {
+        self.debug_registers = debug_registers;
+        self
+    }
+
+    /// Save the entropy of each memory snippit in the history (its
+    /// `memory_entropy`), to spot packed or encrypted buffers.
+    pub fn with_memory_entropy(mut self, memory_entropy: bool) -> Self {
+        self.memory_entropy = memory_entropy;
+        self
+    }
+
+    /// Only log every Nth instruction into the history (every instruction is
+    /// still stepped through and checked for int3s and syscalls).
+    pub fn with_sample_rate(mut self, sample_rate: usize) -> Self {
+        self.sample_rate = std::cmp::max(sample_rate, 1);
+        self
+    }
+
+    /// Only log instructions with these mnemonics (eg, `syscall`, `call`;
+    /// not case sensitive) into the history - every instruction is still
+    /// stepped through, this just picks which ones are kept. Empty (the
+    /// default) logs everything.
+    pub fn with_only_mnemonics(mut self, only_mnemonics: Vec<String>) -> Self {
+        self.only_mnemonics = only_mnemonics;
+        self
+    }
+
+    /// Whether an instruction passes the [`Mandrake::with_only_mnemonics`]
+    /// filter (one that doesn't decode never does, unless there's no filter)
+    fn wants_mnemonic(&self, mnemonic: Option<&str>) -> bool {
+        self.only_mnemonics.is_empty() || mnemonic.is_some_and(|mnemonic| self.only_mnemonics.iter().any(|wanted| wanted.eq_ignore_ascii_case(mnemonic)))
+    }
+
+    /// Only keep the last `tail` logged instructions in the history (the lead
+    /// up to a crash, say), instead of all of them.
+    pub fn with_tail(mut self, tail: Option<usize>) -> Self {
+        self.tail = tail.map(|tail| std::cmp::max(tail, 1));
+        self
+    }
+
+    /// Only save the registers that changed since the previous history
+    /// entry (plus rip).
+    pub fn with_delta_history(mut self, delta_history: bool) -> Self {
+        self.delta_history = delta_history;
+        self
+    }
+
+    /// Collapse runs of the same instruction (like a `rep movsb`, or a jump to
+    /// itself) into one history entry with a `repeat_count` - see
+    /// [`MandrakeOutput::collapse_loops`].
+    pub fn with_collapse_loops(mut self, collapse_loops: bool) -> Self {
+        self.collapse_loops = collapse_loops;
+        self
+    }
+
+    /// Treat `int3` as a hard stop, instead of "stop logging and continue".
+    pub fn with_int3_stops(mut self, int3_stops: bool) -> Self {
+        self.int3_stops = int3_stops;
+        self
+    }
+
+    /// Report where raw code has any of these bytes (defaults to just NUL),
+    /// for shellcode that has to avoid them, and whether each one is part of
+    /// an instruction that ran.
+    pub fn with_bad_bytes(mut self, bad_bytes: Vec<u8>) -> Self {
+        self.bad_bytes = bad_bytes;
+        self
+    }
+
+    /// Keep a compact record of every instruction that runs, even the ones
+    /// that aren't visible or logged (on by default).
+    pub fn with_full_coverage(mut self, full_coverage: bool) -> Self {
+        self.full_coverage = full_coverage;
+        self
+    }
+
+    /// When instructions weren't logged (because they weren't visible, or
+    /// weren't sampled), mark the next logged one with how many were skipped
+    /// and where it came from, so the gaps in the history are explicit.
+    pub fn with_show_gaps(mut self, show_gaps: bool) -> Self {
+        self.show_gaps = show_gaps;
+        self
+    }
+
+    /// Don't save memory snippits in the output (strings and instructions are
+    /// still analyzed).
+    pub fn with_no_memory(mut self, no_memory: bool) -> Self {
+        self.no_memory = no_memory;
+        self
+    }
+
+    /// Turn off address space randomization for the process, so the stack,
+    /// heap, and libraries are at the same addresses every run.
+    pub fn with_disable_aslr(mut self, disable_aslr: bool) -> Self {
+        self.disable_aslr = disable_aslr;
+        self
+    }
+
+    /// Run the process the same way every time, so traces can be compared:
+    /// no inherited file descriptors (past stdin, stdout, and stderr), only
+    /// the environment variables in [`Mandrake::with_keep_env`], the program
+    /// run by its full path with just its file name as argv[0] (both end up
+    /// on the stack), no ASLR, and the kernel's random bytes (AT_RANDOM,
+    /// where the stack canary comes from) zeroed.
+    pub fn with_deterministic(mut self, deterministic: bool) -> Self {
+        self.deterministic = deterministic;
+        self
+    }
+
+    /// The environment variables to keep in deterministic mode (by default,
+    /// there aren't any).
+    pub fn with_keep_env(mut self, keep_env: Vec<String>) -> Self {
+        self.keep_env = keep_env;
+        self
+    }
+
+    /// Save the environment the ELF sees on its stack (argv is always saved).
+    pub fn with_capture_envp(mut self, capture_envp: bool) -> Self {
+        self.capture_envp = capture_envp;
+        self
+    }
+
+    /// Only fully analyze these registers (see [`REGISTERS`]); the others
+    /// are saved as bare values. `rip` is always analyzed.
+    pub fn with_registers(mut self, registers: Option<Vec<String>>) -> Self {
+        self.registers = registers;
+        self
+    }
+
+    /// Check whether a register should be fully analyzed
+    fn is_register_selected(&self, register: &str) -> bool {
+        match &self.registers {
+            Some(registers) => register == "rip" || registers.iter().any(|r| r == register),
+            None => true,
+        }
+    }
+
+    /// Summarize the configuration for the output
+    fn output_config(&self) -> OutputConfig {
+        OutputConfig {
+            snippit_length:        self.snippit_length,
+            rip_snippit_length:    self.snippit_length_for("rip"),
+            data_snippit_length:   self.snippit_length_for("rax"),
+            stack_snippit_length:  self.snippit_length_for("rsp"),
+            analysis_window:       self.analysis_window(),
+            syscall_snippit_length: self.syscall_snippit_length,
+            skip_syscall_detail:   self.skip_syscall_detail.clone(),
+            deliver_signals:       self.deliver_signals.iter().map(|signal| signal.to_string()).collect(),
+            fatal_signals:         self.fatal_signals.iter().map(|signal| signal.to_string()).collect(),
+            inject_signals:        self.signal_injections.iter().map(|injection| injection.to_string()).collect(),
+            minimum_viable_string: self.minimum_viable_string,
+            max_string_length:     self.max_string_length,
+            max_instructions:      self.max_logged_instructions,
+            cpu_time_limit_ms:     self.cpu_time_limit.map(|limit| limit.as_millis() as u64),
+            max_trace_bytes:       self.max_trace_bytes,
+            loop_repeats:          self.loop_repeats,
+            sample_rate:           self.sample_rate,
+            only_mnemonics:        self.only_mnemonics.clone(),
+            tail:                  self.tail,
+            trace_detail:          self.trace_detail.to_string(),
+            analyze:               self.analyze_mode.to_string(),
+            registers:             self.registers.clone(),
+            bad_bytes:             self.bad_bytes.iter().map(|byte| format!("{:02x}", byte)).collect(),
+            no_memory:             self.no_memory,
+            full_coverage:         self.full_coverage,
+            show_gaps:             self.show_gaps,
+            disable_aslr:          self.disable_aslr || self.deterministic,
+            deterministic:         self.deterministic,
+
+            // These are set when the history is actually delta-encoded /
+            // collapsed
+            delta_history:         false,
+            collapse_loops:        false,
+            syscalls_csv:          syscalls_csv_source().map(|s| s.to_string()),
+        }
+    }
+
+    /// Choose how much analysis to do at each step (defaults to everything).
+    pub fn with_trace_detail(mut self, trace_detail: TraceDetail) -> Self {
+        self.trace_detail = trace_detail;
+        self
+    }
+
+    /// Save selected fields from /proc/<pid>/status when the analysis ends.
+    pub fn with_proc_status(mut self, proc_status: bool) -> Self {
+        self.proc_status = proc_status;
+        self
+    }
+
+    /// Stop the analysis right before the first syscall with this name
+    /// executes (either `open` or `sys_open` works).
+    pub fn with_stop_at_syscall(mut self, stop_at_syscall: Option<String>) -> Self {
+        self.stop_at_syscall = stop_at_syscall;
+        self
+    }
+
+    /// Don't decode the arguments of these syscalls (by name, with or without
+    /// the `sys_` prefix) - they're still logged, with just the raw argument
+    /// values. This saves a lot of time (and noise) for syscalls that are
+    /// made constantly, like `futex` or `clock_gettime`.
+    pub fn with_skip_syscall_detail(mut self, skip_syscall_detail: Vec<String>) -> Self {
+        self.skip_syscall_detail = skip_syscall_detail;
+        self
+    }
+
+    /// Describe a syscall for the summary. `decoded` is the arguments, if
+    /// they were already decoded for this stop; otherwise (the instruction
+    /// might not have been analyzed) they're decoded straight from the
+    /// registers, reading the stop's `memory`.
+    fn syscall_event(&self, abi: SyscallAbi, regs: &user_regs_struct, index: usize, address: u64, memory: &MemoryCache, decoded: Option<Vec<String>>) -> SyscallEvent {
+        let args = abi.arguments(regs);
+
+        let (name, arguments) = match abi.find(regs.rax) {
+            Some(syscall) if self.skip_syscall_detail.iter().any(|name| syscall.has_name(name)) => {
+                (syscall.name.clone(), AnalyzedValue::syscall_argument_numbers(abi, syscall, args))
+            },
+            Some(syscall) => {
+                let arguments = decoded.unwrap_or_else(|| {
+                    let values = args.map(|value| AnalyzedValue::from_value(value, false));
+                    AnalyzedValue::syscall_arguments(memory, abi, syscall, values.each_ref(), self.syscall_snippit_length)
+                });
+
+                (syscall.name.clone(), arguments)
+            },
+            None => {
+                warn!("Unknown {} syscall {} at 0x{:08x}", abi, regs.rax, address);
+                (format!("syscall {}", regs.rax), vec![])
+            },
+        };
+
+        SyscallEvent {
+            index,
+            address,
+            number: regs.rax,
+            name,
+            abi: abi.to_string(),
+            arguments,
+            return_value: None,
+            returned: None,
+            mapping: None,
+            first_executed: None,
+        }
+    }
+
+    /// For --dump-region: read each region (as much of it as we can, from
+    /// the start), and save it in the output or to a file.
+    fn dump_memory(&self, pid: Pid) -> Vec<DumpedMemory> {
+        // The registers are only there if the process is still stopped
+        let regs = getregs(pid).ok();
+
+        if let Some(dir) = &self.dump_dir {
+            if let Err(e) = std::fs::create_dir_all(dir) {
+                warn!("Couldn't create {}: {}", dir.display(), e);
+            }
+        }
+
+        self.dump_regions.iter().map(|region| {
+            let mut out = DumpedMemory::new(region.to_string(), region.length);
+
+            out.address = region.address(regs.as_ref());
+            let address = match out.address {
+                Some(address) => address,
+                None => {
+                    out.error = Some("Couldn't read the registers (the process is gone)".to_string());
+                    return out;
+                },
+            };
+
+            let data = AnalyzedValue::get_memory(pid, address, region.length).map(|(data, _)| data).unwrap_or_default();
+            out.readable = data.len();
+            if data.is_empty() {
+                out.error = Some(format!("Couldn't read memory at 0x{:x}", address));
+                return out;
+            }
+
+            match &self.dump_dir {
+                Some(dir) => {
+                    let path = dir.join(format!("{:016x}-{}.bin", address, region.length));
+                    match std::fs::write(&path, &data) {
+                        Ok(()) => out.file = Some(path.display().to_string()),
+                        Err(e) => out.error = Some(format!("Couldn't write {}: {}", path.display(), e)),
+                    }
+                },
+                None => out.data = Some(hex::encode(&data)),
+            }
+
+            out
+        }).collect()
+    }
+
+    /// For --hold-on-crash: let go of the crashed process (leaving it
+    /// stopped) so a debugger can attach, wait until we're told to carry on,
+    /// then kill it.
+    fn hold_process(&self, pid: Pid, signal: Signal, address: u64) {
+        // Detaching with SIGSTOP leaves it stopped, instead of running into
+        // the crash again
+        if let Err(e) = detach(pid, Some(Signal::SIGSTOP)) {
+            warn!("Couldn't detach from process {} to hold it: {}", pid, e);
+            return;
+        }
+
+        eprintln!("Process {} crashed with {} at 0x{:08x}, and is stopped so a debugger can attach (eg, `gdb -p {}`)", pid, signal, address, pid);
+
+        let terminal = isatty(0).unwrap_or(false);
+        match terminal {
+            true  => eprintln!("Process held, press Enter to continue"),
+            false => eprintln!("Process held, send SIGUSR1 to {} to continue", std::process::id()),
+        }
+
+        match wait_for_release(terminal, self.hold_timeout, self.interrupted.as_deref()) {
+            Ok(true)  => (),
+            Ok(false) => eprintln!("Stopped holding process {} after {:?}", pid, self.hold_timeout.unwrap_or_default()),
+            Err(e)    => warn!("Stopped holding process {}: {}", pid, e),
+        }
+
+        // It isn't ours to ptrace anymore (a debugger might have it), so
+        // kill it the normal way; it's still our child, so it's reaped the
+        // same as always
+        let _ = send_signal(pid, Signal::SIGKILL);
+    }
+
+    /// For --show-gaps: how many traced instructions ran between the last
+    /// logged one and `index`, and the address of the one right before it
+    /// (`None` if nothing was skipped).
+    fn gap_before(&self, index: usize, last_logged: Option<usize>, previous_rip: Option<u64>) -> Option<(usize, Option<u64>)> {
+        if !self.show_gaps {
+            return None;
+        }
+
+        let skipped = match last_logged {
+            Some(last_logged) => index - last_logged - 1,
+            None => index,
+        };
+
+        (skipped > 0).then_some((skipped, previous_rip))
+    }
+
+    /// If a syscall is the one we were asked to stop at, returns its full
+    /// name
+    fn stop_syscall_name(&self, syscall: &'static Syscall) -> Option<&'static str> {
+        let stop_at = self.stop_at_syscall.as_ref()?;
+
+        if syscall.has_name(stop_at) {
+            Some(&syscall.name)
+        } else {
+            None
+        }
+    }
+
+    /// Set any ptrace options we need - this must be called while the
+    /// process is stopped (ie, right after it's spawned).
+    fn set_ptrace_options(&self, pid: Pid) -> SimpleResult<()> {
+        // Stop right before the process exits, so we can read its registers,
+        // /proc/<pid>, and its memory while it still exists
+        let options = Options::PTRACE_O_TRACEEXIT;
+
+        setoptions(pid, options)
+            .map_err(|e| trace_error("Couldn't set ptrace options", e))?;
+        debug!("Set ptrace options {:?} on process {}", options, pid);
+
+        Ok(())
+    }
+
+    /// Analyze every register, for `final_registers` (`None` if the process
+    /// isn't stopped, eg because it's gone).
+    fn final_registers(&self, pid: Pid, memory_map: Option<&MemoryMap>) -> Option<HashMap<String, AnalyzedValue>> {
+        let regs = getregs(pid).ok()?;
+
+        self.get_registers_from_pid(pid, &regs, TraceDetail::Full, &self.stop_memory(pid, memory_map), &MemoryCache::new(pid))
+            .map(|(regs, _)| regs)
+            .ok()
+    }
+
+    /// Trace the process until it ends.
+    ///
+    /// `harness_return` is where the harness resumes after the user's code
+    /// returns - the harness has an `int3` there that isn't a user breakpoint.
+    ///
+    /// `executed`, if it's given, has every instruction that runs marked in
+    /// it (whether or not it's visible).
+    fn go(&self, child: Child, visibility: &VisibilityConfiguration, harness_return: Option<u64>, imports: Option<&Imports>, mut executed: Option<&mut ExecutedBytes>) -> SimpleResult<MandrakeOutput> {
+        // Build a state then loop, one instruction at a time, till this ends
+        let mut result = MandrakeOutput::new(child.id());
+        result.config = self.output_config();
+        let pid = Pid::from_raw(child.id() as i32);
+        self.deep_analyses.store(0, Ordering::Relaxed);
+
+        // Kill the process when we're done with it, even if something fails
+        let traced = TracedProcess { pid };
+        info!("Tracing process {}", pid);
+
+        // If the OOM killer's count goes up while we're running, it probably
+        // killed us
+        let oom_kills = read_oom_kill_count();
+
+        // The last address we saw, for when the process dies without telling
+        // us where it was
+        let mut last_rip: Option<u64> = None;
+
+        // The syscall that the last instruction made, so we can show what it
+        // returned (in rax) at the next stop
+        let mut returning_syscall: Option<(SyscallAbi, u64, [u64; 6])> = None;
+
+        // Memory that was mapped executable and hasn't run yet, with the
+        // index of the syscall that mapped it (in `result.syscalls`), so we
+        // can point out when the code jumps into it
+        let mut executable_mappings: Vec<(MemoryMapping, usize)> = vec![];
+
+        // The signal (and address) the code crashed with, if it did
+        let mut crashed: Option<(Signal, u64)> = None;
+
+        // A wait status that was picked up while stepping over an int3, which
+        // needs to be handled instead of waiting again
+        let mut pending_status: Option<WaitStatus> = None;
+
+        // This flag is set when a call to execve is made, and we want to stop
+        // tracing. The new process creation causes debugging to turn back on,
+        // and we don't want that.
+        let mut completed = false;
+
+        // Whether the process is being single-stepped (as opposed to running
+        // freely) - we need this to resume it properly after a ptrace event.
+        // analyze_code() hands us a process that's mid-step, and analyze_elf()
+        // hands us one that's running.
+        let mut stepping = harness_return.is_some();
+
+        // For --progress: when we started, when the line was last updated, and
+        // how many instructions were logged (the history can be trimmed)
+        let started = Instant::now();
+        let mut last_progress: Option<Instant> = None;
+        let mut logged: usize = 0;
+
+        // How many times we've seen each state, when looking for infinite
+        // loops
+        let mut loop_states: HashMap<u64, usize> = HashMap::new();
+
+        // About how much memory the history is using, and what we've stopped
+        // saving to keep it down
+        let mut trace_bytes: usize = 0;
+        let mut shed_memory = false;
+        let mut shed_registers = false;
+
+        // Signals that haven't been injected yet, ones that were sent but
+        // haven't arrived (they can be blocked for a while), and the one whose
+        // handler we're about to enter - the last two by their index in
+        // `result.injected_signals`
+        let mut injections = self.signal_injections.clone();
+        let mut arriving: Vec<(Signal, usize)> = vec![];
+        let mut entering_handler: Option<usize> = None;
+
+        // For --time-breakdown: where the time went, and when the last
+        // instruction's stop was (and where it was: the module, and whether
+        // it was visible)
+        let mut time_breakdown = self.time_breakdown.then(TimeBreakdown::default);
+        let mut timing: Option<(Instant, String, bool)> = None;
+
+        // For --show-gaps: the index of the last instruction that was logged,
+        // and where the last instruction (logged or not) was
+        let mut last_logged: Option<usize> = None;
+        let mut previous_rip: Option<u64> = None;
+
+        // Where the last instruction was, and where it should have left rip
+        // if it doesn't branch - single-stepping should always land there
+        let mut expected_rip: Option<(u64, u64)> = None;
+
+        // Every instruction that ran, visible or not
+        let mut coverage = self.full_coverage.then(CoverageRecorder::default);
+
+        // The phase we're in, which changes whenever we reach a phase's address
+        let mut phase: Option<String> = None;
+
+        // The process's memory map, which we only read again when it might
+        // have changed
+        let mut memory_map: Option<MemoryMap> = None;
+
+        // The status the process is exiting with, from PTRACE_EVENT_EXIT, in
+        // case it's killed on its way out
+        let mut exiting_code: Option<i32> = None;
+
+        loop {
+            let status = match pending_status.take() {
+                Some(status) => Ok(status),
+                None => wait(),
+            };
+
+            match status {
+                Ok(WaitStatus::Exited(_, code)) => {
+                    info!("Process {} exited with code {}", pid, code);
+                    result.set_exit_reason(ExitReason::CleanExit { code });
+                    result.exit_code = Some(code);
+
+                    // Only the low byte of the status makes it to wait()
+                    if let Some(status) = result.exit_syscall_status.filter(|status| status & 0xff != code) {
+                        warn!("Process {} called exit with {}, but exited with code {}", pid, status, code);
+                    }
+                    break;
+                }
+                Ok(WaitStatus::Signaled(_, signal, _)) => {
+                    // The process was killed by something other than us (a
+                    // stop would let us see it first), so all we know is where
+                    // it was last
+                    let oom_killer = signal == Signal::SIGKILL && match (oom_kills, read_oom_kill_count()) {
+                        (Some(before), Some(after)) => after > before,
+                        _ => false,
+                    };
+                    info!("Process {} was killed by {}", pid, signal);
+
+                    // If it was already exiting, that's still the code it
+                    // would have exited with
+                    result.exit_code = exiting_code;
+
+                    result.set_exit_reason(ExitReason::Killed {
+                        signal: signal.to_string(),
+                        oom_killer,
+                        last_address: last_rip,
+                    });
+                    break;
+                },
+                Ok(WaitStatus::Stopped(_, sig)) => {
+                    let stopped_at = Instant::now();
+
+                    // Get rip when it crashes (if the process is gone, the
+                    // next wait() tells us why)
+                    let raw_regs = match getregs(pid) {
+                        Ok(raw_regs) => raw_regs,
+                        Err(nix::Error::Sys(Errno::ESRCH)) => {
+                            debug!("Process {} went away before its registers could be read", pid);
+                            continue;
+                        },
+                        Err(e) => return Err(trace_error("Couldn't read registers", e)),
+                    };
+                    last_rip = Some(raw_regs.rip);
+                    trace!("Stopped at 0x{:08x} ({})", raw_regs.rip, sig);
+
+                    if result.memory_maps.start.is_none() {
+                        result.memory_maps.start = MemoryMap::read(pid).map(|map| map.regions);
+                    }
+
+                    // The first stop after injecting a signal is its handler
+                    if let Some(injected) = entering_handler.take().and_then(|i| result.injected_signals.get_mut(i)) {
+                        injected.handler = Some(raw_regs.rip);
+                    }
+
+                    // When we're sampling, instructions that won't be logged
+                    // only need rip
+                    let sampled = result.instructions_executed % self.sample_rate == 0;
+                    let trace_detail = match (sampled, shed_registers) {
+                        (true, false) => self.trace_detail,
+                        _             => TraceDetail::Minimal,
+                    };
+
+                    // The region filter needs to know what's mapped where
+                    // before anything is read
+                    if self.region_filter.is_some() && memory_map.is_none() {
+                        memory_map = MemoryMap::read(pid);
+                    }
+
+                    // The code that's running is always safe to read, even
+                    // if the region filter doesn't allow it. It's only good
+                    // for this stop, since the process can change it.
+                    let code = MemoryCache::new(pid);
+
+                    let memory = self.stop_memory(pid, memory_map.as_ref());
+                    let (mut regs, syscall_arguments) = self.get_registers_from_pid(pid, &raw_regs, trace_detail, &memory, &code)?;
+
+                    // The syscall this instruction makes, if it makes one.
+                    // It's described now, while the stop's memory can still
+                    // be read (finding regions can read the memory map again).
+                    let syscall_abi = regs.get("rip")
+                        .and_then(|rip| rip.as_instruction.as_deref())
+                        .and_then(SyscallAbi::from_instruction);
+                    let syscall = syscall_abi.and_then(|abi| abi.find(raw_regs.rax));
+                    let syscall_event = syscall_abi.map(|abi| self.syscall_event(abi, &raw_regs, result.instructions_executed, raw_regs.rip, &memory, syscall_arguments));
+
+                    // Finding regions can read memory, so only do it for a
+                    // full analysis
+                    if trace_detail == TraceDetail::Full {
+                        classify_regions(pid, &mut memory_map, &mut regs, self.region_filter.is_none());
+                    }
+
+                    // Show what the syscall we just stepped over returned
+                    if let Some((abi, syscall_num, args)) = returning_syscall.take() {
+                        let name = match abi.find(syscall_num) {
+                            Some(syscall) => syscall.name.clone(),
+                            None => format!("syscall {}", syscall_num),
+                        };
+                        let returned = format_syscall_return(&name, raw_regs.rax);
+                        let mapping = MemoryMapping::from_syscall(abi, syscall_num, args, raw_regs.rax);
+
+                        if let Some(event) = result.syscalls.last_mut() {
+                            event.return_value = Some(raw_regs.rax);
+                            event.returned = Some(returned.clone());
+                            event.mapping = mapping.map(|mapping| mapping.to_string());
+                        }
+
+                        if let Some(rax) = regs.get_mut("rax") {
+                            let extra = rax.extra.get_or_insert_with(Vec::new);
+                            extra.push(format!("Returned from {}: {}", name, returned));
+                            if let Some(mapping) = mapping {
+                                extra.push(mapping.to_string());
+                            }
+                        }
+
+                        if let Some(mapping) = mapping.filter(|mapping| mapping.is_executable()) {
+                            executable_mappings.push((mapping, result.syscalls.len() - 1));
+                        }
+                    }
 
-/// Performs a wait() then cont().
-///
-/// Waits for the current operation to complete (which is a step), then
-/// continues execution
-fn resume_execution(pid: Pid) -> SimpleResult<()> {
-    wait()
-        .map_err(|e| SimpleError::new(&format!("Couldn't step over breakpoint: {}", e)))?;
+                    // A straight-line instruction that didn't end up at the
+                    // next one means the decoder got its length wrong, or
+                    // something (like a signal) moved rip. A rep instruction
+                    // stays where it is until it's finished, and after running
+                    // freely, the last instruction could be anywhere.
+                    if let Some((previous, expected)) = expected_rip.take().filter(|_| stepping) {
+                        if raw_regs.rip != expected && raw_regs.rip != previous {
+                            debug!("Expected to step from 0x{:08x} to 0x{:08x}, but ended up at 0x{:08x}", previous, expected, raw_regs.rip);
 
-    cont(pid, None)
-        .map_err(|e| SimpleError::new(&format!("Couldn't resume execution after breakpoint: {}", e)))?;
+                            if let Some(rip) = regs.get_mut("rip") {
+                                rip.extra.get_or_insert_with(Vec::new).push(format!("Stepped here from 0x{:08x}, which doesn't branch and should have gone to 0x{:08x} (a decoding problem, or a signal)", previous, expected));
+                            }
+                        }
+                    }
+                    expected_rip = match (regs.get("rip").and_then(|rip| rip.flow_control.as_deref()), regs.get("rip").and_then(|rip| rip.instruction_length)) {
+                        (Some("Next"), Some(length)) => Some((raw_regs.rip, raw_regs.rip + length as u64)),
+                        _                            => None,
+                    };
 
-    Ok(())
-}
+                    // Point out the first time the code runs in memory it
+                    // mapped executable (eg, a staged payload)
+                    if let Some(position) = executable_mappings.iter().position(|(mapping, _)| mapping.contains(raw_regs.rip)) {
+                        let (mapping, syscall_index) = executable_mappings.remove(position);
 
-impl Mandrake {
-    pub fn new(snippit_length: usize, minimum_viable_string: usize, max_logged_instructions: Option<usize>, ignore_stdout: bool, ignore_stderr: bool, follow_exec: bool) -> Self {
-        Self {
-            snippit_length:          snippit_length,
-            minimum_viable_string:   minimum_viable_string,
-            max_logged_instructions: max_logged_instructions,
-            capture_stdout:          !ignore_stdout,
-            capture_stderr:          !ignore_stderr,
-            follow_exec:             follow_exec,
-        }
-    }
+                        if let Some(event) = result.syscalls.get_mut(syscall_index) {
+                            event.first_executed = Some(result.instructions_executed);
 
-    fn go(&self, child: Child, visibility: &VisibilityConfiguration) -> SimpleResult<MandrakeOutput> {
-        // Build a state then loop, one instruction at a time, till this ends
-        let mut result = MandrakeOutput::new(child.id());
-        let pid = Pid::from_raw(child.id() as i32);
+                            if let Some(rip) = regs.get_mut("rip") {
+                                rip.extra.get_or_insert_with(Vec::new).push(format!("Jumped into memory {} by {} (instruction {})", mapping, event.name, event.index));
+                            }
+                        }
+                    }
 
-        // This flag is set when a call to execve is made, and we want to stop
-        // tracing. The new process creation causes debugging to turn back on,
-        // and we don't want that.
-        let mut completed = false;
+                    // If it's running another program, save everything it's
+                    // running with now, since it'll be gone afterwards
+                    let is_execve = syscall_abi.is_some_and(|abi| abi.is_execve(raw_regs.rax));
+                    let execve = match (is_execve, syscall_abi) {
+                        (true, Some(abi)) => read_execve_arguments(pid, abi, &raw_regs, self.exec_arg_length)
+                            .map_err(|e| warn!("Couldn't read what execve is going to run: {}", e))
+                            .ok(),
+                        _ => None,
+                    };
 
-        loop {
-            match wait() {
-                Ok(WaitStatus::Exited(_, code)) => {
-                    result.exit_reason = Some(format!("Process exited cleanly with exit code {}", code));
-                    result.exit_code = Some(code);
-                    break;
-                }
-                Ok(WaitStatus::Stopped(_, sig)) => {
-                    // Get rip when it crashes
-                    let regs = self.get_registers_from_pid(pid)
-                        .map_err(|e| SimpleError::new(format!("Couldn't read registers: {}", e)))?;
+                    // Save what write() is about to write, so it's next to the
+                    // code that wrote it
+                    if let (true, Some(abi)) = (self.capture_writes, syscall_abi.filter(|abi| abi.is_write(raw_regs.rax))) {
+                        if let Some(rip) = regs.get_mut("rip") {
+                            let [fd, buf, count, ..] = abi.arguments(&raw_regs);
+                            let length = std::cmp::min(count as usize, MAX_WRITE_CAPTURE);
+
+                            let written = match AnalyzedValue::get_memory(pid, buf, length) {
+                                Some((data, _)) => format!("Wrote to fd {}: \"{}\"", fd, String::from_utf8_lossy(&data).escape_debug()),
+                                None => format!("Wrote to fd {} from unreadable memory: 0x{:08x}", fd, buf),
+                            };
+                            rip.extra.get_or_insert_with(Vec::new).push(written);
+                        }
+                    }
+
+                    // Name the library function we're about to jump to
+                    if let Some(imports) = imports {
+                        if let Some(rip) = regs.get_mut("rip") {
+                            annotate_import(&code, imports, rip);
+                        }
+                    }
 
                     // Get the value for RIP, die if it's missing (shouldn't happen)
                     let rip = match regs.get("rip") {
@@ -82,16 +1848,192 @@ impl Mandrake {
                         None => bail!("rip is missing from the register list!"),
                     };
 
+                    // If this syscall might change the memory map, read it
+                    // again at the next stop
+                    if let Some(abi) = syscall_abi {
+                        returning_syscall = Some((abi, raw_regs.rax, abi.arguments(&raw_regs)));
+                        result.syscalls.extend(syscall_event);
+
+                        if abi.changes_memory_map(raw_regs.rax) {
+                            memory_map = None;
+                        }
+                    }
+
+                    if self.progress && last_progress.map_or(true, |last| last.elapsed() >= PROGRESS_INTERVAL) {
+                        print_progress(pid, &mut memory_map, result.instructions_executed, logged, raw_regs.rip, started.elapsed());
+                        last_progress = Some(Instant::now());
+                    }
+
+                    // If the user hit Ctrl-C, stop here and keep what we have
+                    if self.is_interrupted() {
+                        info!("Interrupted at 0x{:08x}", rip.value);
+                        result.set_exit_reason(ExitReason::Interrupted { address: rip.value, instruction: rip.as_instruction.clone() });
+                        break;
+                    }
+
+                    // Stop if the code has used up its CPU time
+                    if let Some(limit) = self.cpu_time_limit {
+                        if result.instructions_executed % CPU_TIME_CHECK_INTERVAL == 0 {
+                            if let Some(used) = read_cpu_time(pid).filter(|used| *used > limit) {
+                                info!("Used {:?} of CPU time (limit: {:?}) at 0x{:08x}", used, limit, rip.value);
+                                result.set_exit_reason(ExitReason::CpuTimeLimit {
+                                    limit_ms: limit.as_millis() as u64,
+                                    used_ms: used.as_millis() as u64,
+                                    address: rip.value,
+                                    instruction: rip.as_instruction.clone(),
+                                });
+                                break;
+                            }
+                        }
+                    }
+
+                    // Stop if the code is stuck
+                    if let Some(loop_repeats) = self.loop_repeats {
+                        if loop_states.len() >= MAX_LOOP_STATES {
+                            loop_states.clear();
+                        }
+
+                        let repeats = loop_states.entry(loop_state(&raw_regs)).or_insert(0);
+                        *repeats += 1;
+                        if *repeats > loop_repeats {
+                            info!("The same state repeated {} times at 0x{:08x}, so it's stuck", repeats, rip.value);
+                            result.set_exit_reason(ExitReason::InfiniteLoop { address: rip.value, instruction: rip.as_instruction.clone(), repeats: *repeats });
+                            break;
+                        }
+                    }
+
+                    // A signal we injected has arrived, so let it through (it
+                    // was logged when it was sent)
+                    if let Some(position) = arriving.iter().position(|(signal, _)| *signal == sig) {
+                        let (_, injected) = arriving.remove(position);
+                        debug!("Delivering injected {} at 0x{:08x}", sig, rip.value);
+
+                        if catches_signal(pid, sig) != Some(false) {
+                            entering_handler = Some(injected);
+                        }
+                        resume(pid, stepping, Some(sig))?;
+                        continue;
+                    }
+
+                    // Let the process handle signals the user asked for, and
+                    // keep going (when stepping, the next stop is the first
+                    // instruction of the handler)
+                    if self.delivers_signal(sig) {
+                        info!("Delivering {} to process {} at 0x{:08x}", sig, pid, rip.value);
+
+                        let delivered = match read_siginfo(pid, sig).and_then(|info| info.fault_address) {
+                            Some(fault_address) => format!("Delivered {} to the process (fault address: 0x{:08x})", sig, fault_address),
+                            None => format!("Delivered {} to the process", sig),
+                        };
+                        if let Some(rip) = regs.get_mut("rip") {
+                            rip.extra.get_or_insert_with(Vec::new).push(delivered);
+                        }
+
+                        // The instruction didn't run, so it isn't counted
+                        let mut entry = HistoryEntry::new(result.instructions_executed, regs).with_phase(phase.clone()).with_signal(Some(sig.to_string()));
+                        if shed_memory {
+                            entry.strip_memory();
+                        }
+                        trace_bytes += entry.approximate_size();
+                        result.history.push(entry);
+
+                        resume(pid, stepping, Some(sig))?;
+                        continue;
+                    }
+
+                    if CRASH_SIGNALS.contains(&sig) || self.fatal_signals.contains(&sig) {
+                        crashed = Some((sig, rip.value));
+                    }
+
                     match sig {
                         // Do nothing, this is the happy call
                         Signal::SIGTRAP => {
-                            // No matter what, step past the instruction
-                            step(pid, None)
-                                .map_err(|e| SimpleError::new(&format!("Couldn't step through code: {}", e)))?;
+                            // Whether the process was already set running
+                            // (past an execve we aren't following), so it
+                            // mustn't be stepped again
+                            let mut resumed = false;
+
+                            // Check if we're starting a new phase
+                            if let Some(new_phase) = self.phases.iter().find(|p| p.address == rip.value) {
+                                info!("Entering phase {} at 0x{:08x}", new_phase.name, rip.value);
+                                phase = Some(new_phase.name.clone());
+                            }
+
+                            // Some syscalls need to be handled before they run
+                            if let (false, Some(syscall)) = (completed, syscall) {
+                                // If this is the syscall we're waiting for, log it
+                                // (with its full arguments, regardless of the
+                                // trace detail) and stop
+                                if let Some(name) = self.stop_syscall_name(syscall) {
+                                    info!("Reached syscall {} at 0x{:08x}", name, rip.value);
+                                    result.set_exit_reason(ExitReason::ReachedSyscall { syscall: name.to_string(), address: rip.value, instruction: rip.as_instruction.clone(), execve: execve.clone() });
+
+                                    if result.starting_address.is_none() {
+                                        result.starting_address = Some(rip.value);
+                                    }
+                                    let (mut full_regs, _) = self.get_registers_from_pid(pid, &raw_regs, TraceDetail::Full, &self.stop_memory(pid, memory_map.as_ref()), &code)?;
+                                    classify_regions(pid, &mut memory_map, &mut full_regs, self.region_filter.is_none());
+                                    result.history.push(HistoryEntry::new(result.instructions_executed, full_regs).with_phase(phase).with_execve(execve));
+                                    result.instructions_executed += 1;
+
+                                    break;
+                                }
+                            }
+
+                            // Everything below decides what to do with the
+                            // instruction *before* stepping past it, so if
+                            // we stop here, the process is still sitting on
+                            // it (and if stepping faults, the next stop is the
+                            // fault, with rip still pointing at it)
 
                             // If we're already finished, just keep going
                             if completed {
-                                resume_execution(pid)?;
+                                if let (Some(breakdown), Some((started, module, visible))) = (&mut time_breakdown, timing.take()) {
+                                    breakdown.add(&module, visible, stopped_at - started);
+                                }
+                                resume(pid, true, None)?;
+                                pending_status = resume_execution(pid)?;
+                                stepping = false;
+                                continue;
+                            }
+
+                            // Send any signal that's meant to arrive before
+                            // this instruction. It arrives as soon as the
+                            // process resumes (unless it's blocked), and the
+                            // instruction runs once the handler returns, so it
+                            // isn't counted yet; if there's another one for
+                            // the same index, it's sent at the start of the
+                            // handler
+                            if let Some(position) = injections.iter().position(|injection| injection.index == result.instructions_executed) {
+                                let injection = injections.remove(position);
+                                let address = rip.value;
+                                info!("Injecting {} at 0x{:08x} (instruction {})", injection.signal, address, injection.index);
+                                if catches_signal(pid, injection.signal) == Some(false) {
+                                    warn!("Process {} doesn't handle {}, so its default action will happen", pid, injection.signal);
+                                }
+                                ignore_missing_process(send_signal(pid, injection.signal))
+                                    .map_err(|e| trace_error("Couldn't send a signal", e))?;
+
+                                if let Some(rip) = regs.get_mut("rip") {
+                                    rip.extra.get_or_insert_with(Vec::new).push(format!("Injected {}", injection.signal));
+                                }
+                                let mut entry = HistoryEntry::new(result.instructions_executed, regs).with_phase(phase.clone()).with_signal(Some(injection.signal.to_string()));
+                                if shed_memory {
+                                    entry.strip_memory();
+                                }
+                                trace_bytes += entry.approximate_size();
+                                result.history.push(entry);
+
+                                result.injected_signals.push(InjectedSignal {
+                                    signal: injection.signal.to_string(),
+                                    index: injection.index,
+                                    address,
+                                    handler: None,
+                                });
+                                arriving.push((injection.signal, result.injected_signals.len() - 1));
+
+                                resume(pid, true, None)?;
+                                stepping = true;
                                 continue;
                             }
 
@@ -99,45 +2041,95 @@ impl Mandrake {
                             if let Some(instruction) = &rip.as_instruction {
                                 // Toggle "following" for "int 3"
                                 if instruction == "int3" {
+                                    // Unless the user wants int3 to be a hard stop
+                                    if self.int3_stops && harness_return != Some(rip.value) {
+                                        info!("Stopping at int3 at 0x{:08x}", rip.value);
+                                        result.set_exit_reason(ExitReason::Breakpoint { address: rip.value });
+
+                                        if result.starting_address.is_none() {
+                                            result.starting_address = Some(rip.value);
+                                        }
+                                        result.history.push(HistoryEntry::new(result.instructions_executed, regs).with_phase(phase));
+
+                                        break;
+                                    }
+
+                                    info!("int3 at 0x{:08x}, running until the next one", rip.value);
+
+                                    // Running freely isn't part of any instruction
+                                    if let (Some(breakdown), Some((started, module, visible))) = (&mut time_breakdown, timing.take()) {
+                                        breakdown.add(&module, visible, stopped_at - started);
+                                    }
+
                                     // Waiting for the step() to finish before continuing is important
-                                    resume_execution(pid)?;
+                                    resume(pid, true, None)?;
+                                    pending_status = resume_execution(pid)?;
+                                    stepping = false;
 
                                     // Continue so it's not logged
                                     continue;
                                 }
 
                                 // Toggle following on exec, unless the user turned that off
-                                if !self.follow_exec && instruction == "syscall" {
-                                    // Check the syscall num (based on rax)
-                                    let syscall_num = match regs.get("rax") {
-                                        Some(rax) => rax,
-                                        None => bail!("rax is missing from the register list!"),
-                                    };
-
-                                    // sys_execve
-                                    if syscall_num.value == EXECVE_NUM {
-                                        // Skip all future checks
-                                        completed = true;
-
-                                        // Resume, but don't skip the output (the user wants to see the exec!)
-                                        resume_execution(pid)?;
-                                    }
+                                if !self.follow_exec && is_execve {
+                                    info!("Not following execve at 0x{:08x}", rip.value);
+                                    // Skip all future checks
+                                    completed = true;
+
+                                    // Resume, but don't skip the output (the user wants to see the exec!)
+                                    resume(pid, true, None)?;
+                                    pending_status = resume_execution(pid)?;
+                                    stepping = false;
+                                    resumed = true;
                                 }
                             }
 
                             // Count the instructions
+                            let index = result.instructions_executed;
                             result.instructions_executed += 1;
 
                             // Count the actual instructions executed (even if they're invisible)
                             if let Some(max_instructions) = self.max_logged_instructions {
                                 if result.instructions_executed >= max_instructions {
-                                    result.exit_reason = Some(format!("Execution stopped at instruction cap (max instructions: {})", max_instructions));
+                                    info!("Reached the instruction cap ({})", max_instructions);
+                                    result.set_exit_reason(ExitReason::InstructionCap { max_instructions });
                                     break;
                                 }
                             }
 
+                            if let Some(coverage) = &mut coverage {
+                                coverage.record(rip.value, rip.instruction_length);
+                            }
+                            if let Some(executed) = executed.as_deref_mut() {
+                                executed.record(rip.value, rip.instruction_length);
+                            }
+
+                            // The last instruction took until now
+                            if let Some(breakdown) = &mut time_breakdown {
+                                if let Some((started, module, visible)) = timing.take() {
+                                    breakdown.add(&module, visible, stopped_at - started);
+                                }
+
+                                if memory_map.is_none() {
+                                    memory_map = MemoryMap::read(pid);
+                                }
+                                let module = memory_map.as_ref().map_or_else(|| "unknown".to_string(), |map| map.module_name(rip.value));
+                                timing = Some((stopped_at, module, visibility.is_visible(rip.value)));
+                            }
+
+                            // How many instructions since the last one that
+                            // was logged (if it's about to be)
+                            let gap = self.gap_before(index, last_logged, previous_rip);
+                            previous_rip = Some(rip.value);
+
                             // Check if we're supposed to see this
                             if !visibility.is_visible(rip.value) {
+                                trace!("0x{:08x} isn't visible, so it isn't logged", rip.value);
+
+                                if !resumed {
+                                    resume(pid, true, None)?;
+                                    stepping = true;
+                                }
                                 continue;
                             }
 
@@ -146,54 +2138,221 @@ impl Mandrake {
                                 result.starting_address = Some(rip.value);
                             }
 
-                            result.history.push(regs);
+                            if sampled && self.wants_mnemonic(rip.mnemonic.as_deref()) {
+                                let mut entry = HistoryEntry::new(index, regs).with_phase(phase.clone()).with_execve(execve).with_gap(gap);
+                                last_logged = Some(index);
+                                if self.memory_entropy {
+                                    entry.set_memory_entropy();
+                                }
+                                if shed_memory {
+                                    entry.strip_memory();
+                                }
+                                trace_bytes += entry.approximate_size();
+                                result.history.push(entry);
+                                logged += 1;
+
+                                // Drop the oldest entries if we're only keeping
+                                // the tail (in batches, so it's not a copy for
+                                // every instruction)
+                                if let Some(tail) = self.tail {
+                                    if result.history.len() >= tail * 2 {
+                                        let excess = result.history.len() - tail;
+                                        trace_bytes -= result.history.drain(..excess).map(|entry| entry.approximate_size()).sum::<usize>();
+                                    }
+                                }
+
+                                // If the history is getting too big, leave out
+                                // the memory, then everything but rip
+                                if trace_bytes > self.max_trace_bytes {
+                                    if !shed_memory {
+                                        warn!("The history is over {} bytes, so memory snippits won't be saved anymore", self.max_trace_bytes);
+                                        result.shed.push(ShedDetail { shed: "memory".to_string(), index, trace_bytes });
+                                        shed_memory = true;
+
+                                        for entry in &mut result.history {
+                                            entry.strip_memory();
+                                        }
+                                        trace_bytes = result.history.iter().map(HistoryEntry::approximate_size).sum();
+                                    } else if !shed_registers {
+                                        warn!("The history is still over {} bytes, so only rip will be saved from now on", self.max_trace_bytes);
+                                        result.shed.push(ShedDetail { shed: "registers".to_string(), index, trace_bytes });
+                                        shed_registers = true;
+                                    }
+                                }
+                            }
+
+                            // Now that it's logged, step past it
+                            if !resumed {
+                                resume(pid, true, None)?;
+                                stepping = true;
+                            }
 
                             continue;
                         },
 
                         // Check for the special timeout symbol (since we set alarm() in the harness)
-                        Signal::SIGALRM => { result.exit_reason = Some(format!("Execution timed out (SIGALRM) @ {}", rip)); break; },
-
-                        // Try and catch other obvious problems
-                        Signal::SIGABRT => { result.exit_reason = Some(format!("Execution crashed with an abort (SIGABRT) @ {}", rip)); break; }
-                        Signal::SIGBUS => { result.exit_reason = Some(format!("Execution crashed with a bus error (bad memory access) (SIGBUS) @ {}", rip)); break; }
-                        Signal::SIGFPE => { result.exit_reason = Some(format!("Execution crashed with a floating point error (SIGFPE) @ {}", rip)); break; }
-                        Signal::SIGILL => { result.exit_reason = Some(format!("Execution crashed with an illegal instruction (SIGILL) @ {}", rip)); break; },
-                        Signal::SIGKILL => { result.exit_reason = Some(format!("Execution was killed (SIGKILL) @ {}", rip)); break; },
-                        Signal::SIGSEGV => { result.exit_reason = Some(format!("Execution crashed with a segmentation fault (SIGSEGV) @ {}", rip)); break; },
-                        Signal::SIGTERM => { result.exit_reason = Some(format!("Execution was terminated (SIGTERM) @ {}", rip)); break; },
-                        Signal::SIGCHLD => { result.exit_reason = Some(format!("Execution ended when child process ended (SIGCHLD)")); break; },
-
-                        _ => { result.exit_reason = Some(format!("Execution stopped by unexpected signal: {}", sig)); break; }
+                        Signal::SIGALRM if !self.fatal_signals.contains(&sig) => { result.set_exit_reason(ExitReason::Timeout { address: rip.value, instruction: rip.as_instruction.clone() }); break; },
+
+                        // The child process ended
+                        Signal::SIGCHLD if !self.fatal_signals.contains(&sig) => { result.set_exit_reason(ExitReason::ChildExited); break; },
+
+                        // Anything else (usually a crash) ends the analysis
+                        _ => {
+                            info!("Process {} stopped with {} at 0x{:08x}", pid, sig, rip.value);
+                            result.set_exit_reason(ExitReason::Signal {
+                                signal: sig.to_string(),
+                                address: rip.value,
+                                instruction: rip.as_instruction.clone(),
+                                siginfo: read_siginfo(pid, sig),
+                            });
+                            break;
+                        },
+                    };
+
+                },
+                Ok(WaitStatus::PtraceEvent(_, _, event)) if event == Event::PTRACE_EVENT_EXIT as i32 => {
+                    // The process is about to exit, this is our last chance to
+                    // read its status
+                    debug!("Process {} is about to exit", pid);
+                    exiting_code = match ptrace::getevent(pid) {
+                        Ok(status) if status & 0x7f == 0 => Some(((status >> 8) & 0xff) as i32),
+                        _ => None,
                     };
 
+                    // When it's exiting normally, it's in the exit syscall,
+                    // which still has its arguments
+                    if exiting_code.is_some() {
+                        if let Ok(regs) = getregs(pid) {
+                            result.exit_syscall_status = [SyscallAbi::X86_64, SyscallAbi::X86].into_iter()
+                                .find(|abi| abi.is_exit(regs.orig_rax))
+                                .map(|abi| abi.arguments(&regs)[0] as i32);
+                        }
+                    }
+                    result.final_registers = self.final_registers(pid, memory_map.as_ref());
+                    if self.proc_status {
+                        result.proc_status = read_proc_status(pid);
+                    }
+                    if !self.dump_regions.is_empty() {
+                        result.dumped_regions = self.dump_memory(pid);
+                    }
+
+                    resume(pid, stepping, None)?;
+                },
+                Ok(WaitStatus::PtraceEvent(_, _, _)) | Ok(WaitStatus::PtraceSyscall(_)) => {
+                    // Other ptrace events (such as PTRACE_EVENT_STOP for a
+                    // group-stop) and syscall-stops aren't interesting to us,
+                    // so just carry on the way we were going
+                    debug!("Ignoring {:?}", status);
+                    resume(pid, stepping, None)?;
                 },
                 Ok(s) => bail!("Unexpected stop reason: {:?}", s),
-                Err(e) => bail!("Unexpected wait() error: {:?}", e),
+
+                // The process was reaped by someone else, so there's nothing
+                // left to trace
+                Err(nix::Error::Sys(Errno::ECHILD)) => {
+                    warn!("Lost track of process {} (it was reaped by something else)", pid);
+                    result.set_exit_reason(ExitReason::LostProcess);
+                    break;
+                },
+                Err(e) => return Err(trace_error("Unexpected wait() error", e)),
             };
         }
 
+        if let Some(coverage) = coverage {
+            result.full_coverage = coverage.finish();
+        }
+
+        if let Some(mut breakdown) = time_breakdown {
+            if let Some((started, module, visible)) = timing {
+                breakdown.add(&module, visible, started.elapsed());
+            }
+            breakdown.finish();
+            result.time_breakdown = Some(breakdown);
+        }
+
+        result.deep_analyses = self.deep_analyses.load(Ordering::Relaxed);
+
         // I don't know why, but this fixes a random timeout that sometimes breaks
         // this :-/
         //
         // As of 2022-01, I have no idea if this is still needed or if the bug
         // this fixed is long-gone, but I'm too afraid to try because the bug
         // was always sporadic :)
-        println!("");
+        println!();
 
-        // Whatever situation we're in, we need to make sure the process is dead
-        // (We discard errors here, because we don't really care if it was already
-        // killed or failed to kill or whatever)
-        match kill(pid) {
-            Ok(_) => (),
-            Err(_) => (),
-        };
+        // Finish the --progress line
+        if last_progress.is_some() {
+            eprintln!();
+        }
+
+        if let Some(tail) = self.tail {
+            let excess = result.history.len().saturating_sub(tail);
+            result.history.drain(..excess);
+        }
+
+        // If the trace ended some other way than exiting, the process is still
+        // stopped wherever it ended
+        if result.final_registers.is_none() {
+            result.final_registers = self.final_registers(pid, memory_map.as_ref());
+        }
+
+        // If the process is still around, this is our last chance to read its
+        // status
+        if self.proc_status && result.proc_status.is_none() {
+            result.proc_status = read_proc_status(pid);
+        }
+
+        // Read the map again while the process is still there: the one we've
+        // been keeping is only refreshed at the next stop after a syscall
+        // that changes it, so it can be missing the last change. If the
+        // process already exited, the one we kept is the best there is.
+        result.memory_maps.end = MemoryMap::read(pid)
+            .or_else(|| memory_map.take())
+            .map(|map| map.regions);
+
+        // The process is stopped at the crash, so its memory is still there
+        if let (Some(dir), Some((signal, rip))) = (&self.crash_dump, crashed) {
+            match write_crash_dump(pid, dir, signal, rip) {
+                Ok(manifest) => result.crash_dump = Some(manifest.display().to_string()),
+                Err(e) => {
+                    warn!("Couldn't save a crash dump: {}", e);
+                    result.crash_dump_error = Some(e.to_string());
+                },
+            }
+        }
+
+        // (Unless it already exited, and they were saved on the way out)
+        if !self.dump_regions.is_empty() && result.dumped_regions.is_empty() {
+            result.dumped_regions = self.dump_memory(pid);
+        }
+
+        if let (true, Some((signal, rip))) = (self.hold_on_crash, crashed) {
+            self.hold_process(pid, signal, rip);
+        }
+
+        // Make sure the process is dead (and reaped) before we read its
+        // output, otherwise we'd wait forever for the pipes to close
+        drop(traced);
+
+        result.collect_strings();
+
+        if self.collapse_loops {
+            result.collapse_loops();
+        }
+
+        if self.delta_history {
+            result.delta_encode();
+        }
+
+        if self.dedup_memory {
+            result.dedup_memory();
+        }
 
         // If we made it here, grab the stdout + stderr
         if self.capture_stdout {
             let mut stdout: Vec<u8> = vec![];
             child.stdout
-                .ok_or_else(|| SimpleError::new(format!("Couldn't get a handle to stdout")))?
+                .ok_or_else(|| SimpleError::new("Couldn't get a handle to stdout"))?
                 .read_to_end(&mut stdout)
                 .map_err(|e| SimpleError::new(format!("Failed while trying to read stdout: {}", e)))?;
 
@@ -203,7 +2362,7 @@ impl Mandrake {
         if self.capture_stderr {
             let mut stderr: Vec<u8> = vec![];
             child.stderr
-                .ok_or_else(|| SimpleError::new(format!("Couldn't get a handle to stderr")))?
+                .ok_or_else(|| SimpleError::new("Couldn't get a handle to stderr"))?
                 .read_to_end(&mut stderr)
                 .map_err(|e| SimpleError::new(format!("Failed while trying to read stderr: {}", e)))?;
             result.stderr = Some(String::from_utf8_lossy(&stderr).to_string());
@@ -212,104 +2371,362 @@ impl Mandrake {
         Ok(result)
     }
 
-    fn get_registers_from_pid(&self, pid: Pid) -> SimpleResult<HashMap<String, AnalyzedValue>> {
-        // Try and get the registers
-        let regs = match getregs(pid) {
-            Ok(r) => r,
-            Err(e) => bail!("Couldn't read registers: {}", e),
+    /// Only read each page of memory once for a stop, and only what the
+    /// region filter allows (this has to be dropped before the process runs
+    /// again, because its memory changes).
+    fn stop_memory<'a>(&'a self, pid: Pid, memory_map: Option<&'a MemoryMap>) -> MemoryCache<'a> {
+        match (&self.region_filter, memory_map) {
+            (Some(filter), Some(map)) => MemoryCache::new(pid).with_filter(filter, map),
+            _                         => MemoryCache::new(pid),
+        }
+    }
+
+    /// Analyze the registers at a stop, reading `memory` for what they point
+    /// to and `code` (which isn't filtered) for the instructions at rip.
+    ///
+    /// If it's a syscall and its arguments were decoded, they're returned
+    /// too, so the syscall summary doesn't have to decode them again.
+    fn get_registers_from_pid(&self, pid: Pid, regs: &user_regs_struct, trace_detail: TraceDetail, memory: &MemoryCache, code: &MemoryCache) -> SimpleResult<StopRegisters> {
+        // With minimal detail, all we want is the instruction at rip
+        if trace_detail == TraceDetail::Minimal {
+            return Ok((vec![
+                ("rip".to_string(), AnalyzedValue::instruction_only(pid, regs.rip)),
+            ].into_iter().collect(), None));
+        }
+
+        let rip = match trace_detail {
+            TraceDetail::Full => AnalyzedValue::new_with_upcoming(code, regs.rip, true, self.analysis_window(), self.snippit_length_for("rip"), self.minimum_viable_string, self.max_string_length, self.upcoming_instructions),
+            _                 => AnalyzedValue::instruction_only(pid, regs.rip),
+        };
+        let syscall_abi = rip.as_instruction.as_deref().and_then(SyscallAbi::from_instruction);
+
+        // Syscalls the user isn't interested in only get their raw arguments
+        let skip_syscall_detail = syscall_abi
+            .and_then(|abi| abi.find(regs.rax))
+            .is_some_and(|syscall| self.skip_syscall_detail.iter().any(|name| syscall.has_name(name)));
+
+        // In smart mode, only the registers the instruction uses are analyzed
+        let used_registers = match (self.analyze_mode, trace_detail) {
+            (AnalyzeMode::Smart, TraceDetail::Full) => Some(AnalyzedValue::used_registers(code, regs.rip).unwrap_or_default()),
+            _                                       => None,
+        };
+
+        // Registers often hold the same value (eg, rbp == rsp), so remember
+        // what we've analyzed (and how much of it was saved) to avoid reading
+        // the same memory twice
+        let mut cache: HashMap<(u64, usize), AnalyzedValue> = HashMap::new();
+
+        // Analyze a value fully if it's selected (or needed for a syscall),
+        // otherwise just save the value
+        let mut analyze = |register: &str, value: u64| {
+            let used = match &used_registers {
+                Some(used_registers) => used_registers.iter().any(|r| r == register),
+                None => true,
+            };
+            let selected = (used && self.is_register_selected(register)) || (!skip_syscall_detail && syscall_abi.is_some_and(|abi| register == "rax" || abi.argument_registers().contains(&register)));
+
+            match (trace_detail, selected) {
+                (TraceDetail::Full, true) => {
+                    let snippit_length = self.snippit_length_for(register);
+                    cache.entry((value, snippit_length)).or_insert_with(|| {
+                        self.deep_analyses.fetch_add(1, Ordering::Relaxed);
+
+                        let mut analyzed = AnalyzedValue::new(memory, value, false, self.analysis_window(), snippit_length, self.minimum_viable_string, self.max_string_length);
+                        analyzed.follow_pointers(memory, self.deref_depth, self.analysis_window(), snippit_length, self.minimum_viable_string, self.max_string_length);
+                        analyzed.annotate_constants(&self.constants);
+                        analyzed
+                    }).clone()
+                },
+                _                         => AnalyzedValue::from_value(value, false),
+            }
         };
 
         // Analyze and save each one
         let mut out: HashMap<String, AnalyzedValue> = vec![
-            ("rip".to_string(), AnalyzedValue::new(pid, regs.rip, true,  self.snippit_length, self.minimum_viable_string)),
-            ("rax".to_string(), AnalyzedValue::new(pid, regs.rax, false, self.snippit_length, self.minimum_viable_string)),
-            ("rbx".to_string(), AnalyzedValue::new(pid, regs.rbx, false, self.snippit_length, self.minimum_viable_string)),
-            ("rcx".to_string(), AnalyzedValue::new(pid, regs.rcx, false, self.snippit_length, self.minimum_viable_string)),
-            ("rdx".to_string(), AnalyzedValue::new(pid, regs.rdx, false, self.snippit_length, self.minimum_viable_string)),
-            ("rsi".to_string(), AnalyzedValue::new(pid, regs.rsi, false, self.snippit_length, self.minimum_viable_string)),
-            ("rdi".to_string(), AnalyzedValue::new(pid, regs.rdi, false, self.snippit_length, self.minimum_viable_string)),
-            ("rbp".to_string(), AnalyzedValue::new(pid, regs.rbp, false, self.snippit_length, self.minimum_viable_string)),
-            ("rsp".to_string(), AnalyzedValue::new(pid, regs.rsp, false, self.snippit_length, self.minimum_viable_string)),
+            ("rip".to_string(), rip),
+            ("rax".to_string(), analyze("rax", regs.rax)),
+            ("rbx".to_string(), analyze("rbx", regs.rbx)),
+            ("rcx".to_string(), analyze("rcx", regs.rcx)),
+            ("rdx".to_string(), analyze("rdx", regs.rdx)),
+            ("rsi".to_string(), analyze("rsi", regs.rsi)),
+            ("rdi".to_string(), analyze("rdi", regs.rdi)),
+            ("rbp".to_string(), analyze("rbp", regs.rbp)),
+            ("rsp".to_string(), analyze("rsp", regs.rsp)),
 
             // I guess we should do the boring registers, too...
-            ("r8".to_string(),  AnalyzedValue::new(pid, regs.r8,  false, self.snippit_length, self.minimum_viable_string)),
-            ("r9".to_string(),  AnalyzedValue::new(pid, regs.r9,  false, self.snippit_length, self.minimum_viable_string)),
-            ("r10".to_string(), AnalyzedValue::new(pid, regs.r10, false, self.snippit_length, self.minimum_viable_string)),
-            ("r11".to_string(), AnalyzedValue::new(pid, regs.r11, false, self.snippit_length, self.minimum_viable_string)),
-            ("r12".to_string(), AnalyzedValue::new(pid, regs.r12, false, self.snippit_length, self.minimum_viable_string)),
-            ("r13".to_string(), AnalyzedValue::new(pid, regs.r13, false, self.snippit_length, self.minimum_viable_string)),
-            ("r14".to_string(), AnalyzedValue::new(pid, regs.r14, false, self.snippit_length, self.minimum_viable_string)),
-            ("r15".to_string(), AnalyzedValue::new(pid, regs.r15, false, self.snippit_length, self.minimum_viable_string)),
+            ("r8".to_string(),  analyze("r8",  regs.r8)),
+            ("r9".to_string(),  analyze("r9",  regs.r9)),
+            ("r10".to_string(), analyze("r10", regs.r10)),
+            ("r11".to_string(), analyze("r11", regs.r11)),
+            ("r12".to_string(), analyze("r12", regs.r12)),
+            ("r13".to_string(), analyze("r13", regs.r13)),
+            ("r14".to_string(), analyze("r14", regs.r14)),
+            ("r15".to_string(), analyze("r15", regs.r15)),
         ].into_iter().collect();
 
-        // Handle syscalls - this needs to come after because we need all values
-        if let Some(rip) = &out.get("rip") {
-            if let Some(instruction) = &rip.as_instruction {
-                if instruction == "syscall" {
-                    // Load + clone registers before getting a mutable instance of
-                    // rip (Rust smartly doesn't let us read and write a variable
-                    // at the same time!)
-                    let rax = out.get("rax").ok_or_else(|| SimpleError::new(format!("Could not read value of rax")))?.clone();
-                    let rdi = out.get("rdi").ok_or_else(|| SimpleError::new(format!("Could not read value of rdi")))?.clone();
-                    let rsi = out.get("rsi").ok_or_else(|| SimpleError::new(format!("Could not read value of rsi")))?.clone();
-                    let rdx = out.get("rdx").ok_or_else(|| SimpleError::new(format!("Could not read value of rdx")))?.clone();
-                    let r10 = out.get("r10").ok_or_else(|| SimpleError::new(format!("Could not read value of r10")))?.clone();
-                    let r8  = out.get("r8" ).ok_or_else(|| SimpleError::new(format!("Could not read value of r8" )))?.clone();
-                    let r9  = out.get("r9" ).ok_or_else(|| SimpleError::new(format!("Could not read value of r9" )))?.clone();
-
-                    // This gets a mutable handle to `out` - that means we can't
-                    // read from `out` within this block!
-                    out.get_mut("rip").map(|rip| {
-                        rip.extra = Some(AnalyzedValue::syscall_info(pid, &rax, &rdi, &rsi, &rdx, &r10, &r8, &r9));
-                    });
+        // The debug registers are never pointers we care about, so they're
+        // saved as bare values (with DR7 explained)
+        if self.debug_registers {
+            if let Some(debug_registers) = read_debug_registers(pid) {
+                for (name, value) in DEBUG_REGISTERS.iter().zip(debug_registers) {
+                    out.insert(name.to_string(), AnalyzedValue::from_value(value, false));
+                }
+
+                if let Some(dr7) = out.get_mut("dr7") {
+                    let breakpoints = decode_dr7(dr7.value);
+                    if !breakpoints.is_empty() {
+                        dr7.extra = Some(breakpoints);
+                    }
+                }
+            }
+        }
+
+        // Syscall arguments need memory, so without full detail, we only show
+        // the numbers
+        if trace_detail != TraceDetail::Full {
+            if let Some(abi) = syscall_abi {
+                if let Some(rip) = out.get_mut("rip") {
+                    rip.extra = Some(AnalyzedValue::syscall_numbers(abi, regs.rax, abi.arguments(regs)));
                 }
             }
+
+            return Ok((out, None));
+        }
+
+        let mut arguments: Option<Vec<String>> = None;
+
+        // Handle syscalls - this needs to come after because we need all values
+        if let (Some(abi), true) = (syscall_abi, skip_syscall_detail) {
+            if let Some(rip) = out.get_mut("rip") {
+                rip.extra.get_or_insert_with(Vec::new).extend(AnalyzedValue::syscall_numbers(abi, regs.rax, abi.arguments(regs)));
+            }
+        } else if let Some(abi) = syscall_abi {
+            // Load + clone registers before getting a mutable instance of
+            // rip (Rust smartly doesn't let us read and write a variable
+            // at the same time!)
+            let register = |name: &str| out.get(name).cloned().ok_or_else(|| SimpleError::new(format!("Could not read value of {}", name)));
+
+            let rax = register("rax")?;
+            let [a, b, c, d, e, f] = abi.argument_registers();
+            let args = [register(a)?, register(b)?, register(c)?, register(d)?, register(e)?, register(f)?];
+
+            // This gets a mutable handle to `out` - that means we can't
+            // read from `out` within this block!
+            let info = AnalyzedValue::syscall_info(memory, abi, &rax, args.each_ref(), self.syscall_snippit_length);
+
+            // For a syscall we know, that's its name followed by the arguments
+            if abi.find(regs.rax).is_some() {
+                arguments = Some(info[1..].to_vec());
+            }
+
+            if let Some(rip) = out.get_mut("rip") {
+                rip.extra.get_or_insert_with(Vec::new).extend(info);
+            }
+        }
+
+        // Now that the syscall arguments are decoded, we can throw away the
+        // memory if the user doesn't want it
+        if self.no_memory {
+            for value in out.values_mut() {
+                value.for_each_mut(&mut |value| value.memory = None);
+            }
+        }
+
+        Ok((out, arguments))
+    }
+
+    /// Start building the command that runs `program` - in deterministic
+    /// mode, that's by its full path (so it doesn't depend on the current
+    /// directory), as just its file name, with only the allowed environment.
+    fn command(&self, program: &Path) -> SimpleResult<Command> {
+        if !self.deterministic {
+            return Ok(Command::new(program));
+        }
+
+        let full_path = std::fs::canonicalize(program)
+            .map_err(|e| SimpleError::new(format!("Couldn't find the full path to {:?}: {}", program, e)))?;
+
+        let mut command = Command::new(&full_path);
+        if let Some(name) = full_path.file_name() {
+            command.arg0(name);
+        }
+
+        command.env_clear();
+        for name in &self.keep_env {
+            if let Some(value) = std::env::var_os(name) {
+                command.env(name, value);
+            }
         }
 
-        Ok(out)
+        Ok(command)
+    }
+
+    /// Start a process, stopped (and traced) right after it's executed.
+    /// `keep_fds` are file descriptors that are being handed to it on
+    /// purpose, which deterministic mode leaves alone.
+    fn spawn(&self, command: &mut Command, keep_fds: &[RawFd]) -> std::io::Result<Child> {
+        if self.deterministic {
+            // Find what the process would inherit now, since the child
+            // shouldn't allocate (anything close-on-exec goes away anyway)
+            let inherited: Vec<RawFd> = std::fs::read_dir("/proc/self/fd")?
+                .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+                .filter(|fd: &RawFd| *fd > 2 && !keep_fds.contains(fd))
+                .filter(|fd| fcntl(*fd, FcntlArg::F_GETFD).is_ok_and(|flags| flags & nix::libc::FD_CLOEXEC == 0))
+                .collect();
+
+            // Safe because it only makes syscalls between fork() and exec()
+            unsafe {
+                command.pre_exec(move || {
+                    for fd in &inherited {
+                        let _ = close(*fd);
+                    }
+                    Ok(())
+                });
+            }
+        }
+
+        if self.disable_aslr || self.deterministic {
+            // Safe because it only makes a syscall between fork() and exec()
+            unsafe {
+                command.pre_exec(|| {
+                    let persona = personality::get().map_err(|e| std::io::Error::other(e.to_string()))?;
+                    personality::set(persona | Persona::ADDR_NO_RANDOMIZE).map_err(|e| std::io::Error::other(e.to_string()))?;
+                    Ok(())
+                });
+            }
+        }
+
+        command.spawn_ptrace()
     }
 
     pub fn analyze_code(&self, code: Vec<u8>, harness_path: &Path, show_everything: bool) -> SimpleResult<MandrakeOutput> {
         if !harness_path.exists() {
-            bail!("Could not find the execution harness: {:?} - use --harness to specify the path to the 'harness' executable (which is available on https://github.com/counterhack)", harness_path);
+            bail!("Could not find the execution harness: {:?} - use --harness to specify the path to the 'harness' executable (which is available on https://github.com/counterhack), or --embedded-harness to use the one built into Mandrake", harness_path);
         }
 
-        let child = Command::new(harness_path)
-            .arg(hex::encode(code))
+        // The harness maps exactly this much code, so anything past it is
+        // padding rather than the user's code
+        let code_length = code.len();
+
+        // Check for bytes the code isn't allowed to have, before it's gone
+        let bad_byte_offsets: Vec<usize> = code.iter().enumerate()
+            .filter(|(_, byte)| self.bad_bytes.contains(byte))
+            .map(|(offset, _)| offset)
+            .collect();
+        let code_entropy = shannon_entropy(&code);
+        let input = AnalyzedInput {
+            code: Some(InputData::new(&code, Some(self.input_hex_limit))),
+            ..Default::default()
+        };
+
+        let mut command = self.command(harness_path)?;
+        command.arg(hex::encode(&code))
             .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn_ptrace()
-            .map_err(|e| SimpleError::new(format!("Could not execute testing harness: {}", e)))?;
+            .stderr(Stdio::piped());
+        if self.deterministic {
+            command.env("GLIBC_TUNABLES", HARNESS_DETERMINISTIC_TUNABLES);
+        }
+
+        // Give the harness a pipe for its own messages (it closes it before
+        // running the code)
+        let (diagnostics, diagnostics_write) = pipe2(OFlag::O_CLOEXEC)
+            .map_err(|e| SimpleError::new(format!("Couldn't create a pipe for the harness: {}", e)))?;
+        // Safe because the fd is owned by nobody else
+        let mut diagnostics = unsafe { File::from_raw_fd(diagnostics) };
+
+        // Safe because it only makes a syscall between fork() and exec()
+        unsafe {
+            command.pre_exec(move || {
+                // dup2() doesn't copy close-on-exec, so this stays open
+                dup2(diagnostics_write, HARNESS_DIAGNOSTICS_FD).map_err(|e| std::io::Error::other(e.to_string()))?;
+                Ok(())
+            });
+        }
+
+        let child = self.spawn(&mut command, &[HARNESS_DIAGNOSTICS_FD]);
+
+        // Close our end, so reading the pipe finishes once the harness closes
+        // its end
+        let _ = close(diagnostics_write);
+        let child = child.map_err(|e| SimpleError::new(format!("Could not execute testing harness: {}", e)))?;
 
         // Get a pid structure
         let pid = Pid::from_raw(child.id() as i32);
+        self.set_ptrace_options(pid)?;
+        if self.deterministic {
+            clear_random_bytes(pid)?;
+        }
 
         // Find the first breakpiont
-        cont(pid, None).map_err(|e| SimpleError::new(format!("Couldn't resume execution: {}", e)))?;
-        wait().map_err(|e| SimpleError::new(format!("Failed while waiting for process to resume: {}", e)))?;
+        cont(pid, None).map_err(|e| trace_error("Couldn't resume execution", e))?;
+        wait().map_err(|e| trace_error("Failed while waiting for process to resume", e))?;
+
+        // We're sitting on the harness's `call`, so the user's code will
+        // return to the instruction right after it
+        let call_address = getregs(pid).map_err(|e| trace_error("Couldn't read registers", e))?.rip;
+        info!("Reached the harness's breakpoint at 0x{:08x}, stepping into the code", call_address);
+        let harness_return = AnalyzedValue::instruction_length(pid, call_address).map(|length| call_address + length as u64);
 
         // Step over it - this will perform the call() and move us to the start of
         // the user's code
-        step(pid, None).map_err(|e| SimpleError::new(format!("Failed to stop into the shellcode: {}", e)))?;
+        step(pid, None).map_err(|e| trace_error("Failed to stop into the shellcode", e))?;
+
+        // Bad bytes are only reported as executed if an instruction that
+        // actually ran covered them
+        let mut executed = (!bad_byte_offsets.is_empty()).then(|| ExecutedBytes::new(HARNESS_CODE_ADDRESS, code_length));
 
         // At this point, we can proceed to normal analysis
-        match show_everything {
-            false => self.go(child, &VisibilityConfiguration::full_visibility()),
-            true  => self.go(child, &VisibilityConfiguration::harness_visibility()),
-        }
+        let mut result = match show_everything {
+            false => self.go(child, &VisibilityConfiguration::full_visibility(), harness_return, None, executed.as_mut())?,
+            true  => self.go(child, &VisibilityConfiguration::harness_visibility(code_length), harness_return, None, executed.as_mut())?,
+        };
+        result.code_length = code_length;
+        result.badchar_report = bad_byte_offsets.iter().map(|&offset| BadByte {
+            offset,
+            byte: format!("{:02x}", code[offset]),
+            executed: executed.as_ref().is_some_and(|executed| executed.contains(offset)),
+        }).collect();
+        result.bad_byte_offsets = bad_byte_offsets;
+        result.code_entropy = Some(code_entropy);
+        result.input = input;
+        result.image_base = Some(HARNESS_CODE_ADDRESS);
+
+        // The process is gone by now, so this won't block
+        let mut harness_stderr: Vec<u8> = vec![];
+        diagnostics.read_to_end(&mut harness_stderr)
+            .map_err(|e| SimpleError::new(format!("Failed while trying to read the harness's messages: {}", e)))?;
+        result.harness_stderr = Some(String::from_utf8_lossy(&harness_stderr).to_string());
+
+        Ok(result)
     }
 
-    pub fn analyze_elf(&self, binary: &Path, stdin: Option<String>, args: Vec<String>, visibility: &VisibilityConfiguration) -> SimpleResult<MandrakeOutput> {
+    pub fn analyze_elf(&self, binary: &Path, stdin: Option<String>, fds: &[FdMapping], args: Vec<String>, visibility: &VisibilityConfiguration) -> SimpleResult<MandrakeOutput> {
         // Decode the stdin before starting the command, so we don't start the
         // process if the stdin is badly encoded
+        let stdin_hex = stdin.clone();
         let stdin = match stdin {
             Some(stdin) => Some(hex::decode(stdin).map_err(|e| SimpleError::new(format!("Could not parse --stdin-data as a hex string: {}", e)))?),
             None => None,
         };
 
+        // Describe what's being run before it starts (the executable is read
+        // to hash it, so a missing file fails here too)
+        let mut input = AnalyzedInput {
+            path: Some(binary.to_string_lossy().to_string()),
+            file: Some(InputData::new(&fs::read(binary).map_err(|e| SimpleError::new(format!("Couldn't read {:?}: {}", binary, e)))?, None)),
+            stdin: stdin.as_deref().map(|stdin| InputData::new(stdin, Some(self.input_hex_limit))),
+            ..Default::default()
+        };
+
+        // Likewise, read the imports first so a bad ELF fails early
+        let imports = match self.resolve_imports {
+            true  => Some(Imports::from_file(binary)?),
+            false => None,
+        };
+
         // This spawns the process and calls waitpid(), so it reaches the first
         // system call (execve)
-        let mut command = Command::new(binary);
+        let mut command = self.command(binary)?;
         command.stdout(Stdio::piped());
         command.stderr(Stdio::piped());
 
@@ -324,21 +2741,142 @@ impl Mandrake {
             command.arg(arg);
         }
 
-        let mut child = command.spawn_ptrace()
+        // Save the arguments it's actually run with - in deterministic mode,
+        // argv[0] is just the file name (see `command`)
+        let program = command.get_program();
+        let arg0 = match self.deterministic {
+            true  => Path::new(program).file_name().unwrap_or(program),
+            false => program,
+        };
+        input.argv = Some(std::iter::once(arg0).chain(command.get_args()).map(|arg| arg.to_string_lossy().to_string()).collect());
+
+        // Open the extra file descriptors here (so a missing file fails
+        // early), and move them into place in the child before it executes.
+        // They're kept open until the child has started.
+        let files = fds.iter().map(|mapping| {
+            File::open(&mapping.path).map_err(|e| SimpleError::new(format!("Couldn't open {:?} for file descriptor {}: {}", mapping.path, mapping.fd, e)))
+        }).collect::<SimpleResult<Vec<File>>>()?;
+
+        if !fds.is_empty() {
+            let sources: Vec<RawFd> = files.iter().map(|file| file.as_raw_fd()).collect();
+            let targets: Vec<RawFd> = fds.iter().map(|mapping| mapping.fd).collect();
+            let highest = targets.iter().copied().max().unwrap_or(0);
+
+            // Allocated now, since the child shouldn't allocate
+            let mut temporary: Vec<RawFd> = vec![0; sources.len()];
+
+            // Safe because it only makes syscalls between fork() and exec()
+            unsafe {
+                command.pre_exec(move || {
+                    // Move everything above the targets first, so one file
+                    // can't land on another one's fd before it's been moved
+                    for (temporary, source) in temporary.iter_mut().zip(&sources) {
+                        *temporary = fcntl(*source, FcntlArg::F_DUPFD_CLOEXEC(highest + 1)).map_err(|e| std::io::Error::other(e.to_string()))?;
+                    }
+
+                    // dup2() doesn't copy close-on-exec, so these stay open
+                    for (temporary, target) in temporary.iter().zip(&targets) {
+                        dup2(*temporary, *target).map_err(|e| std::io::Error::other(e.to_string()))?;
+                    }
+
+                    Ok(())
+                });
+            }
+        }
+
+        let mut child = self.spawn(&mut command, &fds.iter().map(|mapping| mapping.fd).collect::<Vec<RawFd>>())
             .map_err(|e| SimpleError::new(format!("Could not execute testing harness: {}", e)))?;
+        drop(files);
 
         if let Some(stdin) = stdin {
             child.stdin.take()
-                .ok_or_else(|| SimpleError::new(format!("Couldn't get a handle to stdin")))?
+                .ok_or_else(|| SimpleError::new("Couldn't get a handle to stdin"))?
                 .write_all(&stdin)
                 .map_err(|e| SimpleError::new(format!("Failed while trying to write to stdin: {}", e)))?;
         }
 
-        // Find the first breakpiont
+        // We're stopped right after execve, so the stack has exactly what the
+        // kernel handed to the process. It's only nice to have, so a stack we
+        // can't read doesn't stop the analysis (or leave the process behind).
         let pid = Pid::from_raw(child.id() as i32);
+        info!("Started {:?} as process {}, running until the first int3", binary, pid);
+        let (argv, envp) = match read_initial_stack(pid, self.exec_arg_length) {
+            Ok((argv, envp)) => (Some(argv), Some(envp)),
+            Err(e) => {
+                warn!("Couldn't read argv and envp from the stack: {}", e);
+                (None, None)
+            },
+        };
+        if self.deterministic {
+            clear_random_bytes(pid)?;
+        }
+
+        // Find the first breakpiont
+        self.set_ptrace_options(pid)?;
         cont(pid, None)
-            .map_err(|e| SimpleError::new(format!("Couldn't resume execution: {}", e)))?;
+            .map_err(|e| trace_error("Couldn't resume execution", e))?;
+
+        // Move the imports to where the ELF was loaded (if we can't tell, it's
+        // probably not a PIE, so they're already in the right place)
+        let image_base = MemoryMap::read(pid).and_then(|map| map.executable_base());
+        let imports = imports.map(|imports| {
+            match image_base {
+                Some(load_address) => imports.relocate(load_address),
+                None => imports,
+            }
+        });
+
+        let mut result = self.go(child, visibility, None, imports.as_ref(), None)?;
+        result.image_base = image_base;
+        result.argv = argv;
+        result.stdin = stdin_hex;
+        result.input = input;
+        if self.capture_envp {
+            result.envp = envp;
+        }
+
+        Ok(result)
+    }
+
+    /// Analyze the instruction at an address in a process that's already
+    /// being traced (and is stopped), the same way rip is analyzed in a trace.
+    /// This is for tools that drive a process themselves and want to look
+    /// around without running [`Mandrake::analyze_code`] or [`Mandrake::analyze_elf`].
+    pub fn disassemble_at(&self, pid: Pid, address: u64) -> AnalyzedValue {
+        let memory = MemoryCache::new(pid);
+
+        AnalyzedValue::new_with_upcoming(&memory, address, true, self.analysis_window(), self.snippit_length_for("rip"), self.minimum_viable_string, self.max_string_length, self.upcoming_instructions)
+    }
+
+    /// Read up to `length` bytes from a process that's already being traced
+    /// (and is stopped). If it runs into unreadable memory part way through,
+    /// it returns what it got, so the result can be shorter; `None` means
+    /// nothing at `address` could be read.
+    pub fn read_memory(pid: Pid, address: u64, length: usize) -> Option<Vec<u8>> {
+        AnalyzedValue::get_memory(pid, address, length).map(|(data, _)| data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dump_regions() {
+        assert_eq!("0x13370000:256".parse::<DumpRegion>().unwrap(), DumpRegion { base: DumpBase::Address(0x13370000), length: 256 });
+        assert_eq!("RSP-0x10:64".parse::<DumpRegion>().unwrap().to_string(), "rsp-0x10:64");
+
+        assert!("rsp:0".parse::<DumpRegion>().is_err());
+        assert!("rsp".parse::<DumpRegion>().is_err());
+        assert!("xyz:16".parse::<DumpRegion>().is_err());
+    }
+
+    #[test]
+    fn dump_regions_are_limited() {
+        assert!(format!("rsp:{}", MAX_DUMP_LENGTH).parse::<DumpRegion>().is_ok());
+        assert!(format!("rsp:{}", MAX_DUMP_LENGTH + 1).parse::<DumpRegion>().is_err());
 
-        self.go(child, visibility)
+        // Far bigger (which used to abort, trying to allocate it)
+        assert!("rsp:0xffffffffffffffff".parse::<DumpRegion>().is_err());
     }
 }
@@ -1,43 +1,1520 @@
-///! Just a simple, serializable data structure that represents the output.
+//! Just a simple, serializable data structure that represents the output.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+use std::time::Duration;
 
+use log::warn;
 use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use simple_error::{bail, SimpleError, SimpleResult};
 
 use crate::analyzed_value::AnalyzedValue;
+use crate::coverage::CoverageRun;
+use crate::memory_map::Region;
+use crate::visibility_configuration::HARNESS_CODE_ADDRESS;
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+// Signals that mean the code itself crashed (as opposed to being stopped or
+// killed)
+const CRASH_SIGNALS: &[&str] = &["SIGSEGV", "SIGBUS", "SIGILL", "SIGFPE", "SIGABRT"];
+
+/// The most strings [`MandrakeOutput::collect_strings`] keeps
+pub const MAX_COLLECTED_STRINGS: usize = 1000;
+
+#[allow(clippy::upper_case_acronyms)]
+/// The formats [`MandrakeOutput::to_format`] can write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    JSON,
+    YAML,
+    PLAINTEXT,
+    PICKLE,
+    SUMMARY,
+    INDENTED,
+}
+
+impl FromStr for OutputFormat {
+    type Err = SimpleError;
+
+    fn from_str(input: &str) -> Result<OutputFormat, Self::Err> {
+        match &input.to_lowercase()[..] {
+            "json"   => Ok(OutputFormat::JSON),
+            "yaml"   => Ok(OutputFormat::YAML),
+            "pickle" => Ok(OutputFormat::PICKLE),
+            "plaintext" | "text" => Ok(OutputFormat::PLAINTEXT),
+            "summary-line" | "summary" => Ok(OutputFormat::SUMMARY),
+            "indented" => Ok(OutputFormat::INDENTED),
+
+            _       => bail!("Unknown format: {}", input),
+        }
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::JSON      => write!(f, "JSON"),
+            Self::YAML      => write!(f, "YAML"),
+            Self::PICKLE    => write!(f, "PICKLE"),
+            Self::PLAINTEXT => write!(f, "PLAINTEXT"),
+            Self::SUMMARY   => write!(f, "SUMMARY-LINE"),
+            Self::INDENTED  => write!(f, "INDENTED"),
+        }
+    }
+}
+
+/// The pickle protocol used by default (3 is what Python 3 reads natively)
+pub const DEFAULT_PICKLE_PROTOCOL: u8 = 3;
+
+/// Options for [`MandrakeOutput::to_format`] that only some formats use.
+#[derive(Debug, Clone)]
+pub struct FormatOptions {
+    /// The pickle protocol version (2 or 3)
+    pub pickle_protocol: u8,
+
+    /// Write pickle as a Python script that loads the data, instead of raw
+    /// pickle bytes
+    pub pickle_as_script: bool,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        Self {
+            pickle_protocol: DEFAULT_PICKLE_PROTOCOL,
+            pickle_as_script: false,
+        }
+    }
+}
+
+/// Extra details the kernel gives us about a signal
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SignalInfo {
+    /// Why the signal was sent (`si_code`, eg `SEGV_MAPERR` is 1)
+    pub code: i32,
+
+    /// The address that caused the fault (for SIGSEGV, SIGBUS, SIGILL, and
+    /// SIGFPE)
+    pub fault_address: Option<u64>,
+}
+
+/// Why the analysis ended.
+///
+/// This is serialized with a `kind` field saying which one it is (eg,
+/// `{"kind": "clean_exit", "code": 0}`); the `Display` text is the human
+/// readable version.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ExitReason {
+    /// The process exited on its own
+    CleanExit { code: i32 },
+
+    /// The code was stopped by a signal (usually a crash)
+    Signal {
+        signal: String,
+        address: u64,
+        instruction: Option<String>,
+        siginfo: Option<SignalInfo>,
+    },
+
+    /// The process was killed without stopping first, so all we know is where
+    /// it was last
+    Killed {
+        signal: String,
+        oom_killer: bool,
+        last_address: Option<u64>,
+    },
+
+    /// The code ran past the harness's alarm (SIGALRM)
+    Timeout { address: u64, instruction: Option<String> },
+
+    /// The user hit Ctrl-C
+    Interrupted { address: u64, instruction: Option<String> },
+
+    /// We reached the syscall we were asked to stop at (with what it was going
+    /// to run, if it's `execve`)
+    ReachedSyscall {
+        syscall: String,
+        address: u64,
+        instruction: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        execve: Option<ExecveArguments>,
+    },
+
+    /// We hit an int3 that was set to stop the analysis
+    Breakpoint { address: u64 },
+
+    /// We executed the maximum number of instructions
+    InstructionCap { max_instructions: usize },
+
+    /// The code used more CPU time than it was allowed
+    CpuTimeLimit {
+        limit_ms: u64,
+        used_ms: u64,
+        address: u64,
+        instruction: Option<String>,
+    },
+
+    /// The code got back to exactly the same state too many times (see
+    /// [`crate::mandrake::Mandrake::with_loop_detection`])
+    InfiniteLoop {
+        address: u64,
+        instruction: Option<String>,
+        repeats: usize,
+    },
+
+    /// A child process ended (SIGCHLD)
+    ChildExited,
+
+    /// The process was reaped by someone else, so we couldn't wait for it
+    LostProcess,
+}
+
+impl ExitReason {
+    /// The signal involved, if there was one (eg, `SIGSEGV`)
+    pub fn signal(&self) -> Option<&str> {
+        match self {
+            Self::Signal { signal, .. } | Self::Killed { signal, .. } => Some(signal),
+            Self::Timeout { .. }     => Some("SIGALRM"),
+            Self::Interrupted { .. } => Some("SIGINT"),
+            Self::ChildExited        => Some("SIGCHLD"),
+            _                        => None,
+        }
+    }
+
+    /// Whether the code crashed (as opposed to being stopped or killed)
+    pub fn is_crash(&self) -> bool {
+        matches!(self, Self::Signal { signal, .. } if CRASH_SIGNALS.contains(&signal.as_str()))
+    }
+
+    /// Whether the process ended on its own - it exited, crashed, or was
+    /// killed by something else - rather than Mandrake stopping it (or
+    /// stopping watching it).
+    pub fn is_completed(&self) -> bool {
+        matches!(self, Self::CleanExit { .. } | Self::Signal { .. } | Self::Killed { .. })
+    }
+
+    /// Where the code was when it ended, if we know
+    pub fn address(&self) -> Option<u64> {
+        match self {
+            Self::Signal { address, .. }
+            | Self::Timeout { address, .. }
+            | Self::Interrupted { address, .. }
+            | Self::ReachedSyscall { address, .. }
+            | Self::CpuTimeLimit { address, .. }
+            | Self::InfiniteLoop { address, .. }
+            | Self::Breakpoint { address } => Some(*address),
+            Self::Killed { last_address, .. } => *last_address,
+            _ => None,
+        }
+    }
+}
+
+/// Show an address with its instruction (like rip is shown)
+fn location(address: u64, instruction: &Option<String>) -> String {
+    format!("0x{:08x} {}", address, instruction.as_deref().unwrap_or("(bad)"))
+}
+
+impl fmt::Display for ExitReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::CleanExit { code } => write!(f, "Process exited cleanly with exit code {}", code),
+            Self::Signal { signal, address, instruction, .. } => {
+                let at = location(*address, instruction);
+
+                match signal.as_str() {
+                    "SIGABRT" => write!(f, "Execution crashed with an abort (SIGABRT) @ {}", at),
+                    "SIGBUS"  => write!(f, "Execution crashed with a bus error (bad memory access) (SIGBUS) @ {}", at),
+                    "SIGFPE"  => write!(f, "Execution crashed with a floating point error (SIGFPE) @ {}", at),
+                    "SIGILL"  => write!(f, "Execution crashed with an illegal instruction (SIGILL) @ {}", at),
+                    "SIGKILL" => write!(f, "Execution was killed (SIGKILL) @ {}", at),
+                    "SIGSEGV" => write!(f, "Execution crashed with a segmentation fault (SIGSEGV) @ {}", at),
+                    "SIGTERM" => write!(f, "Execution was terminated (SIGTERM) @ {}", at),
+                    "SIGINT"  => write!(f, "Execution interrupted (SIGINT) @ {}", at),
+                    _         => write!(f, "Execution stopped by unexpected signal: {}", signal),
+                }
+            },
+            Self::Killed { signal, oom_killer, last_address } => {
+                match oom_killer {
+                    true  => write!(f, "Process was killed by the OOM killer ({})", signal)?,
+                    false => write!(f, "Process was killed externally ({})", signal)?,
+                };
+
+                match last_address {
+                    Some(address) => write!(f, " (last seen @ 0x{:08x})", address),
+                    None => Ok(()),
+                }
+            },
+            Self::Timeout { address, instruction } => write!(f, "Execution timed out (SIGALRM) @ {}", location(*address, instruction)),
+            Self::Interrupted { address, instruction } => write!(f, "Execution interrupted (SIGINT) @ {}", location(*address, instruction)),
+            Self::ReachedSyscall { syscall, address, instruction, .. } => write!(f, "Execution stopped at syscall `{}` @ {}", syscall, location(*address, instruction)),
+            Self::Breakpoint { address } => write!(f, "Hit int3 breakpoint @ 0x{:08x}", address),
+            Self::InstructionCap { max_instructions } => write!(f, "Execution stopped at instruction cap (max instructions: {})", max_instructions),
+            Self::CpuTimeLimit { limit_ms, used_ms, address, instruction } => write!(f, "Execution stopped after using {}ms of CPU time (limit: {}ms) @ {}", used_ms, limit_ms, location(*address, instruction)),
+            Self::InfiniteLoop { address, instruction, repeats } => write!(f, "Detected infinite loop at {} (state repeated {} times)", location(*address, instruction), repeats),
+            Self::ChildExited => write!(f, "Execution ended when child process ended (SIGCHLD)"),
+            Self::LostProcess => write!(f, "Lost track of the process (it was no longer there to wait for)"),
+        }
+    }
+}
+
+/// A syscall the code made (see [`MandrakeOutput::syscalls`]).
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SyscallEvent {
+    /// The index of the instruction that made it (like [`HistoryEntry`]'s
+    /// `index`)
+    pub index: usize,
+    pub address: u64,
+    pub number: u64,
+    pub name: String,
+
+    /// Which interface it was made with: `x86_64` (`syscall`) or `x86`
+    /// (`int 0x80` / `sysenter`)
+    pub abi: String,
+
+    /// The decoded arguments, like ``filename (rdi) = `/etc/passwd` ``
+    pub arguments: Vec<String>,
+
+    /// What it returned (rax), if the process was still around to see it
+    pub return_value: Option<u64>,
+
+    /// The return value decoded, like `-2 (ENOENT)`
+    pub returned: Option<String>,
+
+    /// The memory it mapped (for `mmap`), like `mapped 0x7f0000000000 len
+    /// 0x1000 r-x`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mapping: Option<String>,
+
+    /// The index of the first instruction that ran in the memory it mapped,
+    /// if it was mapped executable and anything ran there
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_executed: Option<usize>,
+}
+
+/// A string seen in a register (or something it points to) during the run
+/// (see [`MandrakeOutput::strings`]).
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct CollectedString {
+    pub string: String,
+
+    /// The index of the first instruction it was seen at
+    pub index: usize,
+
+    /// Where it was first seen - a register, with a `*` for each pointer
+    /// followed to get to it (eg, `*rsi`)
+    pub register: String,
+}
+
+/// Something that stopped being saved in the history, to keep it under
+/// `--max-trace-bytes` (see [`MandrakeOutput::shed`]).
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ShedDetail {
+    /// What was left out: `memory` (the memory snippits - including from the
+    /// entries that were already saved), or `registers` (everything but rip)
+    pub shed: String,
+
+    /// The index of the instruction it happened at
+    pub index: usize,
+
+    /// About how big the history was (in bytes) when it happened
+    pub trace_bytes: usize,
+}
+
+/// A signal that was sent to the process at a chosen instruction (see
+/// `--inject-signal`).
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct InjectedSignal {
+    pub signal: String,
+
+    /// The index of the instruction it was sent before
+    pub index: usize,
+
+    /// Where the process was when it was sent
+    pub address: u64,
+
+    /// Where the process's handler started (`None` if it didn't have one,
+    /// so the signal's default action happened instead)
+    pub handler: Option<u64>,
+}
+
+/// How long was spent tracing one kind of code (see [`TimeBreakdown`]).
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TimeBucket {
+    pub name: String,
+    pub instructions: usize,
+    pub seconds: f64,
+
+    /// The share of the total time, from 0 to 100
+    pub percent: f64,
+}
+
+impl TimeBucket {
+    fn new(name: &str) -> Self {
+        Self { name: name.to_string(), instructions: 0, seconds: 0.0, percent: 0.0 }
+    }
+
+    fn add(&mut self, elapsed: Duration) {
+        self.instructions += 1;
+        self.seconds += elapsed.as_secs_f64();
+    }
+}
+
+/// Where the time tracing went (see `--time-breakdown`): the time from each
+/// instruction's stop to the next one is counted against that instruction,
+/// split by whether it was visible, and by the module it was in.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TimeBreakdown {
+    pub visible: TimeBucket,
+    pub hidden: TimeBucket,
+
+    /// Each module (eg, `libc.so.6`), the most time first
+    pub modules: Vec<TimeBucket>,
+}
+
+impl Default for TimeBreakdown {
+    fn default() -> Self {
+        Self {
+            visible: TimeBucket::new("visible"),
+            hidden:  TimeBucket::new("hidden"),
+            modules: vec![],
+        }
+    }
+}
+
+impl TimeBreakdown {
+    /// Count the time an instruction took.
+    pub fn add(&mut self, module: &str, visible: bool, elapsed: Duration) {
+        match visible {
+            true  => self.visible.add(elapsed),
+            false => self.hidden.add(elapsed),
+        }
+
+        match self.modules.iter_mut().find(|bucket| bucket.name == module) {
+            Some(bucket) => bucket.add(elapsed),
+            None => {
+                let mut bucket = TimeBucket::new(module);
+                bucket.add(elapsed);
+                self.modules.push(bucket);
+            },
+        }
+    }
+
+    /// Work out the percentages, once everything's been added.
+    pub fn finish(&mut self) {
+        let total = self.visible.seconds + self.hidden.seconds;
+        if total > 0.0 {
+            for bucket in std::iter::once(&mut self.visible).chain(std::iter::once(&mut self.hidden)).chain(self.modules.iter_mut()) {
+                bucket.percent = bucket.seconds * 100.0 / total;
+            }
+        }
+
+        self.modules.sort_by(|a, b| b.seconds.total_cmp(&a.seconds));
+    }
+}
+
+/// Like `libc.so.6 78% / ld-linux-x86-64.so.2 15% / tp 7%`
+impl fmt::Display for TimeBreakdown {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let modules: Vec<String> = self.modules.iter().map(|bucket| format!("{} {:.0}%", bucket.name, bucket.percent)).collect();
+
+        write!(f, "{} (visible {:.0}%, hidden {:.0}%)", modules.join(" / "), self.visible.percent, self.hidden.percent)
+    }
+}
+
+/// The program an `execve` was going to run, read in full before it ran.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ExecveArguments {
+    pub filename: String,
+    pub argv: Vec<String>,
+    pub envp: Vec<String>,
+}
+
+/// A single logged instruction.
+///
+/// The registers are flattened into the entry, so each entry looks like a map
+/// of register names (plus a few extra fields).
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct HistoryEntry {
+    /// The number of instructions executed before this one
+    pub index: usize,
+
+    /// The execution phase this instruction ran in (see `--phase`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub phase: Option<String>,
+
+    /// If this instruction calls `execve`, what it runs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub execve: Option<ExecveArguments>,
+
+    /// How many times this instruction ran in a row, if the repeats were
+    /// collapsed into this entry (see [`MandrakeOutput::collapse_loops`])
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repeat_count: Option<usize>,
+
+    /// The signal that was delivered to the process here, instead of ending
+    /// the trace (see `--deliver-signal`) or because it was injected (see
+    /// `--inject-signal`); this instruction didn't run yet, so the next entry
+    /// has the same index
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signal: Option<String>,
+
+    /// How many instructions ran since the previous entry without being
+    /// logged (see `--show-gaps`); not set if there weren't any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub skipped_since_last: Option<usize>,
+
+    /// Where the instruction right before this one was, if it wasn't logged
+    /// (see `--show-gaps`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub came_from: Option<u64>,
+
+    #[serde(flatten)]
+    pub registers: HashMap<String, AnalyzedValue>,
+}
+
+impl HistoryEntry {
+    pub fn new(index: usize, registers: HashMap<String, AnalyzedValue>) -> Self {
+        Self {
+            index,
+            phase: None,
+            execve: None,
+            repeat_count: None,
+            signal: None,
+            skipped_since_last: None,
+            came_from: None,
+            registers,
+        }
+    }
+
+    /// Label the entry with the phase it ran in
+    pub fn with_phase(mut self, phase: Option<String>) -> Self {
+        self.phase = phase;
+        self
+    }
+
+    /// Attach what an `execve` is going to run
+    pub fn with_execve(mut self, execve: Option<ExecveArguments>) -> Self {
+        self.execve = execve;
+        self
+    }
+
+    /// Mark the entry as where a signal was delivered
+    pub fn with_signal(mut self, signal: Option<String>) -> Self {
+        self.signal = signal;
+        self
+    }
+
+    /// Mark the entry as coming after instructions that weren't logged (how
+    /// many, and where the last one was)
+    pub fn with_gap(mut self, gap: Option<(usize, Option<u64>)>) -> Self {
+        if let Some((skipped, came_from)) = gap {
+            self.skipped_since_last = Some(skipped);
+            self.came_from = came_from;
+        }
+        self
+    }
+
+    /// Roughly how much memory the entry is using.
+    pub fn approximate_size(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + self.registers.iter().map(|(name, value)| name.len() + value.approximate_size()).sum::<usize>()
+            + self.execve.as_ref().map_or(0, |execve| {
+                execve.filename.len() + execve.argv.iter().chain(&execve.envp).map(String::len).sum::<usize>()
+            })
+    }
+
+    /// Work out the entropy of every value's memory (see
+    /// [`AnalyzedValue::set_memory_entropy`])
+    pub fn set_memory_entropy(&mut self) {
+        for value in self.registers.values_mut() {
+            value.set_memory_entropy();
+        }
+    }
+
+    /// Drop the memory snippits from every register (and what they point to).
+    pub fn strip_memory(&mut self) {
+        for value in self.registers.values_mut() {
+            value.for_each_mut(&mut |value| value.memory = None);
+        }
+    }
+
+    /// How to show that an entry was repeated, after its instruction (eg,
+    /// ` (repeated 16 times)`), or nothing if it wasn't
+    pub fn repeat_suffix(&self) -> String {
+        match self.repeat_count {
+            Some(count) => format!(" (repeated {} times)", count),
+            None => String::new(),
+        }
+    }
+}
+
+/// The settings the analysis ran with, so consumers know why fields might be
+/// missing.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct OutputConfig {
+    pub snippit_length: usize,
+
+    /// How many bytes were saved at rip, at rsp and rbp, and at the other
+    /// registers (the snippit length unless they were set separately)
+    #[serde(default)]
+    pub rip_snippit_length: usize,
+    #[serde(default)]
+    pub data_snippit_length: usize,
+    #[serde(default)]
+    pub stack_snippit_length: usize,
+
+    /// How much memory was read at each value to look for strings and code
+    #[serde(default)]
+    pub analysis_window: usize,
+
+    /// How many bytes of each syscall argument's buffer were shown
+    #[serde(default)]
+    pub syscall_snippit_length: usize,
+
+    /// Syscalls whose arguments weren't decoded (just shown as numbers)
+    #[serde(default)]
+    pub skip_syscall_detail: Vec<String>,
+    pub minimum_viable_string: usize,
+    pub max_string_length: usize,
+    pub max_instructions: Option<usize>,
+
+    /// Signals that were passed to the process's handlers instead of ending
+    /// the trace
+    #[serde(default)]
+    pub deliver_signals: Vec<String>,
+
+    /// Signals that ended the trace even though Mandrake would normally
+    /// handle them (or deliver them)
+    #[serde(default)]
+    pub fatal_signals: Vec<String>,
+
+    /// Signals that were sent at chosen instructions, as `SIGNAL@INDEX`
+    #[serde(default)]
+    pub inject_signals: Vec<String>,
+
+    /// The CPU time the code was allowed, in milliseconds
+    #[serde(default)]
+    pub cpu_time_limit_ms: Option<u64>,
+
+    /// The history was kept to about this many bytes (in memory)
+    #[serde(default)]
+    pub max_trace_bytes: usize,
+
+    /// How many times the same state could repeat before it was called an
+    /// infinite loop (if loops were being detected)
+    #[serde(default)]
+    pub loop_repeats: Option<usize>,
+
+    /// Only every Nth instruction was logged
+    #[serde(default)]
+    pub sample_rate: usize,
+
+    /// If set, only instructions with these mnemonics were logged
+    #[serde(default)]
+    pub only_mnemonics: Vec<String>,
+
+    /// Only the last N logged instructions were kept
+    #[serde(default)]
+    pub tail: Option<usize>,
+    pub trace_detail: String,
+
+    /// Which registers were analyzed at each step ("all", or "smart" for
+    /// only the ones the instruction used)
+    #[serde(default)]
+    pub analyze: String,
+
+    /// The registers that were fully analyzed (`None` means all of them)
+    pub registers: Option<Vec<String>>,
+
+    /// The bytes the code was checked for (see `bad_byte_offsets`), as hex
+    #[serde(default)]
+    pub bad_bytes: Vec<String>,
+
+    /// If set, memory snippits were dropped from the output
+    pub no_memory: bool,
+
+    /// If set, every instruction that ran was recorded in `full_coverage`
+    #[serde(default)]
+    pub full_coverage: bool,
+
+    /// If set, entries after instructions that weren't logged say how many
+    /// were skipped, and where they came from
+    #[serde(default)]
+    pub show_gaps: bool,
+
+    /// If set, address space randomization was turned off for the process,
+    /// so the addresses are the same every run
+    #[serde(default)]
+    pub disable_aslr: bool,
+
+    /// If set, the process was run the same way every time (see
+    /// `--deterministic`)
+    #[serde(default)]
+    pub deterministic: bool,
+
+    /// If set, each history entry only has the registers that changed since
+    /// the previous one (plus rip) - see [`MandrakeOutput::densify`]
+    #[serde(default)]
+    pub delta_history: bool,
+
+    /// If set, runs of the same instruction are collapsed into one entry
+    /// (see [`MandrakeOutput::collapse_loops`])
+    #[serde(default)]
+    pub collapse_loops: bool,
+
+    /// The file that extra syscall definitions were loaded from, if any
+    #[serde(default)]
+    pub syscalls_csv: Option<String>,
+}
+
+/// Snapshots of the process's memory map, so addresses can be placed (stack,
+/// heap, which library, ...) after the process is gone.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct MemoryMaps {
+    /// When the trace started (at the first stop)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start: Option<Vec<Region>>,
+
+    /// When the trace ended, just before the process was killed (or, if the
+    /// map was being kept up to date during the trace, that copy - which is
+    /// still there after the process exits)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end: Option<Vec<Region>>,
+}
+
+/// Memory that was saved when the trace ended (see `--dump-region`).
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct DumpedMemory {
+    /// What was asked for (eg, `rsp-0x10:64`)
+    pub region: String,
+
+    /// Where it was (`None` if it depends on a register we couldn't read)
+    pub address: Option<u64>,
+
+    /// How many bytes were asked for
+    pub length: usize,
+
+    /// How many bytes could be read, from the start (less than `length` if
+    /// the region runs into unreadable memory)
+    pub readable: usize,
+
+    /// The bytes, as hex (unless they were written to `file`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<String>,
+
+    /// The file the bytes were written to (see `--dump-dir`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+
+    /// Why none of it was saved, if it wasn't
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl DumpedMemory {
+    pub fn new(region: String, length: usize) -> Self {
+        Self {
+            region,
+            address: None,
+            length,
+            readable: 0,
+            data: None,
+            file: None,
+            error: None,
+        }
+    }
+}
+
+/// One of the "bad bytes" (see `--bad-bytes`), where it is in the code.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct BadByte {
+    pub offset: usize,
+
+    /// The byte, as hex
+    pub byte: String,
+
+    /// Whether it's part of an instruction that ran (as opposed to data, or
+    /// code that was never reached), going by the full coverage
+    pub executed: bool,
+}
+
+/// Some data that was analyzed, identified by its hash and length, and also
+/// included as hex if it's small enough.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct InputData {
+    pub length: usize,
+    pub sha256: String,
+
+    /// The data, as hex (left out if it's bigger than `--input-hex-limit`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hex: Option<String>,
+}
+
+impl InputData {
+    /// Describe some data, including it as hex only if it's no longer than
+    /// `hex_limit`.
+    pub fn new(data: &[u8], hex_limit: Option<usize>) -> Self {
+        Self {
+            length: data.len(),
+            sha256: hex::encode(Sha256::digest(data)),
+            hex: hex_limit.filter(|limit| data.len() <= *limit).map(|_| hex::encode(data)),
+        }
+    }
+}
+
+/// What was analyzed, so a trace can be matched up with its payload later.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct AnalyzedInput {
+    /// The raw code (raw code only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<InputData>,
+
+    /// The executable's path, as given (ELF only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+
+    /// The executable itself, which is never included as hex (ELF only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<InputData>,
+
+    /// The arguments it was run with, starting with argv[0] (its path, or
+    /// just its file name with `--deterministic`), as Mandrake passed them -
+    /// the top-level `argv` is what the process found on its stack (ELF only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub argv: Option<Vec<String>>,
+
+    /// What was fed to its standard in, if anything, with its hash (it's
+    /// also in the top-level `stdin`, as hex without a size limit) (ELF only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stdin: Option<InputData>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct MandrakeOutput {
+    pub config: OutputConfig,
+
+    /// What was analyzed (see [`AnalyzedInput`])
+    #[serde(default)]
+    pub input: AnalyzedInput,
+
     pub starting_address: Option<u64>,
     pub instructions_executed: usize,
 
+    /// How many bytes of code were loaded by the harness (0 for an ELF), so
+    /// the user's code is `HARNESS_CODE_ADDRESS..+code_length`
+    pub code_length: usize,
+
+    /// Where the code has any of the "bad bytes" (see `--bad-bytes`), as
+    /// offsets into the code (raw code only)
+    #[serde(default)]
+    pub bad_byte_offsets: Vec<usize>,
+
+    /// Each bad byte in the code, and whether it was part of an instruction
+    /// that ran (raw code only)
+    #[serde(default)]
+    pub badchar_report: Vec<BadByte>,
+
+    /// The entropy of the code, in bits per byte (raw code only; see
+    /// [`crate::analyzed_value::shannon_entropy`])
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code_entropy: Option<f64>,
+
+    /// Where the code being analyzed was loaded: where the harness put it, or
+    /// the executable's lowest mapping for an ELF
+    #[serde(default)]
+    pub image_base: Option<u64>,
+
+    /// Always true - this is only here for things that read older output;
+    /// use `completed` to see how the run ended
     pub success: bool,
+
+    /// True if the process ended on its own (it exited or crashed), false if
+    /// Mandrake cut it short (eg, the instruction cap, a timeout, or a
+    /// `--stop-at-syscall`) - see [`ExitReason::is_completed`]
+    #[serde(default)]
+    pub completed: bool,
     pub pid: u32,
-    pub history: Vec<HashMap<String, AnalyzedValue>>,
+    pub history: Vec<HistoryEntry>,
+
+    /// Every syscall the code made, in order - including the ones made by
+    /// instructions that weren't logged (eg, hidden or not sampled)
+    #[serde(default)]
+    pub syscalls: Vec<SyscallEvent>,
+
+    /// Every unique string seen in the history, in the order they were first
+    /// seen (see [`MandrakeOutput::collect_strings`])
+    #[serde(default)]
+    pub strings: Vec<CollectedString>,
+
+    /// Says how many strings were left out, if there were too many to keep
+    #[serde(default)]
+    pub strings_truncated: Option<String>,
+
+    /// What stopped being saved in the history (and when), if it got too big
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub shed: Vec<ShedDetail>,
+
+    /// Every instruction that was traced, including ones that weren't visible
+    /// or logged (but not code that ran freely, eg between int3s), as runs of
+    /// instructions that ran one after another (see [`crate::coverage`])
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub full_coverage: Vec<CoverageRun>,
+
+    /// Where the time tracing went (if `--time-breakdown` was on)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time_breakdown: Option<TimeBreakdown>,
+
+    /// How many register values got a full analysis (their memory read and
+    /// searched for strings, code, and pointers) - `--analyze smart` cuts
+    /// this down
+    #[serde(default)]
+    pub deep_analyses: usize,
+
+    /// Signals that were sent with `--inject-signal`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub injected_signals: Vec<InjectedSignal>,
+
+    /// The standard in that was passed to the process, as hex (ELF only)
+    #[serde(default)]
+    pub stdin: Option<String>,
     pub stdout: Option<String>,
     pub stderr: Option<String>,
-    pub exit_reason: Option<String>,
+
+    /// What the harness printed about itself (eg, if it couldn't load the
+    /// code), kept apart from the code's stderr (raw code only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub harness_stderr: Option<String>,
+    pub exit_reason: Option<ExitReason>,
+
+    /// The exit reason as text (eg, "Process exited cleanly with exit code
+    /// 0"), for people and for scripts that just want to show it
+    #[serde(default)]
+    pub exit_message: Option<String>,
     pub exit_code: Option<i32>,
+
+    /// What the last `exit` or `exit_group` syscall was called with, when
+    /// the code made one; the low byte of it should be `exit_code` (returning
+    /// from `main` ends up here too, by way of libc)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_syscall_status: Option<i32>,
+    pub proc_status: Option<HashMap<String, String>>,
+
+    /// Every register, analyzed, when the trace ended (or just before the
+    /// process exited) - whether or not that instruction was logged
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub final_registers: Option<HashMap<String, AnalyzedValue>>,
+
+    /// The process's memory map when the trace started and when it ended
+    #[serde(default)]
+    pub memory_maps: MemoryMaps,
+
+    /// The arguments / environment the process saw on its stack (ELF only)
+    pub argv: Option<Vec<String>>,
+    pub envp: Option<Vec<String>>,
+
+    /// The manifest of the memory saved when the code crashed (see
+    /// [`crate::crash_dump`])
+    #[serde(default)]
+    pub crash_dump: Option<String>,
+
+    /// Why the crash dump couldn't be saved, if it couldn't
+    #[serde(default)]
+    pub crash_dump_error: Option<String>,
+
+    /// The memory saved with `--dump-region`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dumped_regions: Vec<DumpedMemory>,
+
+    /// Deduplicated memory snippits, keyed by hash (see
+    /// [`MandrakeOutput::dedup_memory`])
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub memory_pool: HashMap<String, Vec<u8>>,
 }
 
 impl MandrakeOutput {
     pub fn new(pid: u32) -> Self {
         MandrakeOutput {
+            config: OutputConfig::default(),
+            input: AnalyzedInput::default(),
             starting_address: None,
             instructions_executed: 0,
+            code_length: 0,
+            bad_byte_offsets: vec![],
+            badchar_report: vec![],
+            code_entropy: None,
+            image_base: None,
 
             success: true,
-            pid: pid,
+            completed: false,
+            pid,
             history: vec![],
+            syscalls: vec![],
+            strings: vec![],
+            strings_truncated: None,
+            shed: vec![],
+            full_coverage: vec![],
+            time_breakdown: None,
+            deep_analyses: 0,
+            injected_signals: vec![],
+            stdin: None,
             stdout: None,
             stderr: None,
+            harness_stderr: None,
             exit_reason: None,
+            exit_message: None,
             exit_code: None,
+            exit_syscall_status: None,
+            proc_status: None,
+            final_registers: None,
+            memory_maps: MemoryMaps::default(),
+            argv: None,
+            envp: None,
+            crash_dump: None,
+            crash_dump_error: None,
+            dumped_regions: vec![],
+            memory_pool: HashMap::new(),
+        }
+    }
+
+    /// Record why the analysis ended (and the text version of it).
+    pub fn set_exit_reason(&mut self, exit_reason: ExitReason) {
+        self.exit_message = Some(exit_reason.to_string());
+        self.completed = exit_reason.is_completed();
+        self.exit_reason = Some(exit_reason);
+    }
+
+    /// Check whether an address is inside the code the harness loaded (always
+    /// false for an ELF).
+    pub fn is_in_code(&self, address: u64) -> bool {
+        address >= HARNESS_CODE_ADDRESS && address < HARNESS_CODE_ADDRESS + self.code_length as u64
+    }
+
+    /// Summarize how the analysis ended on one line, for scripts and CI (eg,
+    /// `ok exit=0 insns=42` or `crash sig=SIGSEGV @0x13370020 insns=15`).
+    ///
+    /// The first word is `ok` (exited with 0), `fail` (exited with anything
+    /// else), `crash`, `timeout` (including running out of CPU time),
+    /// `killed`, or `stopped` (for anything else, like hitting the instruction
+    /// cap, Ctrl-C, or a child process ending).
+    pub fn summary_line(&self) -> String {
+        let signal = self.exit_reason.as_ref().and_then(|r| r.signal());
+        let address = self.exit_reason.as_ref().and_then(|r| r.address());
+
+        // Go by how it ended, not the exit code - a process killed while it
+        // was exiting still has one
+        let status = match (&self.exit_reason, signal) {
+            (Some(ExitReason::CleanExit { code: 0 }), _) => "ok",
+            (Some(ExitReason::CleanExit { .. }), _) => "fail",
+            (_, Some(signal)) if CRASH_SIGNALS.contains(&signal) => "crash",
+            (_, Some("SIGALRM")) => "timeout",
+            (Some(ExitReason::Interrupted { .. } | ExitReason::ChildExited), _) => "stopped",
+            (_, Some(_)) => "killed",
+            (Some(ExitReason::CpuTimeLimit { .. } | ExitReason::InfiniteLoop { .. }), None) => "timeout",
+            (_, None) => "stopped",
+        };
+
+        let mut out = vec![status.to_string()];
+        if let Some(code) = self.exit_code {
+            out.push(format!("exit={}", code));
+        }
+        if let Some(signal) = signal {
+            out.push(format!("sig={}", signal));
+        }
+        if let Some(address) = address {
+            out.push(format!("@0x{:08x}", address));
+        }
+        out.push(format!("insns={}", self.instructions_executed));
+
+        out.join(" ")
+    }
+
+    /// How deeply nested in calls each history entry is: every `call` makes
+    /// the instructions after it one deeper, and every `ret` one shallower.
+    /// The depth starts at 0 and never goes below it (returning to the
+    /// harness, or from code that was called before logging started).
+    pub fn call_depths(&self) -> Vec<usize> {
+        let mut depth: usize = 0;
+
+        self.history.iter().map(|entry| {
+            let current = depth;
+            match entry.registers.get("rip").and_then(|rip| rip.flow_control.as_deref()) {
+                Some("Call") | Some("IndirectCall") => depth += 1,
+                Some("Return") => depth = depth.saturating_sub(1),
+                _ => (),
+            }
+
+            // The call itself is at the caller's depth, and the return at
+            // the depth of the function it's returning from
+            current
+        }).collect()
+    }
+
+    /// List the instructions in the order they ran, indented two spaces for
+    /// each level of call depth (see [`MandrakeOutput::call_depths`]).
+    pub fn indented_listing(&self) -> String {
+        self.history.iter().zip(self.call_depths()).filter_map(|(entry, depth)| {
+            entry.registers.get("rip").map(|rip| format!("{}{}{}", "  ".repeat(depth), rip, entry.repeat_suffix()))
+        }).collect::<Vec<String>>().join("\n")
+    }
+
+    /// Compare this run with another run of the same analysis, and describe
+    /// the first way they differ (None means they're the same).
+    ///
+    /// Things that always change between runs, like the pid and the
+    /// process's memory usage, are ignored.
+    pub fn first_difference(&self, other: &MandrakeOutput) -> Option<String> {
+        for (i, (a, b)) in self.history.iter().zip(&other.history).enumerate() {
+            if a.index != b.index {
+                return Some(format!("History entry {} is instruction {} in one run, but {} in the other", i, a.index, b.index));
+            }
+
+            if a.repeat_count != b.repeat_count {
+                return Some(format!("History entry {} (instruction {}) is repeated {} time(s) in one run, but {} in the other", i, a.index, a.repeat_count.unwrap_or(1), b.repeat_count.unwrap_or(1)));
+            }
+
+            if a.signal != b.signal {
+                return Some(format!("History entry {} (instruction {}) delivered signal {:?} in one run, but {:?} in the other", i, a.index, a.signal, b.signal));
+            }
+
+            if a.phase != b.phase {
+                return Some(format!("History entry {} (instruction {}) is in phase {:?} in one run, but {:?} in the other", i, a.index, a.phase, b.phase));
+            }
+
+            // Sort the registers so the same difference is always reported
+            let mut registers: Vec<&String> = a.registers.keys().chain(b.registers.keys()).collect();
+            registers.sort();
+            registers.dedup();
+
+            for register in registers {
+                let difference = match (a.registers.get(register), b.registers.get(register)) {
+                    (Some(x), Some(y)) if x == y => continue,
+                    (Some(x), Some(y)) if x.value != y.value => format!("{} is 0x{:x} in one run, but 0x{:x} in the other", register, x.value, y.value),
+                    (Some(x), Some(_)) => format!("{} is 0x{:x} in both runs, but what it points to is different", register, x.value),
+                    _ => format!("{} is only in one run", register),
+                };
+
+                return Some(format!("History entry {} (instruction {}): {}", i, a.index, difference));
+            }
+        }
+
+        if self.history.len() != other.history.len() {
+            return Some(format!("One run logged {} instructions, but the other logged {}", self.history.len(), other.history.len()));
+        }
+
+        if self.instructions_executed != other.instructions_executed {
+            return Some(format!("One run executed {} instructions, but the other executed {}", self.instructions_executed, other.instructions_executed));
+        }
+
+        if self.exit_reason != other.exit_reason || self.exit_code != other.exit_code {
+            return Some(format!("One run ended with {:?}, but the other ended with {:?}", self.exit_message, other.exit_message));
+        }
+
+        if self.stdout != other.stdout || self.stderr != other.stderr {
+            return Some("The runs printed different output".to_string());
+        }
+
+        None
+    }
+
+    /// Remove every register that didn't change since the previous entry
+    /// (except rip), so the first entry is the only full snapshot.
+    pub fn delta_encode(&mut self) {
+        if self.config.delta_history {
+            return;
+        }
+
+        let mut previous: Option<HashMap<String, AnalyzedValue>> = None;
+        for entry in &mut self.history {
+            let full = entry.registers.clone();
+
+            if let Some(previous) = &previous {
+                entry.registers.retain(|name, value| name == "rip" || previous.get(name) != Some(value));
+            }
+
+            previous = Some(full);
+        }
+
+        self.config.delta_history = true;
+    }
+
+    /// The opposite of [`MandrakeOutput::delta_encode`] - rebuild a full
+    /// snapshot of the registers for every entry.
+    pub fn densify(&mut self) {
+        if !self.config.delta_history {
+            return;
+        }
+
+        let mut current: HashMap<String, AnalyzedValue> = HashMap::new();
+        for entry in &mut self.history {
+            current.extend(entry.registers.drain());
+            entry.registers = current.clone();
+        }
+
+        self.config.delta_history = false;
+    }
+
+    /// Collapse runs of the same instruction running over and over (like
+    /// `jmp $` or `rep movsb`) into the first entry, with a `repeat_count`.
+    ///
+    /// Entries are only the same if they're at the same rip and each register
+    /// either stays the same or changes by the same amount every time (like a
+    /// counter, or `rep movsb`'s rcx / rsi / rdi), so the registers at any
+    /// repeat can still be worked out from the first two. This has to run on
+    /// the full history (before [`MandrakeOutput::delta_encode`]).
+    pub fn collapse_loops(&mut self) {
+        if self.config.delta_history {
+            return;
+        }
+
+        // How much a register changed between two entries
+        fn step(from: &HistoryEntry, to: &HistoryEntry, register: &str) -> Option<u64> {
+            Some(to.registers.get(register)?.value.wrapping_sub(from.registers.get(register)?.value))
+        }
+
+        let mut out: Vec<HistoryEntry> = vec![];
+
+        // The entry that the current run is collapsed into, the last entry in
+        // the run, and how much each register changes per repeat
+        let mut run: Option<(HistoryEntry, HistoryEntry, HashMap<String, u64>)> = None;
+
+        for entry in self.history.drain(..) {
+            if let Some((first, last, steps)) = &mut run {
+                let same = entry.phase == last.phase
+                    && entry.execve.is_none()
+                    && entry.signal.is_none()
+                    && last.signal.is_none()
+                    && entry.skipped_since_last.is_none()
+                    && entry.registers.len() == last.registers.len()
+                    && step(last, &entry, "rip") == Some(0)
+                    && entry.registers.keys().all(|register| {
+                        match step(last, &entry, register) {
+                            // The first repeat decides how much each register
+                            // changes
+                            Some(current) => *steps.entry(register.clone()).or_insert(current) == current,
+                            None => false,
+                        }
+                    });
+
+                if same {
+                    *first.repeat_count.get_or_insert(1) += 1;
+                    *last = entry;
+                    continue;
+                }
+
+                out.push(run.take().unwrap().0);
+            }
+
+            run = Some((entry.clone(), entry, HashMap::new()));
+        }
+
+        if let Some((first, _, _)) = run {
+            out.push(first);
+        }
+
+        self.history = out;
+        self.config.collapse_loops = true;
+    }
+
+    /// Gather every unique string (at least `minimum_viable_string`
+    /// characters long) from the history into `strings`, keeping the first
+    /// [`MAX_COLLECTED_STRINGS`] of them. This has to run before
+    /// [`MandrakeOutput::delta_encode`] can hide where a string was first seen.
+    pub fn collect_strings(&mut self) {
+        let mut seen: HashSet<String> = HashSet::new();
+        let mut strings: Vec<CollectedString> = vec![];
+        let mut omitted = 0;
+
+        for entry in &self.history {
+            // Go through the registers in a consistent order, so the same run
+            // always credits a string to the same register
+            let mut registers: Vec<(&String, &AnalyzedValue)> = entry.registers.iter().collect();
+            registers.sort_by_key(|(name, _)| name.as_str());
+
+            for (name, value) in registers {
+                let mut register = name.clone();
+
+                value.for_each(&mut |value| {
+                    if let Some(string) = &value.as_string {
+                        if string.chars().count() >= self.config.minimum_viable_string && seen.insert(string.clone()) {
+                            match strings.len() < MAX_COLLECTED_STRINGS {
+                                true  => strings.push(CollectedString { string: string.clone(), index: entry.index, register: register.clone() }),
+                                false => omitted += 1,
+                            }
+                        }
+                    }
+
+                    register.insert(0, '*');
+                });
+            }
+        }
+
+        self.strings = strings;
+        self.strings_truncated = match omitted {
+            0 => None,
+            _ => Some(format!("Only the first {} strings were kept ({} more were seen)", MAX_COLLECTED_STRINGS, omitted)),
+        };
+    }
+
+    /// Move every memory snippit into `memory_pool`, replacing it with a
+    /// reference (`memory_ref`) so repeated snippits are only stored once.
+    /// The key is the snippit's SHA-256 (as hex), so it's the same for
+    /// anything that reads the pool later.
+    pub fn dedup_memory(&mut self) {
+        for entry in &mut self.history {
+            for value in entry.registers.values_mut() {
+                value.for_each_mut(&mut |value| {
+                    if let Some(memory) = value.memory.take() {
+                        let key = hex::encode(Sha256::digest(&memory));
+
+                        self.memory_pool.entry(key.clone()).or_insert(memory);
+                        value.memory_ref = Some(key);
+                    }
+                });
+            }
         }
     }
 
+    /// The opposite of [`MandrakeOutput::dedup_memory`] - put the memory back
+    /// into each value (for readers that don't understand `memory_ref`).
+    pub fn inline_memory(&mut self) {
+        for entry in &mut self.history {
+            for value in entry.registers.values_mut() {
+                value.for_each_mut(&mut |value| {
+                    if let Some(key) = value.memory_ref.take() {
+                        value.memory = self.memory_pool.get(&key).cloned();
+                    }
+                });
+            }
+        }
+
+        self.memory_pool.clear();
+    }
+
+    /// Serialize the output in any of the formats the commandline supports
+    /// (pickle is binary, so this is bytes rather than a `String`).
+    pub fn to_format(&self, format: &OutputFormat, options: &FormatOptions) -> SimpleResult<Vec<u8>> {
+        let mut out: Vec<u8> = vec![];
+
+        // Writing to a Vec<u8> can't fail, so we don't bother checking writeln!
+        match format {
+            OutputFormat::JSON => {
+                let json = serde_json::to_string_pretty(self)
+                    .map_err(|e| SimpleError::new(format!("Couldn't serialize to JSON: {}", e)))?;
+                writeln!(out, "{}", json).unwrap();
+            },
+            OutputFormat::YAML => {
+                let yaml = serde_yaml::to_string(self)
+                    .map_err(|e| SimpleError::new(format!("Couldn't serialize to YAML: {}", e)))?;
+                writeln!(out, "{}", yaml).unwrap();
+            },
+            OutputFormat::PICKLE => {
+                let pickle_options = match options.pickle_protocol {
+                    2 => serde_pickle::SerOptions::new().proto_v2(),
+                    3 => serde_pickle::SerOptions::new(),
+                    _ => bail!("Unsupported pickle protocol: {} (only 2 and 3 are supported)", options.pickle_protocol),
+                };
+
+                let pickle = serde_pickle::to_vec(self, pickle_options)
+                    .map_err(|e| SimpleError::new(format!("Couldn't serialize to pickle: {}", e)))?;
+
+                if options.pickle_as_script {
+                    writeln!(out, "import base64").unwrap();
+                    writeln!(out, "import pickle").unwrap();
+                    writeln!(out).unwrap();
+                    writeln!(out, "pickle.loads(base64.b64decode(\"{}\"))", base64::encode(pickle)).unwrap();
+                } else {
+                    out = pickle;
+                }
+            },
+            OutputFormat::PLAINTEXT => {
+                for entry in &self.history {
+                    if let Some(skipped) = entry.skipped_since_last {
+                        match entry.came_from {
+                            Some(came_from) => writeln!(out, "... {} instruction(s) not logged, last at 0x{:08x}", skipped, came_from).unwrap(),
+                            None => writeln!(out, "... {} instruction(s) not logged", skipped).unwrap(),
+                        }
+                    }
+
+                    match entry.registers.get("rip") {
+                        Some(rip) => {
+                            writeln!(out, "{}{}", rip, entry.repeat_suffix()).unwrap();
+                        },
+                        None => {
+                            warn!("Missing rip in entry");
+                        },
+                    }
+                }
+
+                if let Some(time_breakdown) = &self.time_breakdown {
+                    writeln!(out).unwrap();
+                    writeln!(out, "Time: {}", time_breakdown).unwrap();
+                }
+
+                if let Some(code_entropy) = self.code_entropy {
+                    writeln!(out).unwrap();
+                    writeln!(out, "Code entropy: {:.2} bits per byte", code_entropy).unwrap();
+                }
+
+                if !self.badchar_report.is_empty() {
+                    let bad_bytes: Vec<String> = self.badchar_report.iter().map(|bad| {
+                        match bad.executed {
+                            true  => format!("{} ({}, executed)", bad.offset, bad.byte),
+                            false => format!("{} ({})", bad.offset, bad.byte),
+                        }
+                    }).collect();
+
+                    writeln!(out).unwrap();
+                    writeln!(out, "Bad bytes at offset(s): {}", bad_bytes.join(", ")).unwrap();
+                }
+
+                if let Some(stdout) = &self.stdout {
+                    if !stdout.is_empty() {
+                        writeln!(out).unwrap();
+                        writeln!(out, "Stdout: {}", stdout).unwrap();
+                    }
+                }
+
+                if let Some(stderr) = &self.stderr {
+                    if !stderr.is_empty() {
+                        writeln!(out).unwrap();
+                        writeln!(out, "stderr: {}", stderr).unwrap();
+                    }
+                }
+
+                if let Some(harness_stderr) = &self.harness_stderr {
+                    if !harness_stderr.is_empty() {
+                        writeln!(out).unwrap();
+                        writeln!(out, "Harness: {}", harness_stderr).unwrap();
+                    }
+                }
+            },
+            OutputFormat::SUMMARY => {
+                writeln!(out, "{}", self.summary_line()).unwrap();
+            },
+            OutputFormat::INDENTED => {
+                writeln!(out, "{}", self.indented_listing()).unwrap();
+            },
+        };
+
+        Ok(out)
+    }
+
+    /// Print the output to stdout as JSON.
     pub fn print(&self) {
         // I'm hoping that the to-json part can't fail
-        println!("{}", serde_json::to_string_pretty(self).unwrap());
+        std::io::stdout().write_all(&self.to_format(&OutputFormat::JSON, &FormatOptions::default()).unwrap()).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An output with a bit of everything: registers with memory, a syscall,
+    /// an exit reason, and output
+    fn sample_output() -> MandrakeOutput {
+        let mut rip = AnalyzedValue::from_value(0x13370000, true);
+        rip.memory = Some(vec![0x0f, 0x05]);
+        rip.as_instruction = Some("syscall".to_string());
+
+        let mut rsi = AnalyzedValue::from_value(0x13370005, false);
+        rsi.memory = Some(b"Hello World!".to_vec());
+        rsi.as_string = Some("Hello World!".to_string());
+
+        let registers: HashMap<String, AnalyzedValue> = vec![
+            ("rip".to_string(), rip),
+            ("rsi".to_string(), rsi),
+            ("rax".to_string(), AnalyzedValue::from_value(12, false)),
+        ].into_iter().collect();
+
+        let mut output = MandrakeOutput::new(1234);
+        output.history.push(HistoryEntry::new(0, registers));
+        output.instructions_executed = 1;
+        output.code_length = 2;
+        output.stdout = Some("Hello World!".to_string());
+        output.set_exit_reason(ExitReason::CleanExit { code: 12 });
+        output.exit_code = Some(12);
+
+        output
+    }
+
+    #[test]
+    fn pickle_round_trips() {
+        let output = sample_output();
+
+        for pickle_protocol in [2, 3] {
+            let options = FormatOptions { pickle_protocol, pickle_as_script: false };
+            let pickle = output.to_format(&OutputFormat::PICKLE, &options).unwrap();
+            let loaded: MandrakeOutput = serde_pickle::from_slice(&pickle, serde_pickle::DeOptions::new()).unwrap();
+
+            assert_eq!(loaded, output, "protocol {}", pickle_protocol);
+        }
+    }
+
+    #[test]
+    fn dedup_memory_shrinks_loops() {
+        // A loop runs the same instructions over the same memory, so every
+        // pass has the same snippits
+        let mut output = sample_output();
+        let entry = output.history[0].clone();
+        for index in 1..100 {
+            output.history.push(HistoryEntry { index, ..entry.clone() });
+        }
+
+        let mut deduped = output.clone();
+        deduped.dedup_memory();
+        assert_eq!(deduped.memory_pool.len(), 2);
+        assert_eq!(deduped.memory_pool.get(&hex::encode(Sha256::digest(b"Hello World!"))), Some(&b"Hello World!".to_vec()));
+
+        let json = output.to_format(&OutputFormat::JSON, &FormatOptions::default()).unwrap();
+        let deduped_json = deduped.to_format(&OutputFormat::JSON, &FormatOptions::default()).unwrap();
+        assert!(deduped_json.len() < json.len(), "{} bytes deduped, {} without", deduped_json.len(), json.len());
+
+        // Loading it back (like --replay does) gets the same bytes back
+        let mut loaded: MandrakeOutput = serde_json::from_slice(&deduped_json).unwrap();
+        loaded.inline_memory();
+        assert_eq!(loaded, output);
+    }
+
+    // serde_pickle can write values past i64::MAX (like -1 in a register),
+    // and Python reads them fine, but it can't read them back into a u64 -
+    // so this checks them at the pickle level instead
+    #[test]
+    fn pickle_keeps_large_values() {
+        let mut output = sample_output();
+        output.history[0].registers.insert("rax".to_string(), AnalyzedValue::from_value(u64::MAX, false));
+
+        let pickle = output.to_format(&OutputFormat::PICKLE, &FormatOptions::default()).unwrap();
+        let loaded = serde_pickle::value_from_slice(&pickle, serde_pickle::DeOptions::new()).unwrap();
+
+        assert_eq!(loaded, serde_pickle::to_value(&output).unwrap());
+    }
+
+    #[test]
+    fn summary_line_goes_by_exit_reason() {
+        let mut output = sample_output();
+        assert!(output.summary_line().starts_with("fail exit=12 "));
+
+        output.set_exit_reason(ExitReason::CleanExit { code: 0 });
+        output.exit_code = Some(0);
+        assert!(output.summary_line().starts_with("ok exit=0 "));
+
+        // Killed part way through exiting, so it still has an exit code
+        output.set_exit_reason(ExitReason::Killed { signal: "SIGKILL".to_string(), oom_killer: false, last_address: None });
+        assert!(output.summary_line().starts_with("killed exit=0 sig=SIGKILL "), "{}", output.summary_line());
+    }
+
+    #[test]
+    fn summary_line_stopped_isnt_killed() {
+        let mut output = sample_output();
+        output.exit_code = None;
+
+        output.set_exit_reason(ExitReason::Interrupted { address: 0x13370000, instruction: None });
+        assert!(output.summary_line().starts_with("stopped sig=SIGINT "), "{}", output.summary_line());
+
+        output.set_exit_reason(ExitReason::ChildExited);
+        assert!(output.summary_line().starts_with("stopped sig=SIGCHLD "), "{}", output.summary_line());
     }
 }
@@ -0,0 +1,170 @@
+//! A read-through cache of a stopped process's memory.
+//!
+//! At any one stop, a lot of registers point into the same few pages (rsp and
+//! rbp into the stack, rsi and rdi into the same buffer), and decoding a
+//! syscall reads the same buffers again. This reads each page once and serves
+//! everything else from memory.
+//!
+//! Whole pages are only read when `process_vm_readv()` can read them, since
+//! that's one syscall. When it can't (eg, if Yama blocks it, or the process
+//! itself can't read the page), just the words that are asked for are read
+//! with ptrace, one at a time - reading the whole page that way would take
+//! 512 syscalls.
+//!
+//! The process's memory changes as soon as it runs again, so a cache must
+//! only ever be used for a single stop!
+//!
+//! It can also be given a [`RegionFilter`], in which case pages in regions the
+//! filter doesn't allow are never read (they look unreadable).
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+
+use nix::unistd::Pid;
+
+use crate::analyzed_value::AnalyzedValue;
+use crate::memory_map::{MemoryMap, RegionFilter};
+
+const PAGE_SIZE: u64 = 0x1000;
+
+/// What we know about a page
+#[derive(Debug)]
+enum Page {
+    /// We read the whole page
+    Read(Vec<u8>),
+
+    /// `process_vm_readv()` couldn't read it, so it's read a word at a time
+    Peek,
+
+    /// The region filter doesn't allow it
+    Denied,
+}
+
+/// The memory of a process at one stop.
+#[derive(Debug)]
+pub struct MemoryCache<'a> {
+    pid: Pid,
+
+    /// Pages we've looked at, by address
+    pages: RefCell<HashMap<u64, Page>>,
+
+    /// Words we've read with ptrace, by (aligned) address (None means it
+    /// wasn't readable)
+    words: RefCell<HashMap<u64, Option<[u8; 8]>>>,
+
+    /// How many times we've read the process's memory (see
+    /// [`MemoryCache::reads`])
+    reads: Cell<usize>,
+
+    /// Which regions we can read, and the map to look them up in
+    filter: Option<(&'a RegionFilter, &'a MemoryMap)>,
+}
+
+impl<'a> MemoryCache<'a> {
+    pub fn new(pid: Pid) -> Self {
+        Self {
+            pid,
+            pages: RefCell::new(HashMap::new()),
+            words: RefCell::new(HashMap::new()),
+            reads: Cell::new(0),
+            filter: None,
+        }
+    }
+
+    /// Only read memory from regions that the filter allows.
+    pub fn with_filter(mut self, filter: &'a RegionFilter, map: &'a MemoryMap) -> Self {
+        self.filter = Some((filter, map));
+        self
+    }
+
+    /// How many syscalls were used to read the process's memory: one per page
+    /// read with `process_vm_readv()`, plus one per word read with ptrace.
+    pub fn reads(&self) -> usize {
+        self.reads.get()
+    }
+
+    /// If the filter won't let us read this address (and it's mapped, so we
+    /// otherwise could), get the name of its region.
+    pub fn denied_region(&self, addr: u64) -> Option<String> {
+        let (filter, map) = self.filter?;
+
+        match map.find(addr).is_some() && !filter.is_allowed(map, addr) {
+            true  => Some(map.classify(addr)),
+            false => None,
+        }
+    }
+
+    /// Read up to `length` bytes. Like [`AnalyzedValue::get_memory`], this
+    /// stops at unreadable memory, and the flag says whether it was cut
+    /// short (nothing readable at all returns None).
+    pub fn read(&self, addr: u64, length: usize) -> Option<(Vec<u8>, bool)> {
+        let mut data: Vec<u8> = vec![];
+        let mut pages = self.pages.borrow_mut();
+
+        while data.len() < length {
+            let current = match addr.checked_add(data.len() as u64) {
+                Some(current) => current,
+                None => break,
+            };
+            let page_start = current & !(PAGE_SIZE - 1);
+
+            let page = pages.entry(page_start).or_insert_with(|| {
+                // Mappings are page-aligned, so the whole page is in the same
+                // region
+                if let Some((filter, map)) = self.filter {
+                    if !filter.is_allowed(map, page_start) {
+                        return Page::Denied;
+                    }
+                }
+
+                self.reads.set(self.reads.get() + 1);
+                match AnalyzedValue::get_memory_vm_readv(self.pid, page_start, PAGE_SIZE as usize) {
+                    Some((page, false)) => Page::Read(page),
+                    _                   => Page::Peek,
+                }
+            });
+
+            // Take as much as we can from the page (or the word, if we're
+            // peeking), up to the end of it
+            let word: [u8; 8];
+            let (chunk, offset) = match page {
+                Page::Read(page) => (&page[..], (current - page_start) as usize),
+                Page::Peek => {
+                    let word_start = current & !7;
+                    word = match self.read_word(word_start) {
+                        Some(word) => word,
+                        None => break,
+                    };
+                    (&word[..], (current - word_start) as usize)
+                },
+                Page::Denied => break,
+            };
+
+            let wanted = std::cmp::min(chunk.len() - offset, length - data.len());
+            data.extend_from_slice(&chunk[offset..offset + wanted]);
+        }
+
+        if data.is_empty() {
+            return None;
+        }
+
+        let truncated = data.len() < length;
+        Some((data, truncated))
+    }
+
+    /// Read the (aligned) word at `addr` with ptrace, once
+    fn read_word(&self, addr: u64) -> Option<[u8; 8]> {
+        *self.words.borrow_mut().entry(addr).or_insert_with(|| {
+            self.reads.set(self.reads.get() + 1);
+            AnalyzedValue::get_memory_as_u64(self.pid, addr).map(u64::to_le_bytes)
+        })
+    }
+
+    /// Read a single (little endian) u64
+    pub fn read_u64(&self, addr: u64) -> Option<u64> {
+        match self.read(addr, 8)? {
+            (data, false) => Some(u64::from_le_bytes(data.try_into().ok()?)),
+            (_, true) => None,
+        }
+    }
+}
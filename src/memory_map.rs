@@ -0,0 +1,200 @@
+//! Reads a process's memory map, so we can say where an address lives.
+//!
+//! This is parsed from `/proc/<pid>/maps`. Each address is classified as one
+//! of:
+//!
+//! * `stack` / `heap` / `vdso` / etc - the kernel's special regions
+//! * `self:.text`, `self:.rodata`, `self:.data` - the executable itself
+//!   (guessed from the permissions, since the map doesn't have sections)
+//! * `libc.so.6` (etc) - the name of a mapped file
+//! * `anonymous` - memory that isn't backed by a file (eg, from `mmap`)
+//! * `unmapped` - anything else
+//!
+//! A [`RegionFilter`] uses the same names to decide which memory we're allowed
+//! to read.
+
+use std::fs;
+
+use nix::unistd::Pid;
+use serde::{Serialize, Deserialize};
+
+/// A single line from the memory map.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Region {
+    pub start: u64,
+    pub end: u64,
+
+    /// The permissions, such as `r-xp`
+    pub permissions: String,
+
+    /// Where in the file the region starts (0 if it isn't a file)
+    #[serde(default)]
+    pub offset: u64,
+
+    /// The file or special region (eg, `[stack]`), if there is one
+    pub path: Option<String>,
+}
+
+impl Region {
+    pub fn is_writable(&self) -> bool {
+        self.permissions.as_bytes().get(1) == Some(&b'w')
+    }
+
+    pub fn is_executable(&self) -> bool {
+        self.permissions.as_bytes().get(2) == Some(&b'x')
+    }
+
+    /// Parse a line like `7fa12b09a000-7fa12b1f0000 r-xp 00026000 fe:00 395379   /usr/lib/libc.so.6`
+    fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.splitn(6, ' ');
+
+        let (start, end) = fields.next()?.split_once('-')?;
+        let permissions = fields.next()?.to_string();
+        let offset = u64::from_str_radix(fields.next()?, 16).ok()?;
+
+        // Skip the device and inode
+        let path = fields.nth(2)
+            .map(|path| path.trim().to_string())
+            .filter(|path| !path.is_empty());
+
+        Some(Self {
+            start: u64::from_str_radix(start, 16).ok()?,
+            end: u64::from_str_radix(end, 16).ok()?,
+            permissions,
+            offset,
+            path,
+        })
+    }
+}
+
+/// The memory map of a process, at some point in time.
+#[derive(Debug, Clone)]
+pub struct MemoryMap {
+    pub regions: Vec<Region>,
+
+    /// The executable's path, so we can recognize its own mappings
+    executable: Option<String>,
+}
+
+impl MemoryMap {
+    /// Read the map for a process (`None` if it's not readable, eg because
+    /// the process is gone).
+    pub fn read(pid: Pid) -> Option<Self> {
+        let maps = fs::read_to_string(format!("/proc/{}/maps", pid)).ok()?;
+
+        let executable = fs::read_link(format!("/proc/{}/exe", pid)).ok()
+            .map(|path| path.to_string_lossy().to_string());
+
+        Some(Self {
+            regions: maps.lines().filter_map(Region::parse).collect(),
+            executable,
+        })
+    }
+
+    /// Find the region an address is in
+    pub fn find(&self, address: u64) -> Option<&Region> {
+        self.regions.iter().find(|r| address >= r.start && address < r.end)
+    }
+
+    /// Find where the executable itself was loaded (its lowest mapping)
+    pub fn executable_base(&self) -> Option<u64> {
+        self.regions.iter()
+            .filter(|r| r.path.is_some() && r.path == self.executable)
+            .map(|r| r.start)
+            .min()
+    }
+
+    /// Describe an address as an offset into the file it's mapped from (eg,
+    /// `libc.so.6+0x2a1ca`), from the file's lowest mapping. `None` if it's
+    /// not in a file (including shared anonymous memory, which is mapped from
+    /// `/dev/zero`).
+    pub fn module_offset(&self, address: u64) -> Option<String> {
+        let path = self.find(address)?.path.as_ref().filter(|path| !path.starts_with('[') && !path.starts_with("/dev/zero"))?;
+
+        let base = self.regions.iter()
+            .filter(|r| r.path.as_ref() == Some(path))
+            .map(|r| r.start)
+            .min()?;
+
+        Some(format!("{}+0x{:x}", path.rsplit('/').next().unwrap_or(path), address - base))
+    }
+
+    /// The name of the file an address is mapped from (eg, `libc.so.6`), the
+    /// kernel's name for the region (eg, `[vdso]`), or `anonymous` /
+    /// `unmapped` - for grouping addresses by module.
+    pub fn module_name(&self, address: u64) -> String {
+        let region = match self.find(address) {
+            Some(region) => region,
+            None => return "unmapped".to_string(),
+        };
+
+        match &region.path {
+            Some(path) if path.starts_with('[') => path.clone(),
+            Some(path) if !path.starts_with("/dev/zero") => path.rsplit('/').next().unwrap_or(path).to_string(),
+            _ => "anonymous".to_string(),
+        }
+    }
+
+    /// Get a short description of where an address lives
+    pub fn classify(&self, address: u64) -> String {
+        let region = match self.find(address) {
+            Some(region) => region,
+            None => return "unmapped".to_string(),
+        };
+
+        match &region.path {
+            // Kernel regions, like [stack] or [heap]
+            Some(path) if path.starts_with('[') => path.trim_matches(|c| c == '[' || c == ']').to_string(),
+
+            Some(path) if Some(path) == self.executable.as_ref() => {
+                match (region.is_executable(), region.is_writable()) {
+                    (true, _)     => "self:.text".to_string(),
+                    (false, true) => "self:.data".to_string(),
+                    _             => "self:.rodata".to_string(),
+                }
+            },
+
+            // Shared anonymous memory shows up as a deleted /dev/zero
+            Some(path) if path.starts_with("/dev/zero") => "anonymous".to_string(),
+
+            Some(path) => path.rsplit('/').next().unwrap_or(path).to_string(),
+            None => "anonymous".to_string(),
+        }
+    }
+}
+
+/// Which regions we're allowed to read memory from.
+///
+/// Each pattern matches a region if it's the region's name (as returned by
+/// [`MemoryMap::classify`], eg `stack` or `libc.so.6`), or part of the path
+/// it's mapped from (eg, `/dev/`). If there's an allowlist, only regions on it
+/// are readable; anything on the denylist never is.
+#[derive(Debug, Clone, Default)]
+pub struct RegionFilter {
+    allow: Vec<String>,
+    deny: Vec<String>,
+}
+
+impl RegionFilter {
+    pub fn new(allow: Vec<String>, deny: Vec<String>) -> Self {
+        Self {
+            allow,
+            deny,
+        }
+    }
+
+    /// Check whether the filter does anything
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    /// Check whether we're allowed to read memory at this address
+    pub fn is_allowed(&self, map: &MemoryMap, address: u64) -> bool {
+        let name = map.classify(address);
+        let path = map.find(address).and_then(|r| r.path.as_deref());
+
+        let matches = |pattern: &String| *pattern == name || path.map(|path| path.contains(pattern.as_str())).unwrap_or(false);
+
+        (self.allow.is_empty() || self.allow.iter().any(matches)) && !self.deny.iter().any(matches)
+    }
+}
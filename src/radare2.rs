@@ -0,0 +1,68 @@
+//! Writes a radare2 (or rizin) script that marks a trace in a static analysis.
+//!
+//! The script comments every instruction that ran (with what each syscall was
+//! called with and returned, and the registers at the crash), hints how long
+//! each one was, and flags where the trace started and crashed. Every address is relative to `$B`, the
+//! binary's base address, so `r2 -i trace.r2 ./binary` lines up wherever the
+//! binary was loaded (see [`crate::annotations`]).
+//!
+//! Things that happened outside the binary (eg, a syscall in libc) are listed
+//! in a commented-out section at the end, since they can't be placed.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::annotations::TraceAnnotations;
+use crate::mandrake_output::MandrakeOutput;
+
+/// Build the script for a trace.
+pub fn radare2_script(output: &MandrakeOutput) -> String {
+    let annotations = TraceAnnotations::new(output);
+
+    // Gather everything to say about each instruction, so each one gets a
+    // single comment
+    let mut comments: BTreeMap<u64, Vec<String>> = BTreeMap::new();
+    for (offset, count) in &annotations.executed {
+        comments.entry(*offset).or_default().push(format!("Mandrake: executed {} time(s)", count));
+    }
+    for (offset, comment) in &annotations.comments {
+        comments.entry(*offset).or_default().push(comment.clone());
+    }
+
+    let mut out = String::new();
+    writeln!(out, "# Marks a Mandrake trace (generated by Mandrake) - load it with `r2 -i <this file> <binary>`").unwrap();
+    writeln!(out, "# Addresses are relative to the binary's base ($B), which was 0x{:x} in the trace", annotations.image_base).unwrap();
+
+    if let Some(start) = annotations.start {
+        writeln!(out, "f mandrake.start @ $B+0x{:x}", start).unwrap();
+    }
+    if let Some(crash) = annotations.crash {
+        writeln!(out, "f mandrake.crash @ $B+0x{:x}", crash).unwrap();
+    }
+
+    // r2's `b` (block size) is global, so the per-address equivalent is an
+    // instruction size hint: it makes r2 split the code the way it actually
+    // ran, even if it jumps into the middle of what looks like another
+    // instruction
+    for (offset, length) in &annotations.lengths {
+        writeln!(out, "ahs {} @ $B+0x{:x}", length, offset).unwrap();
+    }
+
+    // Comments can have anything in them (including `;` and newlines), so
+    // they're base64 encoded, the same way r2 saves them
+    for (offset, comment) in &comments {
+        writeln!(out, "CCu base64:{} @ $B+0x{:x}", base64::encode(comment.join("\n")), offset).unwrap();
+    }
+
+    if !annotations.outside.is_empty() {
+        writeln!(out).unwrap();
+        writeln!(out, "# Outside the binary (at their addresses in the trace):").unwrap();
+        for (address, comment) in &annotations.outside {
+            for line in comment.lines() {
+                writeln!(out, "# 0x{:x}: {}", address, line).unwrap();
+            }
+        }
+    }
+
+    out
+}
@@ -0,0 +1,258 @@
+//! Checks that Mandrake works on this machine (`mandrake selftest`).
+//!
+//! Setting up somewhere new means the harness, ptrace permissions, and kernel
+//! quirks all have to line up, and when they don't, the errors come out of
+//! the middle of a trace. This runs a few cases with known results (raw code
+//! through the harness, and a tiny ELF) and says exactly which ones didn't
+//! behave, along with anything in the environment that's likely to be why.
+
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use simple_error::{SimpleError, SimpleResult};
+
+use crate::mandrake::Mandrake;
+use crate::mandrake_output::{ExitReason, MandrakeOutput};
+use crate::syscalls::SyscallAbi;
+use crate::visibility_configuration::VisibilityConfiguration;
+
+/// Where Yama's ptrace restrictions are set (it's not there if Yama isn't
+/// enabled, which is fine)
+const PTRACE_SCOPE_PATH: &str = "/proc/sys/kernel/yama/ptrace_scope";
+
+/// The instruction cap used by the cases
+const MAX_INSTRUCTIONS: usize = 100;
+
+/// Where the minimal ELF is loaded
+const ELF_BASE: u64 = 0x400000;
+
+/// The size of the ELF header and the one program header after it
+const ELF_HEADER_LENGTH: u64 = 64;
+const PROGRAM_HEADER_LENGTH: u64 = 56;
+
+/// The code for the minimal ELF: an int3 (to start logging), then
+/// `write(1, "ok\n", 3)` and `exit(0)`
+const ELF_CODE: &[u8] = &[
+    0xcc,                                     // int3
+    0xb8, 0x01, 0x00, 0x00, 0x00,             // mov eax, 1 (sys_write)
+    0xbf, 0x01, 0x00, 0x00, 0x00,             // mov edi, 1
+    0x48, 0x8d, 0x35, 0x10, 0x00, 0x00, 0x00, // lea rsi, [rel message]
+    0xba, 0x03, 0x00, 0x00, 0x00,             // mov edx, 3
+    0x0f, 0x05,                               // syscall
+    0xb8, 0x3c, 0x00, 0x00, 0x00,             // mov eax, 60 (sys_exit)
+    0x31, 0xff,                               // xor edi, edi
+    0x0f, 0x05,                               // syscall
+    b'o', b'k', b'\n',                        // message
+];
+
+/// One thing that was checked, and how it went.
+#[derive(Debug, Clone)]
+pub struct SelftestResult {
+    pub name: String,
+    pub passed: bool,
+
+    /// What was seen (or what went wrong)
+    pub detail: String,
+}
+
+impl SelftestResult {
+    fn new(name: &str, passed: bool, detail: impl Into<String>) -> Self {
+        Self { name: name.to_string(), passed, detail: detail.into() }
+    }
+}
+
+/// Like `PASS  clean exit      Process exited cleanly with exit code 0`
+impl fmt::Display for SelftestResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = match self.passed {
+            true  => "PASS",
+            false => "FAIL",
+        };
+
+        write!(f, "{}  {:<18} {}", status, self.name, self.detail)
+    }
+}
+
+/// What a case is expected to do
+struct Expected {
+    exit_reason: fn(&ExitReason) -> bool,
+    instructions: Option<usize>,
+    stdout: Option<&'static str>,
+
+    /// A syscall it makes, by its x86-64 number (so it's found even without
+    /// the syscall tables)
+    syscall: Option<u64>,
+}
+
+/// The x86-64 number of `write`
+const SYS_WRITE: u64 = 1;
+
+/// The raw code cases, as (name, code, what it should do)
+const CODE_CASES: &[(&str, &str, Expected)] = &[
+    ("clean exit", "c3", Expected {
+        exit_reason: |reason| matches!(reason, ExitReason::CleanExit { code: 0 }),
+        instructions: Some(1),
+        stdout: None,
+        syscall: None,
+    }),
+    ("segfault", "6841414141c3", Expected {
+        exit_reason: |reason| matches!(reason, ExitReason::Signal { signal, address: 0x41414141, .. } if signal == "SIGSEGV"),
+        instructions: Some(3),
+        stdout: None,
+        syscall: None,
+    }),
+    ("instruction cap", "ebfe", Expected {
+        exit_reason: |reason| matches!(reason, ExitReason::InstructionCap { max_instructions: MAX_INSTRUCTIONS }),
+        instructions: Some(MAX_INSTRUCTIONS),
+        stdout: None,
+        syscall: None,
+    }),
+    ("write syscall", "e80d00000048656c6c6f20576f726c64210048c7c00100000048c7c7010000005e48c7c20c0000000f05c3", Expected {
+        exit_reason: |reason| matches!(reason, ExitReason::CleanExit { code: 12 }),
+        instructions: Some(7),
+        stdout: Some("Hello World!"),
+        syscall: Some(SYS_WRITE),
+    }),
+];
+
+/// The ELF case
+const ELF_CASE: Expected = Expected {
+    exit_reason: |reason| matches!(reason, ExitReason::CleanExit { code: 0 }),
+    instructions: None,
+    stdout: Some("ok\n"),
+    syscall: Some(SYS_WRITE),
+};
+
+/// Build the smallest ELF that runs `code`: a header and one segment (with
+/// the headers and the code in it), loaded at [`ELF_BASE`].
+fn minimal_elf(code: &[u8]) -> Vec<u8> {
+    let code_offset = ELF_HEADER_LENGTH + PROGRAM_HEADER_LENGTH;
+    let length = code_offset + code.len() as u64;
+
+    let mut elf: Vec<u8> = vec![];
+
+    // The ELF header: 64-bit, little endian, an x86-64 executable
+    elf.extend_from_slice(b"\x7fELF\x02\x01\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00");
+    elf.extend_from_slice(&2u16.to_le_bytes());                        // e_type (ET_EXEC)
+    elf.extend_from_slice(&0x3eu16.to_le_bytes());                     // e_machine (x86-64)
+    elf.extend_from_slice(&1u32.to_le_bytes());                        // e_version
+    elf.extend_from_slice(&(ELF_BASE + code_offset).to_le_bytes());    // e_entry
+    elf.extend_from_slice(&ELF_HEADER_LENGTH.to_le_bytes());           // e_phoff
+    elf.extend_from_slice(&0u64.to_le_bytes());                        // e_shoff
+    elf.extend_from_slice(&0u32.to_le_bytes());                        // e_flags
+    elf.extend_from_slice(&(ELF_HEADER_LENGTH as u16).to_le_bytes());  // e_ehsize
+    elf.extend_from_slice(&(PROGRAM_HEADER_LENGTH as u16).to_le_bytes()); // e_phentsize
+    elf.extend_from_slice(&1u16.to_le_bytes());                        // e_phnum
+    elf.extend_from_slice(&[0; 6]);                                    // e_shentsize, e_shnum, e_shstrndx
+
+    // The program header: load the whole file, readable and executable
+    elf.extend_from_slice(&1u32.to_le_bytes());                        // p_type (PT_LOAD)
+    elf.extend_from_slice(&5u32.to_le_bytes());                        // p_flags (R + X)
+    elf.extend_from_slice(&0u64.to_le_bytes());                        // p_offset
+    elf.extend_from_slice(&ELF_BASE.to_le_bytes());                    // p_vaddr
+    elf.extend_from_slice(&ELF_BASE.to_le_bytes());                    // p_paddr
+    elf.extend_from_slice(&length.to_le_bytes());                      // p_filesz
+    elf.extend_from_slice(&length.to_le_bytes());                      // p_memsz
+    elf.extend_from_slice(&0x1000u64.to_le_bytes());                   // p_align
+
+    elf.extend_from_slice(code);
+    elf
+}
+
+/// Check what a case did against what it should have done.
+fn check(name: &str, result: SimpleResult<MandrakeOutput>, expected: &Expected) -> SelftestResult {
+    let result = match result {
+        Ok(result) => result,
+        Err(e) => return SelftestResult::new(name, false, format!("The analysis failed: {}", e)),
+    };
+
+    let message = result.exit_message.clone().unwrap_or_else(|| "(no exit reason)".to_string());
+    let mut problems: Vec<String> = vec![];
+
+    if !result.exit_reason.as_ref().is_some_and(expected.exit_reason) {
+        problems.push(format!("unexpected exit reason: {}", message));
+    }
+
+    if let Some(instructions) = expected.instructions {
+        if result.instructions_executed != instructions {
+            problems.push(format!("executed {} instruction(s), expected {}", result.instructions_executed, instructions));
+        }
+    }
+
+    if let Some(stdout) = expected.stdout {
+        if result.stdout.as_deref() != Some(stdout) {
+            problems.push(format!("stdout was {:?}, expected {:?}", result.stdout.as_deref().unwrap_or_default(), stdout));
+        }
+    }
+
+    if let Some(syscall) = expected.syscall {
+        if !result.syscalls.iter().any(|event| event.number == syscall && event.abi == SyscallAbi::X86_64.to_string()) {
+            problems.push(format!("didn't see syscall {}", syscall));
+        }
+    }
+
+    match problems.is_empty() {
+        true  => SelftestResult::new(name, true, message),
+        false => SelftestResult::new(name, false, problems.join("; ")),
+    }
+}
+
+/// Write the minimal ELF somewhere it can be run from, and run it.
+fn run_elf(mandrake: &Mandrake) -> SimpleResult<MandrakeOutput> {
+    let mut file = tempfile::Builder::new()
+        .prefix("mandrake-selftest-")
+        .tempfile()
+        .map_err(|e| SimpleError::new(format!("Couldn't create a temporary file: {}", e)))?;
+
+    file.write_all(&minimal_elf(ELF_CODE))
+        .map_err(|e| SimpleError::new(format!("Couldn't write the ELF: {}", e)))?;
+    file.as_file().set_permissions(fs::Permissions::from_mode(0o700))
+        .map_err(|e| SimpleError::new(format!("Couldn't make the ELF executable: {}", e)))?;
+
+    // Close it, or running it fails with ETXTBSY
+    let path = file.into_temp_path();
+
+    mandrake.analyze_elf(&path, None, &[], vec![], &VisibilityConfiguration::full_visibility())
+}
+
+/// Check for things in the environment that stop Mandrake from working.
+pub fn check_environment(harness: &Path) -> Vec<SelftestResult> {
+    let mut out = vec![];
+
+    out.push(match fs::read_to_string(PTRACE_SCOPE_PATH).map(|scope| scope.trim().to_string()) {
+        Err(_) => SelftestResult::new("ptrace_scope", true, "Yama isn't enabled, so ptrace isn't restricted"),
+        Ok(scope) if scope == "0" || scope == "1" => SelftestResult::new("ptrace_scope", true, format!("{} (Mandrake only traces its own children, which is allowed)", scope)),
+        Ok(scope) if scope == "2" => SelftestResult::new("ptrace_scope", false, format!("2 (only root / CAP_SYS_PTRACE can trace) - run as root, or lower {}", PTRACE_SCOPE_PATH)),
+        Ok(scope) => SelftestResult::new("ptrace_scope", false, format!("{} (ptrace is disabled until a reboot)", scope)),
+    });
+
+    out.push(match fs::metadata(harness) {
+        Ok(metadata) if metadata.permissions().mode() & 0o111 != 0 => SelftestResult::new("harness", true, harness.display().to_string()),
+        Ok(_) => SelftestResult::new("harness", false, format!("{} isn't executable", harness.display())),
+        Err(e) => SelftestResult::new("harness", false, format!("{}: {} (use --harness to say where it is)", harness.display(), e)),
+    });
+
+    out
+}
+
+/// Run every case, and check each one did what it should have.
+pub fn run_cases(harness: &Path) -> Vec<SelftestResult> {
+    // The defaults, so a config file or commandline options can't change the
+    // results
+    let mandrake = Mandrake::new(64, 6, Some(MAX_INSTRUCTIONS), false, false, false);
+
+    let mut out: Vec<SelftestResult> = CODE_CASES.iter().map(|(name, code, expected)| {
+        let result = hex::decode(code)
+            .map_err(|e| SimpleError::new(e.to_string()))
+            .and_then(|code| mandrake.analyze_code(code, harness, false));
+
+        check(name, result, expected)
+    }).collect();
+
+    out.push(check("minimal ELF", run_elf(&mandrake), &ELF_CASE));
+
+    out
+}
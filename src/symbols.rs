@@ -0,0 +1,579 @@
+//! Finds the functions an ELF imports, so calls into libraries can be named.
+//!
+//! A call to a library function goes through the PLT, which jumps through a
+//! slot in the GOT that the dynamic linker fills in. The ELF's relocations say
+//! which symbol each slot is for, so once we know where the slots are, an
+//! indirect jump (or call) through one tells us exactly what's being called.
+//!
+//! It can also pull the bytes of a section or a function out of an ELF (see
+//! [`section_bytes`] and [`symbol_bytes`]), so they can be run as raw code.
+//!
+//! This is a minimal parser for 64-bit little endian ELFs - it only reads the
+//! section and program headers, the symbols, their versions, and the
+//! relocations that point at them.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use byteorder::{ByteOrder, LittleEndian};
+use simple_error::{bail, SimpleError, SimpleResult};
+
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+
+const PT_LOAD: u32 = 1;
+
+const SHT_SYMTAB: u32 = 2;
+const SHT_RELA: u32 = 4;
+const SHT_NOBITS: u32 = 8;
+const SHT_DYNSYM: u32 = 11;
+const SHT_GNU_VERNEED: u32 = 0x6ffffffe;
+const SHT_GNU_VERSYM: u32 = 0x6fffffff;
+
+// Relocations that fill in a GOT slot with a symbol's address
+const R_X86_64_GLOB_DAT: u32 = 6;
+const R_X86_64_JUMP_SLOT: u32 = 7;
+
+const PHDR_SIZE: usize = 56;
+const SHDR_SIZE: usize = 64;
+const SYM_SIZE: usize = 24;
+const RELA_SIZE: usize = 24;
+
+/// A section header (just the parts we use)
+#[derive(Debug, Clone)]
+struct Section {
+    /// Where the name is in the section name table
+    name: usize,
+    kind: u32,
+    offset: usize,
+    size: usize,
+    link: usize,
+}
+
+/// The imported functions of an ELF, by the address of their GOT slot.
+#[derive(Debug, Clone, Default)]
+pub struct Imports {
+    slots: HashMap<u64, String>,
+
+    /// Where the ELF expects to be loaded (the lowest PT_LOAD address)
+    base_address: u64,
+}
+
+impl Imports {
+    /// Read the imports from an ELF file.
+    pub fn from_file(path: &Path) -> SimpleResult<Self> {
+        let data = std::fs::read(path)
+            .map_err(|e| SimpleError::new(format!("Couldn't read {}: {}", path.display(), e)))?;
+
+        Self::parse(&data)
+            .map_err(|e| SimpleError::new(format!("Couldn't read imports from {}: {}", path.display(), e)))
+    }
+
+    /// Parse the imports from the contents of an ELF file.
+    pub fn parse(data: &[u8]) -> SimpleResult<Self> {
+        let base_address = load_segments(data)?.iter()
+            .map(|segment| segment.address)
+            .min()
+            .unwrap_or(0);
+
+        let sections = read_sections(data)?;
+        for section in sections.iter().filter(|s| matches!(s.kind, SHT_RELA | SHT_DYNSYM | SHT_GNU_VERNEED | SHT_GNU_VERSYM)) {
+            read_bytes(data, section.offset, section.size)?;
+        }
+
+        // The version each symbol needs (eg, GLIBC_2.14), if there are any
+        let versions = version_names(data, &sections)?;
+        let versym = sections.iter().find(|s| s.kind == SHT_GNU_VERSYM);
+
+        let mut slots: HashMap<u64, String> = HashMap::new();
+
+        // Each RELA section that's linked to the dynamic symbols is a list of
+        // slots to fill in
+        for rela in sections.iter().filter(|s| s.kind == SHT_RELA) {
+            let dynsym = match sections.get(rela.link) {
+                Some(dynsym) if dynsym.kind == SHT_DYNSYM => dynsym,
+                _ => continue,
+            };
+            let dynstr = sections.get(dynsym.link)
+                .ok_or_else(|| SimpleError::new("Dynamic symbols have no string table"))?;
+
+            for entry in (rela.offset..rela.offset + rela.size).step_by(RELA_SIZE) {
+                let slot = read_u64(data, entry)?;
+                let info = read_u64(data, entry + 8)?;
+
+                let (symbol, kind) = ((info >> 32) as usize, info as u32);
+                if symbol == 0 || (kind != R_X86_64_JUMP_SLOT && kind != R_X86_64_GLOB_DAT) {
+                    continue;
+                }
+
+                let name_offset = read_u32(data, dynsym.offset + symbol * SYM_SIZE)? as usize;
+                let mut name = read_string(data, dynstr.offset.saturating_add(name_offset))?;
+
+                // The high bit of the version means "hidden", which doesn't
+                // matter to us
+                if let Some(versym) = versym {
+                    let version = read_u16(data, versym.offset + symbol * 2)? & 0x7fff;
+                    if let Some(version) = versions.get(&version) {
+                        name = format!("{}@{}", name, version);
+                    }
+                }
+
+                slots.insert(slot, name);
+            }
+        }
+
+        Ok(Self {
+            slots,
+            base_address,
+        })
+    }
+
+    /// Move the slots to where the ELF was actually loaded (for a PIE, this
+    /// is somewhere random).
+    pub fn relocate(self, load_address: u64) -> Self {
+        let bias = load_address.wrapping_sub(self.base_address);
+
+        Self {
+            slots: self.slots.into_iter().map(|(slot, name)| (slot.wrapping_add(bias), name)).collect(),
+            base_address: load_address,
+        }
+    }
+
+    /// Get the name of the function whose address is stored at this slot
+    pub fn get(&self, slot: u64) -> Option<&str> {
+        self.slots.get(&slot).map(|name| name.as_str())
+    }
+}
+
+/// A loadable segment (just the parts we use)
+#[derive(Debug, Clone)]
+struct Segment {
+    offset: usize,
+    address: u64,
+    file_size: usize,
+}
+
+/// Make sure the data is an ELF we can read
+fn check_header(data: &[u8]) -> SimpleResult<()> {
+    if data.get(0..4) != Some(ELF_MAGIC) {
+        bail!("Not an ELF file");
+    }
+    if data.get(4) != Some(&ELFCLASS64) || data.get(5) != Some(&ELFDATA2LSB) {
+        bail!("Only 64-bit little endian ELF files are supported");
+    }
+
+    Ok(())
+}
+
+/// Read the PT_LOAD program headers
+fn load_segments(data: &[u8]) -> SimpleResult<Vec<Segment>> {
+    check_header(data)?;
+
+    let phoff = read_u64(data, 32)? as usize;
+    let phnum = read_u16(data, 56)? as usize;
+
+    // Make sure the table is inside the file, so the offsets we calculate
+    // from it can't overflow
+    read_bytes(data, phoff, phnum * PHDR_SIZE)?;
+
+    (0..phnum)
+        .map(|i| phoff + i * PHDR_SIZE)
+        .filter(|&ph| read_u32(data, ph).ok() == Some(PT_LOAD))
+        .map(|ph| Ok(Segment {
+            offset:    read_u64(data, ph + 8)? as usize,
+            address:   read_u64(data, ph + 16)?,
+            file_size: read_u64(data, ph + 32)? as usize,
+        }))
+        .collect()
+}
+
+/// Read the section headers (the sections themselves aren't checked, since
+/// some, like .bss, don't take up space in the file)
+fn read_sections(data: &[u8]) -> SimpleResult<Vec<Section>> {
+    check_header(data)?;
+
+    let shoff = read_u64(data, 40)? as usize;
+    let shnum = read_u16(data, 60)? as usize;
+    read_bytes(data, shoff, shnum * SHDR_SIZE)?;
+
+    (0..shnum).map(|i| {
+        let sh = shoff + i * SHDR_SIZE;
+
+        Ok(Section {
+            name:   read_u32(data, sh)? as usize,
+            kind:   read_u32(data, sh + 4)?,
+            offset: read_u64(data, sh + 24)? as usize,
+            size:   read_u64(data, sh + 32)? as usize,
+            link:   read_u32(data, sh + 40)? as usize,
+        })
+    }).collect()
+}
+
+/// Get the contents of a section by name (eg, ".text").
+pub fn section_bytes(data: &[u8], name: &str) -> SimpleResult<Vec<u8>> {
+    let sections = read_sections(data)?;
+    let names = sections.get(read_u16(data, 62)? as usize)
+        .ok_or_else(|| SimpleError::new("The ELF has no section names"))?;
+
+    for section in &sections {
+        if read_string(data, names.offset.saturating_add(section.name))? != name {
+            continue;
+        }
+        if section.kind == SHT_NOBITS {
+            bail!("Section {} isn't stored in the file (it's only allocated when the ELF is loaded)", name);
+        }
+
+        return Ok(read_bytes(data, section.offset, section.size)?.to_vec());
+    }
+
+    bail!("The ELF has no section named {}", name);
+}
+
+/// Get the code of a symbol (eg, a function) by name, from the regular symbol
+/// table or the dynamic one. The symbol's size is used unless `length` is
+/// given (some symbols, like ones from assembly, don't have one).
+pub fn symbol_bytes(data: &[u8], name: &str, length: Option<usize>) -> SimpleResult<Vec<u8>> {
+    let sections = read_sections(data)?;
+    let tables: Vec<&Section> = sections.iter().filter(|s| s.kind == SHT_SYMTAB).chain(sections.iter().filter(|s| s.kind == SHT_DYNSYM)).collect();
+    let stripped = !sections.iter().any(|s| s.kind == SHT_SYMTAB);
+
+    for table in tables {
+        read_bytes(data, table.offset, table.size)?;
+        let strings = sections.get(table.link)
+            .ok_or_else(|| SimpleError::new("Symbols have no string table"))?;
+
+        for entry in (table.offset..table.offset + table.size).step_by(SYM_SIZE) {
+            let name_offset = read_u32(data, entry)? as usize;
+            if name_offset == 0 || read_string(data, strings.offset.saturating_add(name_offset))? != name {
+                continue;
+            }
+
+            // An undefined symbol (eg, an import) has no code here
+            let address = read_u64(data, entry + 8)?;
+            if read_u16(data, entry + 6)? == 0 {
+                bail!("Symbol {} isn't defined in this ELF (it's imported)", name);
+            }
+
+            let length = match (length, read_u64(data, entry + 16)? as usize) {
+                (Some(length), _) => length,
+                (None, 0)         => bail!("Symbol {} doesn't have a size, use --length to say how much to read", name),
+                (None, size)      => size,
+            };
+
+            return read_address(data, address, length)
+                .map_err(|e| SimpleError::new(format!("Couldn't read symbol {} at 0x{:x}: {}", name, address, e)));
+        }
+    }
+
+    match stripped {
+        true  => bail!("The ELF has no symbol named {} (it only has the dynamic symbols, so it's probably stripped)", name),
+        false => bail!("The ELF has no symbol named {}", name),
+    }
+}
+
+/// Read the bytes that will be loaded at a (virtual) address, by finding the
+/// segment that loads them
+fn read_address(data: &[u8], address: u64, length: usize) -> SimpleResult<Vec<u8>> {
+    let segment = load_segments(data)?.into_iter()
+        .find(|segment| address >= segment.address && address - segment.address < segment.file_size as u64)
+        .ok_or_else(|| SimpleError::new("the address isn't loaded from the file"))?;
+
+    let start = (address - segment.address) as usize;
+    if start.saturating_add(length) > segment.file_size {
+        bail!("only {} bytes are loaded from the file there", segment.file_size - start);
+    }
+
+    Ok(read_bytes(data, segment.offset.saturating_add(start), length)?.to_vec())
+}
+
+/// Get the names of the versions in the "version needed" section, by their
+/// index (the same index that the version symbol table uses).
+fn version_names(data: &[u8], sections: &[Section]) -> SimpleResult<HashMap<u16, String>> {
+    let mut out: HashMap<u16, String> = HashMap::new();
+
+    let verneed = match sections.iter().find(|s| s.kind == SHT_GNU_VERNEED) {
+        Some(verneed) => verneed,
+        None => return Ok(out),
+    };
+    let strings = sections.get(verneed.link)
+        .ok_or_else(|| SimpleError::new("Version section has no string table"))?;
+
+    // A linked list of libraries, each with a linked list of versions
+    let mut need = verneed.offset;
+    loop {
+        let count = read_u16(data, need + 2)?;
+        let mut aux = need + read_u32(data, need + 8)? as usize;
+
+        for _ in 0..count {
+            let index = read_u16(data, aux + 6)?;
+            let name = read_u32(data, aux + 8)? as usize;
+            out.insert(index, read_string(data, strings.offset.saturating_add(name))?);
+
+            aux += read_u32(data, aux + 12)? as usize;
+        }
+
+        match read_u32(data, need + 12)? {
+            0 => break,
+            next => need += next as usize,
+        }
+    }
+
+    Ok(out)
+}
+
+fn read_bytes(data: &[u8], offset: usize, length: usize) -> SimpleResult<&[u8]> {
+    data.get(offset..offset.saturating_add(length))
+        .ok_or_else(|| SimpleError::new(format!("ELF file is truncated (reading 0x{:x})", offset)))
+}
+
+fn read_u16(data: &[u8], offset: usize) -> SimpleResult<u16> {
+    Ok(LittleEndian::read_u16(read_bytes(data, offset, 2)?))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> SimpleResult<u32> {
+    Ok(LittleEndian::read_u32(read_bytes(data, offset, 4)?))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> SimpleResult<u64> {
+    Ok(LittleEndian::read_u64(read_bytes(data, offset, 8)?))
+}
+
+fn read_string(data: &[u8], offset: usize) -> SimpleResult<String> {
+    let string = data.get(offset..)
+        .and_then(|rest| rest.split(|&b| b == 0).next())
+        .ok_or_else(|| SimpleError::new(format!("ELF file is truncated (reading a string at 0x{:x})", offset)))?;
+
+    Ok(String::from_utf8_lossy(string).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Where the test ELF's one segment is loaded
+    const BASE: u64 = 0x400000;
+
+    /// The first section starts right after the ELF header and the program
+    /// header
+    const FIRST_SECTION: u64 = 64 + PHDR_SIZE as u64;
+
+    /// A section of a test ELF; `link` is the index of another section, where
+    /// the first one in the list is 1 (0 is the null section)
+    struct TestSection {
+        name: &'static str,
+        kind: u32,
+        data: Vec<u8>,
+        link: u32,
+    }
+
+    impl TestSection {
+        fn new(name: &'static str, kind: u32, data: Vec<u8>, link: u32) -> Self {
+            Self { name, kind, data, link }
+        }
+    }
+
+    /// Build an ELF with these sections, followed by the section name table.
+    /// One segment loads the whole file (up to the section headers) at
+    /// [`BASE`]; SHT_NOBITS sections only take up space in memory.
+    fn test_elf(sections: &[TestSection]) -> Vec<u8> {
+        let mut names: Vec<u8> = vec![0];
+        let mut body: Vec<u8> = vec![];
+        let mut headers: Vec<u8> = vec![0; SHDR_SIZE];
+
+        let name_table = TestSection::new(".shstrtab", 3, vec![], 0);
+        for section in sections.iter().chain(std::iter::once(&name_table)) {
+            let name = names.len() as u32;
+            names.extend_from_slice(section.name.as_bytes());
+            names.push(0);
+
+            let data = match section.name {
+                ".shstrtab" => &names,
+                _           => &section.data,
+            };
+
+            while body.len() % 8 != 0 {
+                body.push(0);
+            }
+            let offset = FIRST_SECTION + body.len() as u64;
+            if section.kind != SHT_NOBITS {
+                body.extend_from_slice(data);
+            }
+
+            headers.extend_from_slice(&name.to_le_bytes());                 // sh_name
+            headers.extend_from_slice(&section.kind.to_le_bytes());         // sh_type
+            headers.extend_from_slice(&0u64.to_le_bytes());                 // sh_flags
+            headers.extend_from_slice(&(BASE + offset).to_le_bytes());      // sh_addr
+            headers.extend_from_slice(&offset.to_le_bytes());               // sh_offset
+            headers.extend_from_slice(&(data.len() as u64).to_le_bytes());  // sh_size
+            headers.extend_from_slice(&section.link.to_le_bytes());         // sh_link
+            headers.extend_from_slice(&[0; 20]);                            // sh_info, sh_addralign, sh_entsize
+        }
+
+        let length = FIRST_SECTION + body.len() as u64;
+        let mut elf: Vec<u8> = vec![];
+
+        // The ELF header: 64-bit, little endian, an x86-64 executable
+        elf.extend_from_slice(b"\x7fELF\x02\x01\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00");
+        elf.extend_from_slice(&2u16.to_le_bytes());                         // e_type (ET_EXEC)
+        elf.extend_from_slice(&0x3eu16.to_le_bytes());                      // e_machine (x86-64)
+        elf.extend_from_slice(&1u32.to_le_bytes());                         // e_version
+        elf.extend_from_slice(&(BASE + FIRST_SECTION).to_le_bytes());       // e_entry
+        elf.extend_from_slice(&64u64.to_le_bytes());                        // e_phoff
+        elf.extend_from_slice(&length.to_le_bytes());                       // e_shoff
+        elf.extend_from_slice(&0u32.to_le_bytes());                         // e_flags
+        elf.extend_from_slice(&64u16.to_le_bytes());                        // e_ehsize
+        elf.extend_from_slice(&(PHDR_SIZE as u16).to_le_bytes());           // e_phentsize
+        elf.extend_from_slice(&1u16.to_le_bytes());                         // e_phnum
+        elf.extend_from_slice(&(SHDR_SIZE as u16).to_le_bytes());           // e_shentsize
+        elf.extend_from_slice(&(sections.len() as u16 + 2).to_le_bytes());  // e_shnum
+        elf.extend_from_slice(&(sections.len() as u16 + 1).to_le_bytes());  // e_shstrndx
+
+        // The program header: load everything but the section headers
+        elf.extend_from_slice(&PT_LOAD.to_le_bytes());                      // p_type
+        elf.extend_from_slice(&5u32.to_le_bytes());                         // p_flags (R + X)
+        elf.extend_from_slice(&0u64.to_le_bytes());                         // p_offset
+        elf.extend_from_slice(&BASE.to_le_bytes());                         // p_vaddr
+        elf.extend_from_slice(&BASE.to_le_bytes());                         // p_paddr
+        elf.extend_from_slice(&length.to_le_bytes());                       // p_filesz
+        elf.extend_from_slice(&length.to_le_bytes());                       // p_memsz
+        elf.extend_from_slice(&0x1000u64.to_le_bytes());                    // p_align
+
+        elf.extend_from_slice(&body);
+        elf.extend_from_slice(&headers);
+        elf
+    }
+
+    /// A symbol table entry
+    fn symbol(name: u32, shndx: u16, value: u64, size: u64) -> Vec<u8> {
+        let mut entry = name.to_le_bytes().to_vec();
+        entry.extend_from_slice(&[0x12, 0]);                                // st_info (global function), st_other
+        entry.extend_from_slice(&shndx.to_le_bytes());
+        entry.extend_from_slice(&value.to_le_bytes());
+        entry.extend_from_slice(&size.to_le_bytes());
+        entry
+    }
+
+    /// The dynamic part of an ELF that imports puts@GLIBC_2.2.5 through the
+    /// GOT slot at 0x404018
+    fn dynamic_sections() -> Vec<TestSection> {
+        let dynsym = [vec![0; SYM_SIZE], symbol(1, 0, 0, 0)].concat();
+        let dynstr = b"\0puts\0libc.so.6\0GLIBC_2.2.5\0".to_vec();
+
+        // A jump slot for puts, and a relative relocation (no symbol) that
+        // should be skipped
+        let rela = [
+            0x404018u64.to_le_bytes(), ((1u64 << 32) | R_X86_64_JUMP_SLOT as u64).to_le_bytes(), 0u64.to_le_bytes(),
+            0x404020u64.to_le_bytes(), 8u64.to_le_bytes(),                                        0u64.to_le_bytes(),
+        ].concat();
+
+        // Symbol 1 (puts) needs version 2
+        let versym = [0u16.to_le_bytes(), 2u16.to_le_bytes()].concat();
+
+        // One library (libc.so.6) that one version (GLIBC_2.2.5) is needed from
+        let verneed = [
+            &1u16.to_le_bytes()[..], &1u16.to_le_bytes(), &6u32.to_le_bytes(), &16u32.to_le_bytes(), &0u32.to_le_bytes(),
+            &0u32.to_le_bytes(), &0u16.to_le_bytes(), &2u16.to_le_bytes(), &16u32.to_le_bytes(), &0u32.to_le_bytes(),
+        ].concat();
+
+        vec![
+            TestSection::new(".dynsym",        SHT_DYNSYM,      dynsym,  2),
+            TestSection::new(".dynstr",        3,               dynstr,  0),
+            TestSection::new(".rela.plt",      SHT_RELA,        rela,    1),
+            TestSection::new(".gnu.version",   SHT_GNU_VERSYM,  versym,  1),
+            TestSection::new(".gnu.version_r", SHT_GNU_VERNEED, verneed, 2),
+        ]
+    }
+
+    /// `push rbp` / `mov rbp, rsp` / `pop rbp` / `ret`, then padding
+    const TEXT: &[u8] = b"\x55\x48\x89\xe5\x5d\xc3\x90\x90";
+
+    /// An ELF with code, a .bss, and symbols: `main` (the code), `label` (in
+    /// the code, with no size), `puts` (imported), `unloaded` (somewhere that
+    /// isn't in the file), and `too_long` (bigger than the file)
+    fn symbols_elf() -> Vec<u8> {
+        let text = BASE + FIRST_SECTION;
+
+        let symtab = [
+            vec![0; SYM_SIZE],
+            symbol(1,  1, text,     6),
+            symbol(6,  1, text + 4, 0),
+            symbol(12, 0, 0,        0),
+            symbol(17, 1, 0x900000, 4),
+            symbol(26, 1, text,     0x100000),
+        ].concat();
+        let strtab = b"\0main\0label\0puts\0unloaded\0too_long\0".to_vec();
+
+        test_elf(&[
+            TestSection::new(".text",   1,           TEXT.to_vec(), 0),
+            TestSection::new(".bss",    SHT_NOBITS,  vec![0; 16],   0),
+            TestSection::new(".symtab", SHT_SYMTAB,  symtab,        4),
+            TestSection::new(".strtab", 3,           strtab,        0),
+        ])
+    }
+
+    #[test]
+    fn section_bytes_by_name() {
+        let elf = symbols_elf();
+
+        assert_eq!(section_bytes(&elf, ".text").unwrap(), TEXT);
+        assert!(section_bytes(&elf, ".bss").unwrap_err().to_string().contains("isn't stored in the file"));
+        assert!(section_bytes(&elf, ".data").unwrap_err().to_string().contains("no section named .data"));
+    }
+
+    #[test]
+    fn symbol_bytes_by_name() {
+        let elf = symbols_elf();
+
+        assert_eq!(symbol_bytes(&elf, "main", None).unwrap(), &TEXT[..6]);
+        assert_eq!(symbol_bytes(&elf, "main", Some(2)).unwrap(), &TEXT[..2]);
+
+        // Without a size, it needs a length
+        assert!(symbol_bytes(&elf, "label", None).unwrap_err().to_string().contains("doesn't have a size"));
+        assert_eq!(symbol_bytes(&elf, "label", Some(2)).unwrap(), &TEXT[4..6]);
+    }
+
+    #[test]
+    fn symbol_bytes_errors() {
+        let elf = symbols_elf();
+
+        assert!(symbol_bytes(&elf, "puts", None).unwrap_err().to_string().contains("isn't defined in this ELF"));
+        assert!(symbol_bytes(&elf, "unloaded", None).unwrap_err().to_string().contains("isn't loaded from the file"));
+        assert!(symbol_bytes(&elf, "too_long", None).unwrap_err().to_string().contains("bytes are loaded from the file there"));
+
+        let missing = symbol_bytes(&elf, "missing", None).unwrap_err().to_string();
+        assert!(missing.contains("no symbol named missing") && !missing.contains("stripped"), "{}", missing);
+
+        // With just the dynamic symbols, the imports are there, but nothing
+        // else is
+        let stripped = test_elf(&dynamic_sections());
+        assert!(symbol_bytes(&stripped, "puts", None).unwrap_err().to_string().contains("isn't defined in this ELF"));
+        assert!(symbol_bytes(&stripped, "main", None).unwrap_err().to_string().contains("probably stripped"));
+    }
+
+    #[test]
+    fn read_address_uses_the_segments() {
+        let elf = symbols_elf();
+
+        // The segment loads the file from the start, so the headers are at
+        // the base address, and the code is after them
+        assert_eq!(read_address(&elf, BASE, 4).unwrap(), ELF_MAGIC);
+        assert_eq!(read_address(&elf, BASE + FIRST_SECTION + 4, 2).unwrap(), &TEXT[4..6]);
+
+        assert!(read_address(&elf, BASE - 1, 1).is_err());
+        assert!(read_address(&elf, BASE + FIRST_SECTION, 0x1000).unwrap_err().to_string().contains("bytes are loaded from the file there"));
+    }
+
+    #[test]
+    fn imports_name_got_slots() {
+        let imports = Imports::parse(&test_elf(&dynamic_sections())).unwrap();
+
+        assert_eq!(imports.get(0x404018), Some("puts@GLIBC_2.2.5"));
+        assert_eq!(imports.get(0x404020), None);
+
+        // Loaded somewhere else (like a PIE), the slot moves with it
+        let imports = imports.relocate(0x555555554000);
+        assert_eq!(imports.get(0x555555558018), Some("puts@GLIBC_2.2.5"));
+        assert_eq!(imports.get(0x404018), None);
+    }
+}
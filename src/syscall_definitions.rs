@@ -0,0 +1,85 @@
+//! The format of the syscall tables (`syscalls.csv` and `syscalls_x86.csv`).
+//!
+//! Each line is the syscall number, its name, then up to six parameters, each
+//! written like a C declaration (eg, `const char *filename`).
+//!
+//! `build.rs` uses this to check the built-in tables when Mandrake is
+//! compiled (so a typo fails the build, rather than an analysis), which
+//! means it can only use the standard library.
+
+/// The most parameters a syscall can have
+pub const MAX_PARAMS: usize = 6;
+
+/// One parameter, like `const char *filename` or `const char *const argv[]`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParamDefinition {
+    /// The type, without the `*`s next to the name (eg, `const char`)
+    pub field_type: String,
+    pub is_pointer: bool,
+    pub field_name: String,
+    pub is_array: bool,
+}
+
+impl ParamDefinition {
+    /// Parse a parameter: a type, a space, then any number of `*`s, the name,
+    /// and maybe `[]`.
+    pub fn parse(param: &str) -> Result<Self, String> {
+        // The type can have spaces in it too, so use the first space that
+        // has a valid name after it
+        for (i, _) in param.match_indices(' ') {
+            let (field_type, declarator) = (&param[..i], &param[i + 1..]);
+
+            let name = declarator.trim_start_matches('*');
+            let (name, is_array) = match name.strip_suffix("[]") {
+                Some(name) => (name, true),
+                None       => (name, false),
+            };
+
+            if name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+                return Ok(Self {
+                    field_type: field_type.to_string(),
+                    is_pointer: declarator.starts_with('*'),
+                    field_name: name.to_string(),
+                    is_array,
+                });
+            }
+        }
+
+        Err(format!("Could not parse syscall parameter: {}", param))
+    }
+}
+
+/// One line of a syscall table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyscallDefinition {
+    pub number: u64,
+    pub name: String,
+    pub params: Vec<ParamDefinition>,
+}
+
+impl SyscallDefinition {
+    /// Parse the fields of one line of a syscall table
+    pub fn parse(fields: &[&str]) -> Result<Self, String> {
+        let number = fields.first()
+            .ok_or_else(|| "missing the syscall number".to_string())?
+            .trim()
+            .parse()
+            .map_err(|e| format!("Couldn't parse the syscall number as an integer: {}", e))?;
+
+        let name = match fields.get(1) {
+            Some(name) if !name.is_empty() => name.to_string(),
+            _ => return Err("missing the syscall name".to_string()),
+        };
+
+        let params = fields.get(2..).unwrap_or_default();
+        if params.len() > MAX_PARAMS {
+            return Err(format!("{} has {} parameters, but syscalls can only have {}", name, params.len(), MAX_PARAMS));
+        }
+
+        Ok(Self {
+            number,
+            name,
+            params: params.iter().map(|param| ParamDefinition::parse(param)).collect::<Result<_, _>>()?,
+        })
+    }
+}
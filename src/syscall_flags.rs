@@ -0,0 +1,502 @@
+//! Decoders for syscall parameters that are flags or operation codes.
+//!
+//! Most parameters are printed as a plain value, but some (like `clone`'s
+//! flags) are much more useful when they're broken down into their names.
+//! Each decoder is registered in [`FLAG_DECODERS`] against a syscall and a
+//! parameter name, and is attached to the parameter when the syscall table is
+//! loaded.
+
+use std::convert::TryFrom;
+
+use nix::sys::signal::Signal;
+
+/// A function that turns a parameter's value into something readable
+pub type FlagDecoder = fn(u64) -> String;
+
+/// The decoders, as (syscall name, parameter name, decoder)
+pub const FLAG_DECODERS: &[(&str, &str, FlagDecoder)] = &[
+    ("sys_clone",      "clone_flags", decode_clone_flags),
+    ("sys_futex",      "op",          decode_futex_op),
+    ("sys_ioctl",      "cmd",         decode_ioctl_request),
+    ("sys_open",       "flags",       decode_open_flags),
+    ("sys_openat",     "flags",       decode_open_flags),
+    ("sys_open",       "mode",        decode_file_mode),
+    ("sys_openat",     "mode",        decode_file_mode),
+    ("sys_creat",      "mode",        decode_file_mode),
+    ("sys_mkdir",      "mode",        decode_file_mode),
+    ("sys_chmod",      "mode",        decode_file_mode),
+    ("sys_fchmod",     "mode",        decode_file_mode),
+    ("sys_access",     "mode",        decode_access_mode),
+    ("sys_faccessat",  "mode",        decode_access_mode),
+    ("sys_mmap",       "prot",        decode_mmap_prot),
+    ("sys_mprotect",   "prot",        decode_mmap_prot),
+    ("sys_mmap",       "flags",       decode_mmap_flags),
+    ("sys_socket",     "family",      decode_socket_family),
+    ("sys_socketpair", "family",      decode_socket_family),
+    ("sys_socket",     "type",        decode_socket_type),
+    ("sys_socketpair", "type",        decode_socket_type),
+];
+
+const CLONE_FLAGS: &[(u64, &str)] = &[
+    (0x00000100, "CLONE_VM"),
+    (0x00000200, "CLONE_FS"),
+    (0x00000400, "CLONE_FILES"),
+    (0x00000800, "CLONE_SIGHAND"),
+    (0x00001000, "CLONE_PIDFD"),
+    (0x00002000, "CLONE_PTRACE"),
+    (0x00004000, "CLONE_VFORK"),
+    (0x00008000, "CLONE_PARENT"),
+    (0x00010000, "CLONE_THREAD"),
+    (0x00020000, "CLONE_NEWNS"),
+    (0x00040000, "CLONE_SYSVSEM"),
+    (0x00080000, "CLONE_SETTLS"),
+    (0x00100000, "CLONE_PARENT_SETTID"),
+    (0x00200000, "CLONE_CHILD_CLEARTID"),
+    (0x00400000, "CLONE_DETACHED"),
+    (0x00800000, "CLONE_UNTRACED"),
+    (0x01000000, "CLONE_CHILD_SETTID"),
+    (0x02000000, "CLONE_NEWCGROUP"),
+    (0x04000000, "CLONE_NEWUTS"),
+    (0x08000000, "CLONE_NEWIPC"),
+    (0x10000000, "CLONE_NEWUSER"),
+    (0x20000000, "CLONE_NEWPID"),
+    (0x40000000, "CLONE_NEWNET"),
+    (0x80000000, "CLONE_IO"),
+];
+
+// The low byte of clone's flags is the signal sent to the parent when the
+// child exits
+const CLONE_SIGNAL_MASK: u64 = 0xff;
+
+// Flags are matched in order, and a flag only matches if all of its bits are
+// set - so flags that include other flags (like O_SYNC, which includes
+// O_DSYNC) have to come first
+const OPEN_FLAGS: &[(u64, &str)] = &[
+    (0x00410000, "O_TMPFILE"),
+    (0x00101000, "O_SYNC"),
+    (0x00000040, "O_CREAT"),
+    (0x00000080, "O_EXCL"),
+    (0x00000100, "O_NOCTTY"),
+    (0x00000200, "O_TRUNC"),
+    (0x00000400, "O_APPEND"),
+    (0x00000800, "O_NONBLOCK"),
+    (0x00001000, "O_DSYNC"),
+    (0x00002000, "O_ASYNC"),
+    (0x00004000, "O_DIRECT"),
+    (0x00008000, "O_LARGEFILE"),
+    (0x00010000, "O_DIRECTORY"),
+    (0x00020000, "O_NOFOLLOW"),
+    (0x00040000, "O_NOATIME"),
+    (0x00080000, "O_CLOEXEC"),
+    (0x00200000, "O_PATH"),
+];
+
+// The low two bits of the open flags are the access mode
+const OPEN_ACCESS_MODES: &[(u64, &str)] = &[
+    (0, "O_RDONLY"),
+    (1, "O_WRONLY"),
+    (2, "O_RDWR"),
+];
+const OPEN_ACCESS_MASK: u64 = 0x3;
+
+const ACCESS_MODES: &[(u64, &str)] = &[
+    (4, "R_OK"),
+    (2, "W_OK"),
+    (1, "X_OK"),
+];
+
+const MMAP_PROT: &[(u64, &str)] = &[
+    (0x00000001, "PROT_READ"),
+    (0x00000002, "PROT_WRITE"),
+    (0x00000004, "PROT_EXEC"),
+    (0x01000000, "PROT_GROWSDOWN"),
+    (0x02000000, "PROT_GROWSUP"),
+];
+
+const MMAP_FLAGS: &[(u64, &str)] = &[
+    (0x00000003, "MAP_SHARED_VALIDATE"),
+    (0x00000001, "MAP_SHARED"),
+    (0x00000002, "MAP_PRIVATE"),
+    (0x00000010, "MAP_FIXED"),
+    (0x00000020, "MAP_ANONYMOUS"),
+    (0x00000040, "MAP_32BIT"),
+    (0x00000100, "MAP_GROWSDOWN"),
+    (0x00000800, "MAP_DENYWRITE"),
+    (0x00001000, "MAP_EXECUTABLE"),
+    (0x00002000, "MAP_LOCKED"),
+    (0x00004000, "MAP_NORESERVE"),
+    (0x00008000, "MAP_POPULATE"),
+    (0x00010000, "MAP_NONBLOCK"),
+    (0x00020000, "MAP_STACK"),
+    (0x00040000, "MAP_HUGETLB"),
+    (0x00080000, "MAP_SYNC"),
+    (0x00100000, "MAP_FIXED_NOREPLACE"),
+];
+
+const SOCKET_FAMILIES: &[(u64, &str)] = &[
+    (0,  "AF_UNSPEC"),
+    (1,  "AF_UNIX"),
+    (2,  "AF_INET"),
+    (3,  "AF_AX25"),
+    (4,  "AF_IPX"),
+    (5,  "AF_APPLETALK"),
+    (10, "AF_INET6"),
+    (16, "AF_NETLINK"),
+    (17, "AF_PACKET"),
+    (31, "AF_BLUETOOTH"),
+    (38, "AF_ALG"),
+    (40, "AF_VSOCK"),
+];
+
+// The low four bits of a socket's type are the type, and the rest are flags
+const SOCKET_TYPES: &[(u64, &str)] = &[
+    (1,  "SOCK_STREAM"),
+    (2,  "SOCK_DGRAM"),
+    (3,  "SOCK_RAW"),
+    (4,  "SOCK_RDM"),
+    (5,  "SOCK_SEQPACKET"),
+    (6,  "SOCK_DCCP"),
+    (10, "SOCK_PACKET"),
+];
+const SOCKET_TYPE_MASK: u64 = 0xf;
+
+const SOCKET_TYPE_FLAGS: &[(u64, &str)] = &[
+    (0x00000800, "SOCK_NONBLOCK"),
+    (0x00080000, "SOCK_CLOEXEC"),
+];
+
+const FUTEX_OPS: &[&str] = &[
+    "FUTEX_WAIT",
+    "FUTEX_WAKE",
+    "FUTEX_FD",
+    "FUTEX_REQUEUE",
+    "FUTEX_CMP_REQUEUE",
+    "FUTEX_WAKE_OP",
+    "FUTEX_LOCK_PI",
+    "FUTEX_UNLOCK_PI",
+    "FUTEX_TRYLOCK_PI",
+    "FUTEX_WAIT_BITSET",
+    "FUTEX_WAKE_BITSET",
+    "FUTEX_WAIT_REQUEUE_PI",
+    "FUTEX_CMP_REQUEUE_PI",
+    "FUTEX_LOCK_PI2",
+];
+
+const FUTEX_PRIVATE_FLAG: u64 = 0x80;
+const FUTEX_CLOCK_REALTIME: u64 = 0x100;
+
+// Well-known ioctl requests (x86-64 values)
+const IOCTL_REQUESTS: &[(u64, &str)] = &[
+    (0x00000001, "FIBMAP"),
+    (0x00000002, "FIGETBSZ"),
+    (0x00005401, "TCGETS"),
+    (0x00005402, "TCSETS"),
+    (0x00005403, "TCSETSW"),
+    (0x00005404, "TCSETSF"),
+    (0x00005405, "TCGETA"),
+    (0x00005406, "TCSETA"),
+    (0x00005409, "TCSBRK"),
+    (0x0000540a, "TCXONC"),
+    (0x0000540b, "TCFLSH"),
+    (0x0000540c, "TIOCEXCL"),
+    (0x0000540d, "TIOCNXCL"),
+    (0x0000540e, "TIOCSCTTY"),
+    (0x0000540f, "TIOCGPGRP"),
+    (0x00005410, "TIOCSPGRP"),
+    (0x00005411, "TIOCOUTQ"),
+    (0x00005412, "TIOCSTI"),
+    (0x00005413, "TIOCGWINSZ"),
+    (0x00005414, "TIOCSWINSZ"),
+    (0x00005415, "TIOCMGET"),
+    (0x00005418, "TIOCMSET"),
+    (0x0000541b, "FIONREAD"),
+    (0x0000541c, "TIOCLINUX"),
+    (0x00005421, "FIONBIO"),
+    (0x00005422, "TIOCNOTTY"),
+    (0x00005450, "FIONCLEX"),
+    (0x00005451, "FIOCLEX"),
+    (0x00005452, "FIOASYNC"),
+    (0x00008912, "SIOCGIFCONF"),
+    (0x00008913, "SIOCGIFFLAGS"),
+    (0x00008914, "SIOCSIFFLAGS"),
+    (0x00008915, "SIOCGIFADDR"),
+    (0x00008927, "SIOCGIFHWADDR"),
+    (0x00008933, "SIOCGIFINDEX"),
+    (0x40045431, "TIOCSPTLCK"),
+    (0x400454ca, "TUNSETIFF"),
+    (0x80045430, "TIOCGPTN"),
+    (0x80081272, "BLKGETSIZE64"),
+];
+
+// How an ioctl request number is laid out (see _IOC in asm-generic/ioctl.h)
+const IOC_NR_SHIFT: u64 = 0;
+const IOC_TYPE_SHIFT: u64 = 8;
+const IOC_SIZE_SHIFT: u64 = 16;
+const IOC_DIR_SHIFT: u64 = 30;
+
+const IOC_NR_MASK: u64 = 0xff;
+const IOC_TYPE_MASK: u64 = 0xff;
+const IOC_SIZE_MASK: u64 = 0x3fff;
+const IOC_DIR_MASK: u64 = 0x3;
+
+/// Find the decoder for a syscall's parameter, if there is one
+pub fn find_decoder(syscall_name: &str, field_name: &str) -> Option<FlagDecoder> {
+    FLAG_DECODERS.iter()
+        .find(|(syscall, field, _)| *syscall == syscall_name && *field == field_name)
+        .map(|(_, _, decoder)| *decoder)
+}
+
+/// Break a value into the names of its flags. Any bits we don't know the
+/// name of are left at the end, as hex, so nothing is hidden.
+fn decode_bitmask(value: u64, flags: &[(u64, &str)]) -> Vec<String> {
+    let mut out: Vec<String> = vec![];
+    let mut remaining = value;
+
+    for (flag, name) in flags {
+        if remaining & flag == *flag {
+            out.push(name.to_string());
+            remaining &= !flag;
+        }
+    }
+
+    // Anything we don't know the name of
+    if remaining != 0 {
+        out.push(format!("0x{:x}", remaining));
+    }
+
+    out
+}
+
+/// Look up the name of a value that's one of a list of options (or show it
+/// as hex if we don't know it)
+fn decode_enum(value: u64, options: &[(u64, &str)]) -> String {
+    match options.iter().find(|(option, _)| *option == value) {
+        Some((_, name)) => name.to_string(),
+        None            => format!("0x{:x}", value),
+    }
+}
+
+/// Join flags with `|`, or use `empty` if there aren't any
+fn join_flags(flags: Vec<String>, empty: &str) -> String {
+    match flags.is_empty() {
+        true  => empty.to_string(),
+        false => flags.join("|"),
+    }
+}
+
+/// Decode the flags passed to `clone` (eg, `CLONE_VM|CLONE_FS|SIGCHLD`)
+pub fn decode_clone_flags(value: u64) -> String {
+    let mut out = decode_bitmask(value & !CLONE_SIGNAL_MASK, CLONE_FLAGS);
+
+    // The exit signal goes at the end, the way strace does it
+    let signal = value & CLONE_SIGNAL_MASK;
+    if signal != 0 {
+        match Signal::try_from(signal as i32) {
+            Ok(signal) => out.push(format!("{:?}", signal)),
+            Err(_)     => out.push(format!("signal {}", signal)),
+        }
+    }
+
+    join_flags(out, "0")
+}
+
+/// Decode the flags passed to `open` (eg, `O_WRONLY|O_CREAT|O_TRUNC`)
+pub fn decode_open_flags(value: u64) -> String {
+    // It's an int, so ignore the upper half of the register
+    let value = value & 0xffffffff;
+
+    let mut out = vec![decode_enum(value & OPEN_ACCESS_MASK, OPEN_ACCESS_MODES)];
+    out.extend(decode_bitmask(value & !OPEN_ACCESS_MASK, OPEN_FLAGS));
+
+    out.join("|")
+}
+
+/// Show a file's permissions in octal, the way `chmod` takes them (eg, `0644`)
+pub fn decode_file_mode(value: u64) -> String {
+    format!("0{:o}", value & 0xffffffff)
+}
+
+/// Decode the mode passed to `access` (eg, `R_OK|W_OK`)
+pub fn decode_access_mode(value: u64) -> String {
+    join_flags(decode_bitmask(value & 0xffffffff, ACCESS_MODES), "F_OK")
+}
+
+/// Decode memory protection (eg, `PROT_READ|PROT_EXEC`)
+pub fn decode_mmap_prot(value: u64) -> String {
+    join_flags(decode_bitmask(value, MMAP_PROT), "PROT_NONE")
+}
+
+/// Decode the flags passed to `mmap` (eg, `MAP_PRIVATE|MAP_ANONYMOUS`)
+pub fn decode_mmap_flags(value: u64) -> String {
+    join_flags(decode_bitmask(value, MMAP_FLAGS), "0")
+}
+
+/// Decode the family passed to `socket` (eg, `AF_INET`)
+pub fn decode_socket_family(value: u64) -> String {
+    decode_enum(value & 0xffffffff, SOCKET_FAMILIES)
+}
+
+/// Decode the type passed to `socket` (eg, `SOCK_STREAM|SOCK_CLOEXEC`)
+pub fn decode_socket_type(value: u64) -> String {
+    let value = value & 0xffffffff;
+
+    let mut out = vec![decode_enum(value & SOCKET_TYPE_MASK, SOCKET_TYPES)];
+    out.extend(decode_bitmask(value & !SOCKET_TYPE_MASK, SOCKET_TYPE_FLAGS));
+
+    out.join("|")
+}
+
+/// Decode the operation passed to `futex` (eg, `FUTEX_WAIT|FUTEX_PRIVATE_FLAG`)
+pub fn decode_futex_op(value: u64) -> String {
+    // It's an int, so ignore the upper half of the register
+    let value = value & 0xffffffff;
+    let command = value & !(FUTEX_PRIVATE_FLAG | FUTEX_CLOCK_REALTIME);
+
+    let mut out: Vec<String> = vec![
+        match FUTEX_OPS.get(command as usize) {
+            Some(name) => name.to_string(),
+            None       => format!("0x{:x}", command),
+        }
+    ];
+
+    if value & FUTEX_PRIVATE_FLAG != 0 {
+        out.push("FUTEX_PRIVATE_FLAG".to_string());
+    }
+
+    if value & FUTEX_CLOCK_REALTIME != 0 {
+        out.push("FUTEX_CLOCK_REALTIME".to_string());
+    }
+
+    out.join("|")
+}
+
+/// Decode an `ioctl` request into its `_IOC` parts, with the name if it's a
+/// well-known request (eg, `TIOCGPTN (_IOR('T', 0x30, 4))`)
+pub fn decode_ioctl_request(value: u64) -> String {
+    // It's an unsigned int, so ignore the upper half of the register
+    let value = value & 0xffffffff;
+
+    let nr   = (value >> IOC_NR_SHIFT) & IOC_NR_MASK;
+    let kind = (value >> IOC_TYPE_SHIFT) & IOC_TYPE_MASK;
+    let size = (value >> IOC_SIZE_SHIFT) & IOC_SIZE_MASK;
+    let dir  = (value >> IOC_DIR_SHIFT) & IOC_DIR_MASK;
+
+    let kind = match (kind as u8).is_ascii_graphic() {
+        true  => format!("'{}'", kind as u8 as char),
+        false => format!("0x{:x}", kind),
+    };
+
+    let components = match dir {
+        1 => format!("_IOW({}, 0x{:x}, {})", kind, nr, size),
+        2 => format!("_IOR({}, 0x{:x}, {})", kind, nr, size),
+        3 => format!("_IOWR({}, 0x{:x}, {})", kind, nr, size),
+        _ => format!("_IO({}, 0x{:x})", kind, nr),
+    };
+
+    match IOCTL_REQUESTS.iter().find(|(request, _)| *request == value) {
+        Some((_, name)) => format!("{} ({})", name, components),
+        None            => components,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clone_flags_for_a_thread() {
+        // What glibc's pthread_create passes
+        assert_eq!(
+            decode_clone_flags(0x3d0f00),
+            "CLONE_VM|CLONE_FS|CLONE_FILES|CLONE_SIGHAND|CLONE_THREAD|CLONE_SYSVSEM|CLONE_SETTLS|CLONE_PARENT_SETTID|CLONE_CHILD_CLEARTID",
+        );
+    }
+
+    #[test]
+    fn clone_flags_with_a_signal() {
+        assert_eq!(decode_clone_flags(0x01200011), "CLONE_CHILD_CLEARTID|CLONE_CHILD_SETTID|SIGCHLD");
+        assert_eq!(decode_clone_flags(0), "0");
+    }
+
+    #[test]
+    fn clone_flags_with_unknown_bits() {
+        assert_eq!(decode_clone_flags(0x100000100), "CLONE_VM|0x100000000");
+    }
+
+    #[test]
+    fn futex_ops() {
+        assert_eq!(decode_futex_op(0x80), "FUTEX_WAIT|FUTEX_PRIVATE_FLAG");
+        assert_eq!(decode_futex_op(0x101), "FUTEX_WAKE|FUTEX_CLOCK_REALTIME");
+
+        // The upper half of the register isn't part of it
+        assert_eq!(decode_futex_op(0xffffffff00000081), "FUTEX_WAKE|FUTEX_PRIVATE_FLAG");
+    }
+
+    #[test]
+    fn futex_op_with_unknown_bits() {
+        assert_eq!(decode_futex_op(0x40), "0x40");
+        assert_eq!(decode_futex_op(0x1c0), "0x40|FUTEX_PRIVATE_FLAG|FUTEX_CLOCK_REALTIME");
+    }
+
+    #[test]
+    fn open_flags() {
+        assert_eq!(decode_open_flags(0x241), "O_WRONLY|O_CREAT|O_TRUNC");
+        assert_eq!(decode_open_flags(0), "O_RDONLY");
+        assert_eq!(decode_open_flags(0x80002), "O_RDWR|O_CLOEXEC");
+
+        // O_SYNC includes O_DSYNC's bit, so it's not named twice
+        assert_eq!(decode_open_flags(0x101001), "O_WRONLY|O_SYNC");
+
+        // The upper half of the register isn't part of it
+        assert_eq!(decode_open_flags(0xffffffff00000000), "O_RDONLY");
+    }
+
+    #[test]
+    fn open_flags_with_unknown_bits() {
+        assert_eq!(decode_open_flags(0x3), "0x3");
+        assert_eq!(decode_open_flags(0x10000040), "O_RDONLY|O_CREAT|0x10000000");
+    }
+
+    #[test]
+    fn file_modes() {
+        assert_eq!(decode_file_mode(0o755), "0755");
+        assert_eq!(decode_file_mode(0o4644), "04644");
+        assert_eq!(decode_file_mode(0), "00");
+    }
+
+    #[test]
+    fn access_modes() {
+        assert_eq!(decode_access_mode(0), "F_OK");
+        assert_eq!(decode_access_mode(6), "R_OK|W_OK");
+        assert_eq!(decode_access_mode(0x11), "X_OK|0x10");
+    }
+
+    #[test]
+    fn mmap_prot() {
+        assert_eq!(decode_mmap_prot(0), "PROT_NONE");
+        assert_eq!(decode_mmap_prot(5), "PROT_READ|PROT_EXEC");
+        assert_eq!(decode_mmap_prot(0x1000003), "PROT_READ|PROT_WRITE|PROT_GROWSDOWN");
+        assert_eq!(decode_mmap_prot(0x10), "0x10");
+    }
+
+    #[test]
+    fn mmap_flags() {
+        assert_eq!(decode_mmap_flags(0x22), "MAP_PRIVATE|MAP_ANONYMOUS");
+
+        // MAP_SHARED_VALIDATE includes both sharing bits
+        assert_eq!(decode_mmap_flags(0x3), "MAP_SHARED_VALIDATE");
+        assert_eq!(decode_mmap_flags(0), "0");
+        assert_eq!(decode_mmap_flags(0x200001), "MAP_SHARED|0x200000");
+    }
+
+    #[test]
+    fn socket_arguments() {
+        assert_eq!(decode_socket_family(2), "AF_INET");
+        assert_eq!(decode_socket_family(99), "0x63");
+
+        assert_eq!(decode_socket_type(1), "SOCK_STREAM");
+        assert_eq!(decode_socket_type(0x80802), "SOCK_DGRAM|SOCK_NONBLOCK|SOCK_CLOEXEC");
+        assert_eq!(decode_socket_type(0x10003), "SOCK_RAW|0x10000");
+        assert_eq!(decode_socket_type(0), "0x0");
+    }
+}
@@ -1,8 +1,15 @@
 use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::OnceLock;
 
 use lazy_static::lazy_static;
-use regex::Regex;
-use simple_error::SimpleError;
+use nix::errno::Errno;
+use nix::libc::user_regs_struct;
+use simple_error::{bail, SimpleError, SimpleResult};
+
+use crate::syscall_definitions::{ParamDefinition, SyscallDefinition};
+use crate::syscall_flags::{find_decoder, FlagDecoder};
 
 /// A single syscall parameter
 #[derive(Debug)]
@@ -12,38 +19,72 @@ pub struct SyscallEntry {
     pub is_pointer: bool,
     pub field_name: String,
     pub is_array: bool,
-}
 
-impl SyscallEntry {
-    /// Parse a syscall parameter from a string-based definition
-    pub fn new(syscall_param: &str) -> Self {
-        // Match with everything before the identifier, then the identifier
-                            // type  0+ *  identifier     optional []
-        let re = Regex::new(r"^(.*?) (\**)([a-zA-Z0-9_-]*)(\[\])?$").unwrap();
+    /// If set, used to display the value (see [`crate::syscall_flags`])
+    pub decoder: Option<FlagDecoder>,
 
-        if let Some(out) = re.captures(syscall_param) {
-            let out = SyscallEntry {
-                field_type:  out.get(1).unwrap().as_str().to_string(),
-                is_string:   out.get(1).unwrap().as_str().contains("char"),
-                is_pointer:  out.get(2).unwrap().as_str().contains('*'),
-                field_name:  out.get(3).unwrap().as_str().to_string(),
-                is_array:    match &out.get(4) {
-                    Some(a) => a.as_str() == "[]",
-                    None    => false,
-                },
-            };
+    /// If this is a buffer, which parameter (0 = rdi, 1 = rsi, etc) has its
+    /// length
+    pub length_from: Option<usize>,
+
+    /// Set if the syscall fills in this buffer (so it has nothing useful in
+    /// it yet when we see the syscall)
+    pub is_output: bool,
+}
 
-            out
-        } else {
-            panic!("Could not parse syscall parameter: {}", syscall_param);
+impl SyscallEntry {
+    /// Make an entry from a parameter's definition (without any of the
+    /// extras, like a decoder)
+    fn from_definition(param: ParamDefinition) -> Self {
+        SyscallEntry {
+            is_string:   param.field_type.contains("char"),
+            field_type:  param.field_type,
+            is_pointer:  param.is_pointer,
+            field_name:  param.field_name,
+            is_array:    param.is_array,
+            decoder:     None,
+            length_from: None,
+            is_output:   false,
         }
     }
 }
 
+/// Buffers whose length is another parameter, as (syscall name, buffer
+/// parameter, length parameter, whether the syscall fills the buffer in)
+const BUFFER_LENGTHS: &[(&str, &str, &str, bool)] = &[
+    ("sys_read",     "buf",  "count", true),
+    ("sys_write",    "buf",  "count", false),
+    ("sys_pread64",  "buf",  "count", true),
+    ("sys_pwrite64", "buf",  "count", false),
+    ("sys_sendto",   "buff", "len",   false),
+    ("sys_recvfrom", "ubuf", "size",  true),
+];
+
+/// Other parameters that the syscall fills in, as (syscall name, parameter)
+const OUTPUT_PARAMS: &[(&str, &str)] = &[
+    ("sys_accept",      "upeer_sockaddr"),
+    ("sys_accept4",     "upeer_sockaddr"),
+    ("sys_recvfrom",    "addr"),
+    ("sys_getsockname", "usockaddr"),
+    ("sys_getpeername", "usockaddr"),
+];
+
+/// Syscalls that return an address (which is easier to read in hex)
+const ADDRESS_RETURNS: &[&str] = &["sys_mmap", "sys_mmap2", "sys_mremap", "sys_brk", "sys_shmat"];
+
+// The mmap protection bits (see `syscall_flags`)
+const PROT_READ: u64 = 0x1;
+const PROT_WRITE: u64 = 0x2;
+const PROT_EXEC: u64 = 0x4;
+
+// The kernel returns errors as -1 to -4095 (see `IS_ERR_VALUE`)
+const MAX_ERRNO: i64 = 4095;
+
 /// Defines a syscall.
 ///
-/// This is populated from the `syscalls.csv` file, which is loaded at compile-
-/// time. That file, in turn, is based on Ryan Chapman's blog:
+/// This is populated from the `syscalls.csv` file, which is checked and loaded
+/// at compile-time (by build.rs). That file, in turn, is based on Ryan
+/// Chapman's blog:
 ///
 /// https://blog.rchapman.org/posts/Linux_System_Call_Table_for_x86_64/
 #[derive(Debug)]
@@ -57,44 +98,472 @@ pub struct Syscall {
     pub r9:  Option<SyscallEntry>,
 }
 
+impl Syscall {
+    /// The parameters, in the order they're passed (see
+    /// [`SyscallAbi::argument_registers`] for which registers they're in -
+    /// the fields are named for the 64-bit ABI)
+    pub fn params(&self) -> [&Option<SyscallEntry>; 6] {
+        [&self.rdi, &self.rsi, &self.rdx, &self.r10, &self.r8, &self.r9]
+    }
+
+    /// Make a syscall from its definition, attaching anything special we know
+    /// about its parameters (decoders, buffer lengths, and outputs)
+    fn from_definition(definition: SyscallDefinition) -> Self {
+        let name = definition.name;
+
+        let mut params: Vec<Option<SyscallEntry>> = definition.params.into_iter().map(|param| {
+            let mut entry = SyscallEntry::from_definition(param);
+            entry.decoder = find_decoder(&name, &entry.field_name);
+            Some(entry)
+        }).collect();
+
+        // Link buffers to their lengths
+        for (_, buffer, length, is_output) in BUFFER_LENGTHS.iter().filter(|(syscall, _, _, _)| *syscall == name) {
+            let length_from = params.iter().position(|p| matches!(p, Some(p) if p.field_name == *length));
+
+            if let Some(Some(param)) = params.iter_mut().find(|p| matches!(p, Some(p) if p.field_name == *buffer)) {
+                param.length_from = length_from;
+                param.is_output = *is_output;
+            }
+        }
+
+        for (_, output) in OUTPUT_PARAMS.iter().filter(|(syscall, _)| *syscall == name) {
+            if let Some(Some(param)) = params.iter_mut().find(|p| matches!(p, Some(p) if p.field_name == *output)) {
+                param.is_output = true;
+            }
+        }
+
+        let mut params = params.into_iter();
+        Syscall {
+            rdi: params.next().flatten(),
+            rsi: params.next().flatten(),
+            rdx: params.next().flatten(),
+            r10: params.next().flatten(),
+            r8:  params.next().flatten(),
+            r9:  params.next().flatten(),
+            name,
+        }
+    }
+
+    /// Check if this is the syscall a user named, with or without the `sys_`
+    /// prefix (eg, `futex` or `sys_futex`)
+    pub fn has_name(&self, name: &str) -> bool {
+        self.name == name || self.name.strip_prefix("sys_") == Some(name)
+    }
+}
+
+/// The ways a process can make a syscall, which each have their own syscall
+/// numbers and argument registers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyscallAbi {
+    /// The `syscall` instruction
+    X86_64,
+
+    /// The legacy 32-bit interface (`int 0x80` or `sysenter`), which 64-bit
+    /// processes can still use - the numbers are completely different (eg,
+    /// 11 is execve), and only the low 32 bits of each register are used
+    X86,
+}
+
+impl SyscallAbi {
+    /// Which interface an instruction (as disassembled) makes a syscall with,
+    /// if it makes one
+    pub fn from_instruction(instruction: &str) -> Option<Self> {
+        match instruction {
+            "syscall"              => Some(Self::X86_64),
+            "int 80h" | "sysenter" => Some(Self::X86),
+            _                      => None,
+        }
+    }
+
+    /// The registers that hold the arguments, in order (the syscall number is
+    /// always in rax)
+    pub fn argument_registers(&self) -> [&'static str; 6] {
+        match self {
+            Self::X86_64 => ["rdi", "rsi", "rdx", "r10", "r8", "r9"],
+            Self::X86    => ["rbx", "rcx", "rdx", "rsi", "rdi", "rbp"],
+        }
+    }
+
+    /// Get the arguments from the registers, in order
+    pub fn arguments(&self, regs: &user_regs_struct) -> [u64; 6] {
+        match self {
+            Self::X86_64 => [regs.rdi, regs.rsi, regs.rdx, regs.r10, regs.r8, regs.r9],
+            Self::X86    => [regs.rbx, regs.rcx, regs.rdx, regs.rsi, regs.rdi, regs.rbp].map(|r| r & 0xffffffff),
+        }
+    }
+
+    /// Whether the number in rax is `execve` (checked by number, so it works
+    /// without the syscall tables)
+    pub fn is_execve(&self, rax: u64) -> bool {
+        match self {
+            Self::X86_64 => rax == 59,
+            Self::X86    => rax & 0xffffffff == 11,
+        }
+    }
+
+    /// Whether the number in rax is `exit` or `exit_group` (checked by
+    /// number, like [`Self::is_execve`])
+    pub fn is_exit(&self, rax: u64) -> bool {
+        match self {
+            Self::X86_64 => rax == 60 || rax == 231,
+            Self::X86    => matches!(rax & 0xffffffff, 1 | 252),
+        }
+    }
+
+    /// Whether the number in rax is `write` (checked by number, like
+    /// [`Self::is_execve`])
+    pub fn is_write(&self, rax: u64) -> bool {
+        match self {
+            Self::X86_64 => rax == 1,
+            Self::X86    => rax & 0xffffffff == 4,
+        }
+    }
+
+    /// Whether the number in rax is a syscall that can change the memory map
+    /// (mmap, mprotect, munmap, brk, mremap, shmat, shmdt, or execve), so it
+    /// has to be read again afterwards (checked by number, like
+    /// [`Self::is_execve`])
+    pub fn changes_memory_map(&self, rax: u64) -> bool {
+        match self {
+            Self::X86_64 => matches!(rax, 9 | 10 | 11 | 12 | 25 | 30 | 67 | 59),
+
+            // The old mmap and mmap2 are both here, and shmat and shmdt can
+            // also be made through ipc (117)
+            Self::X86    => matches!(rax & 0xffffffff, 90 | 192 | 125 | 91 | 45 | 163 | 397 | 398 | 117 | 11),
+        }
+    }
+
+    /// Whether the number in rax maps memory, with the address, length, and
+    /// protection in the first three arguments: `mmap`, or `mmap2` for the
+    /// 32-bit ABI (its old `mmap` passes a struct, so it's not counted).
+    /// It's checked by number, like [`Self::is_execve`].
+    pub fn is_mmap(&self, rax: u64) -> bool {
+        match self {
+            Self::X86_64 => rax == 9,
+            Self::X86    => rax & 0xffffffff == 192,
+        }
+    }
+
+    /// How big the pointers passed to a syscall are
+    pub fn pointer_size(&self) -> usize {
+        match self {
+            Self::X86_64 => 8,
+            Self::X86    => 4,
+        }
+    }
+
+    /// Look up a syscall by the number in rax
+    pub fn find(&self, rax: u64) -> Option<&'static Syscall> {
+        match self {
+            Self::X86_64 => find_syscall(rax),
+            Self::X86    => SYSCALLS_X86.get(&(rax & 0xffffffff)),
+        }
+    }
+
+    /// A note to add to the syscall's name, so it's clear which table it's
+    /// from
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::X86_64 => "",
+            Self::X86    => " (32-bit ABI)",
+        }
+    }
+}
+
+impl fmt::Display for SyscallAbi {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::X86_64 => write!(f, "x86_64"),
+            Self::X86    => write!(f, "x86"),
+        }
+    }
+}
+
+/// A syscall from a table that build.rs checked: (number, name, parameters),
+/// and each parameter is (type, is_pointer, name, is_array)
+type GeneratedSyscall = (u64, &'static str, &'static [(&'static str, bool, &'static str, bool)]);
+
+// The built-in tables, SYSCALL_TABLE and SYSCALL_TABLE_X86
+#[cfg(feature = "syscalls")]
+include!(concat!(env!("OUT_DIR"), "/syscall_tables.rs"));
+
+// Without the `syscalls` feature there are no built-in tables, so every
+// syscall is shown as just its number (unless `--syscalls-csv` defines it)
+#[cfg(not(feature = "syscalls"))]
+static SYSCALL_TABLE: &[GeneratedSyscall] = &[];
+#[cfg(not(feature = "syscalls"))]
+static SYSCALL_TABLE_X86: &[GeneratedSyscall] = &[];
+
+/// Turn a table that build.rs generated into syscalls, by number
+fn from_generated(table: &[GeneratedSyscall]) -> HashMap<u64, Syscall> {
+    table.iter().map(|(number, name, params)| {
+        let definition = SyscallDefinition {
+            number: *number,
+            name: name.to_string(),
+            params: params.iter().map(|(field_type, is_pointer, field_name, is_array)| ParamDefinition {
+                field_type: field_type.to_string(),
+                is_pointer: *is_pointer,
+                field_name: field_name.to_string(),
+                is_array: *is_array,
+            }).collect(),
+        };
+
+        (*number, Syscall::from_definition(definition))
+    }).collect()
+}
+
 lazy_static! {
-    /// Enumerations comment
-    pub static ref SYSCALLS: HashMap<u64, Syscall> = {
-        let mut out: HashMap<u64, Syscall> = HashMap::new();
-
-        let mut rdr = csv::ReaderBuilder::new()
-            .has_headers(false)
-            .flexible(true)
-            .from_reader(include_str!("./syscalls.csv").as_bytes());
-
-        for result in rdr.records() {
-            let record = result.map_err(|e| {
-                SimpleError::new(format!("Couldn't read CSV: {}", e))
-            }).unwrap();
-
-            let rax: u64 = record.get(0).ok_or(
-                SimpleError::new("Error reading the CSV file")
-            ).unwrap().parse().map_err(|e| {
-                SimpleError::new(format!("Couldn't parse first CSV field as integer: {}", e))
-            }).unwrap();
-
-            if out.contains_key(&rax) {
-                panic!("Duplicate key in syscall CSV: {}", rax);
+    /// The syscalls, by number (from `syscalls.csv`)
+    pub static ref SYSCALLS: HashMap<u64, Syscall> = from_generated(SYSCALL_TABLE);
+
+    /// The legacy 32-bit syscalls, by number (from `syscalls_x86.csv`) - the
+    /// parameters are in the order they're passed (ebx, ecx, edx, esi, edi,
+    /// then ebp)
+    pub static ref SYSCALLS_X86: HashMap<u64, Syscall> = from_generated(SYSCALL_TABLE_X86);
+}
+
+/// Syscall definitions loaded at runtime (see [`load_syscalls_csv`]), and the
+/// file they came from
+static SYSCALL_OVERRIDES: OnceLock<(String, HashMap<u64, Syscall>)> = OnceLock::new();
+
+/// Parse a syscall CSV (see [`crate::syscall_definitions`] for the format).
+/// Lines starting with `#` are ignored.
+///
+/// Every line that can't be parsed is reported (as `file:line: problem`,
+/// like the build does for the built-in tables), not just the first.
+pub fn parse_syscalls(data: &str, source: &str) -> SimpleResult<HashMap<u64, Syscall>> {
+    let mut out: HashMap<u64, Syscall> = HashMap::new();
+    let mut errors: Vec<String> = Vec::new();
+
+    let mut rdr = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .comment(Some(b'#'))
+        .from_reader(data.as_bytes());
+
+    for result in rdr.records() {
+        let record = match result {
+            Ok(record) => record,
+            Err(e) => {
+                errors.push(format!("{}:{}: {}", source, e.position().map_or(0, |p| p.line()), e));
+                continue;
             }
+        };
+        let line = record.position().map(|p| p.line()).unwrap_or(0);
+
+        match SyscallDefinition::parse(&record.iter().collect::<Vec<&str>>()) {
+            Ok(definition) if out.contains_key(&definition.number) => errors.push(format!("{}:{}: syscall {} is defined more than once", source, line, definition.number)),
+            Ok(definition) => { out.insert(definition.number, Syscall::from_definition(definition)); },
+            Err(e) => errors.push(format!("{}:{}: {}", source, line, e)),
+        }
+    }
+
+    if !errors.is_empty() {
+        bail!("Couldn't parse syscall definitions:\n{}", errors.join("\n"));
+    }
+
+    Ok(out)
+}
+
+/// Load more syscall definitions from a CSV file (in the same format as the
+/// built-in `syscalls.csv`), which override the built-in ones with the same
+/// numbers. This can only be done once, before the analysis starts.
+///
+/// Returns how many syscalls were loaded.
+pub fn load_syscalls_csv(path: &Path) -> SimpleResult<usize> {
+    let data = std::fs::read_to_string(path)
+        .map_err(|e| SimpleError::new(format!("Couldn't read syscall definitions from {}: {}", path.display(), e)))?;
 
-            let syscall = Syscall {
-                name: record.get(1).unwrap().to_string(),
-                rdi: record.get(2).map(|r| SyscallEntry::new(r)),
-                rsi: record.get(3).map(|r| SyscallEntry::new(r)),
-                rdx: record.get(4).map(|r| SyscallEntry::new(r)),
-                r10: record.get(5).map(|r| SyscallEntry::new(r)),
-                r8:  record.get(6).map(|r| SyscallEntry::new(r)),
-                r9:  record.get(7).map(|r| SyscallEntry::new(r)),
-            };
+    let source = path.display().to_string();
+    let syscalls = parse_syscalls(&data, &source)?;
+    let count = syscalls.len();
 
-            out.insert(rax, syscall);
+    SYSCALL_OVERRIDES.set((source, syscalls))
+        .map_err(|_| SimpleError::new("Syscall definitions were already loaded"))?;
+
+    Ok(count)
+}
+
+/// Look up a syscall by number, preferring definitions loaded with
+/// [`load_syscalls_csv`] over the built-in ones.
+pub fn find_syscall(rax: u64) -> Option<&'static Syscall> {
+    SYSCALL_OVERRIDES.get()
+        .and_then(|(_, overrides)| overrides.get(&rax))
+        .or_else(|| SYSCALLS.get(&rax))
+}
+
+/// The file that extra syscall definitions were loaded from, if any
+pub fn syscalls_csv_source() -> Option<&'static str> {
+    SYSCALL_OVERRIDES.get().map(|(source, _)| source.as_str())
+}
+
+/// Get the name of an errno (eg, `ENOENT` for 2), if it's one we know.
+///
+/// Linux's errno numbers are the same in the kernel and libc, so we can use
+/// libc's list.
+pub fn errno_name(errno: i32) -> Option<String> {
+    match Errno::from_i32(errno) {
+        Errno::UnknownErrno => None,
+        e => Some(format!("{:?}", e)),
+    }
+}
+
+/// Memory that a syscall mapped (see [`MemoryMapping::from_syscall`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryMapping {
+    pub address: u64,
+    pub length: u64,
+    pub prot: u64,
+}
+
+impl MemoryMapping {
+    /// The memory a successful `mmap` mapped, from the syscall's number (in
+    /// rax), its arguments, and what it returned (`None` for any other
+    /// syscall, or if it failed).
+    pub fn from_syscall(abi: SyscallAbi, rax: u64, args: [u64; 6], returned: u64) -> Option<Self> {
+        if !abi.is_mmap(rax) || (-MAX_ERRNO..0).contains(&(returned as i64)) {
+            return None;
         }
 
-        out
-    };
+        Some(Self {
+            address: returned,
+            length: args[1],
+            prot: args[2],
+        })
+    }
+
+    pub fn is_executable(&self) -> bool {
+        self.prot & PROT_EXEC != 0
+    }
+
+    pub fn contains(&self, address: u64) -> bool {
+        address >= self.address && address - self.address < self.length
+    }
+}
+
+/// Like `mapped 0x7f0000000000 len 0x1000 r-x`
+impl fmt::Display for MemoryMapping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let permissions: String = [(PROT_READ, 'r'), (PROT_WRITE, 'w'), (PROT_EXEC, 'x')].iter()
+            .map(|(bit, c)| if self.prot & bit != 0 { *c } else { '-' })
+            .collect();
+
+        write!(f, "mapped 0x{:x} len 0x{:x} {}", self.address, self.length, permissions)
+    }
+}
+
+/// Show what a syscall returned: errors as a negative errno with its name
+/// (eg, `-2 (ENOENT)`), addresses in hex, and anything else in decimal.
+pub fn format_syscall_return(name: &str, value: u64) -> String {
+    let signed = value as i64;
+
+    if (-MAX_ERRNO..0).contains(&signed) {
+        match errno_name(-signed as i32) {
+            Some(errno) => format!("{} ({})", signed, errno),
+            None        => format!("{}", signed),
+        }
+    } else if ADDRESS_RETURNS.contains(&name) {
+        format!("0x{:08x}", value)
+    } else {
+        format!("{}", signed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+
+    #[test]
+    fn parse_syscalls_reads_definitions() {
+        let syscalls = parse_syscalls("# A comment\n0,sys_read,unsigned int fd,char *buf,size_t count\n60,sys_exit,int error_code\n", "test.csv").unwrap();
+
+        assert_eq!(syscalls.len(), 2);
+
+        let read = &syscalls[&0];
+        assert_eq!(read.name, "sys_read");
+        let buf = read.rsi.as_ref().unwrap();
+        assert!(buf.is_pointer && buf.is_string);
+
+        // The extras are attached, just like for the built-in table
+        assert_eq!(buf.length_from, Some(2));
+        assert!(buf.is_output);
+    }
+
+    #[test]
+    fn parse_syscalls_reports_every_bad_line() {
+        let data = [
+            "0,sys_read,unsigned int fd",
+            "zero,sys_write",
+            "2,",
+            "3,sys_close,unsigned int fd",
+            "3,sys_close_again,unsigned int fd",
+            "4,sys_stat,a,b,c,d,e,f,g",
+            "5,sys_fstat,nospace",
+        ].join("\n");
+
+        let error = parse_syscalls(&data, "test.csv").unwrap_err().to_string();
+        let lines: Vec<&str> = error.lines().skip(1).collect();
+
+        assert_eq!(lines.len(), 5, "{}", error);
+        assert!(lines[0].starts_with("test.csv:2: Couldn't parse the syscall number"), "{}", lines[0]);
+        assert_eq!(lines[1], "test.csv:3: missing the syscall name");
+        assert_eq!(lines[2], "test.csv:5: syscall 3 is defined more than once");
+        assert_eq!(lines[3], "test.csv:6: sys_stat has 7 parameters, but syscalls can only have 6");
+        assert_eq!(lines[4], "test.csv:7: Could not parse syscall parameter: nospace");
+    }
+
+    #[test]
+    fn mmap_mappings() {
+        let args = [0, 0x2000, 0x5, 0x22, 0xffffffffffffffff, 0];
+
+        let mapping = MemoryMapping::from_syscall(SyscallAbi::X86_64, 9, args, 0x7f0000000000).unwrap();
+        assert_eq!(mapping.to_string(), "mapped 0x7f0000000000 len 0x2000 r-x");
+        assert!(mapping.is_executable());
+        assert!(mapping.contains(0x7f0000001fff));
+        assert!(!mapping.contains(0x7f0000002000));
+
+        // 32-bit code maps memory with mmap2
+        assert!(MemoryMapping::from_syscall(SyscallAbi::X86, 192, args, 0xf7f00000).is_some());
+        assert!(MemoryMapping::from_syscall(SyscallAbi::X86, 9, args, 0xf7f00000).is_none());
+
+        // A failed mmap didn't map anything (-12 is ENOMEM)
+        assert!(MemoryMapping::from_syscall(SyscallAbi::X86_64, 9, args, -12i64 as u64).is_none());
+    }
+
+    #[test]
+    fn syscalls_are_checked_by_number() {
+        assert!(SyscallAbi::X86_64.is_write(1));
+        assert!(SyscallAbi::X86.is_write(4));
+        assert!(!SyscallAbi::X86.is_write(1));
+
+        assert!(SyscallAbi::X86_64.changes_memory_map(9));
+        assert!(!SyscallAbi::X86_64.changes_memory_map(1));
+
+        // Only the low 32 bits of eax are the number for the 32-bit ABI
+        assert!(SyscallAbi::X86.changes_memory_map(0xffffffff000000c0));
+    }
+
+    // The overrides can only be loaded once, so this is the only test that
+    // loads them (and it uses a syscall nothing else here needs)
+    #[test]
+    fn load_syscalls_csv_overrides_the_built_in_table() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "39,sys_getpid_override").unwrap();
+        writeln!(file, "1000,sys_made_up,int value").unwrap();
+
+        assert_eq!(load_syscalls_csv(file.path()).unwrap(), 2);
+        assert_eq!(syscalls_csv_source(), Some(file.path().display().to_string().as_str()));
+
+        assert_eq!(find_syscall(39).unwrap().name, "sys_getpid_override");
+        assert_eq!(find_syscall(1000).unwrap().name, "sys_made_up");
+
+        // Anything that isn't overridden comes from the built-in table
+        #[cfg(feature = "syscalls")]
+        assert_eq!(find_syscall(60).unwrap().name, "sys_exit");
+
+        assert!(load_syscalls_csv(file.path()).is_err());
+    }
 }
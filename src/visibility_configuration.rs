@@ -13,10 +13,15 @@
 
 use clap::Parser;
 use clap_num::maybe_hex;
+use serde::Deserialize;
 
 const DEFAULT_MASK: u64 = 0xFFFFFFFFFFFF0000;
 
-#[derive(Parser, Debug)]
+/// Where the harness loads the user's code
+pub const HARNESS_CODE_ADDRESS: u64 = 0x13370000;
+
+#[derive(Parser, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
 pub struct VisibilityConfiguration {
     /// Hide instructions that match this address (ANDed with the --hidden-mask)
     #[clap(long, parse(try_from_str=maybe_hex))]
@@ -33,18 +38,26 @@ pub struct VisibilityConfiguration {
     /// ANDed with the --visible-address before comparing - by default, 0xFFFFFFFFFFFF0000
     #[clap(long, parse(try_from_str=maybe_hex))]
     visible_mask:            Option<u64>,
+
+    /// If set, only show this many bytes starting at the visible address
+    /// (this is set by the harness, not on the commandline)
+    #[clap(skip)]
+    #[serde(skip)]
+    visible_length:          Option<u64>,
 }
 
 impl VisibilityConfiguration {
     /// Visibility settings when using the harness
     ///
-    /// The harness always loads code to `0x13370000`.
-    pub fn harness_visibility() -> Self {
+    /// The harness always loads code to `0x13370000`, and we know exactly
+    /// how long it is.
+    pub fn harness_visibility(code_length: usize) -> Self {
         Self {
             hidden_address:          None,
             hidden_mask:             None,
-            visible_address:         Some(0x13370000),
+            visible_address:         Some(HARNESS_CODE_ADDRESS),
             visible_mask:            Some(0xFFFF0000),
+            visible_length:          Some(code_length as u64),
         }
     }
 
@@ -55,9 +68,19 @@ impl VisibilityConfiguration {
             hidden_mask:             None,
             visible_address:         None,
             visible_mask:            None,
+            visible_length:          None,
         }
     }
 
+    /// Fill in any settings that aren't set from another configuration
+    /// (for example, one loaded from a file)
+    pub fn fill_from(&mut self, other: Self) {
+        self.hidden_address  = self.hidden_address.or(other.hidden_address);
+        self.hidden_mask     = self.hidden_mask.or(other.hidden_mask);
+        self.visible_address = self.visible_address.or(other.visible_address);
+        self.visible_mask    = self.visible_mask.or(other.visible_mask);
+    }
+
     pub fn is_visible(&self, address: u64) -> bool {
         // Suppress addresses that match the hidden_address / hidden_mask, if set
         if let Some(hidden_address) = self.hidden_address {
@@ -75,6 +98,12 @@ impl VisibilityConfiguration {
             if (address & mask) != visible_address {
                 return false;
             }
+
+            if let Some(visible_length) = self.visible_length {
+                if address < visible_address || address >= visible_address + visible_length {
+                    return false;
+                }
+            }
         }
 
         true
@@ -0,0 +1,117 @@
+//! End-to-end tests that trace real code with the embedded harness.
+//!
+//! These need ptrace, so they're skipped (with a note on stderr) if the
+//! environment doesn't allow it - see `mandrake --selftest`.
+use std::time::Instant;
+
+use mandrake::harness::Harness;
+use mandrake::mandrake::{AnalyzeMode, Mandrake};
+use mandrake::mandrake_output::{ExitReason, MandrakeOutput};
+use mandrake::selftest::check_environment;
+
+/// Writes "Hello World!" to stdout, then returns
+const HELLO_WORLD: &str = "e80d00000048656c6c6f20576f726c64210048c7c00100000048c7c7010000005e48c7c20c0000000f05c3";
+
+/// `mov ecx, 5000` / `dec ecx` / `jnz -4` / `ret` - 10,002 instructions
+const LOOP_10000: &str = "b988130000ffc975fcc3";
+
+/// The embedded harness, or None if Mandrake can't run here
+fn harness() -> Option<Harness> {
+    let harness = Harness::embedded().unwrap();
+
+    let failed: Vec<String> = check_environment(&harness.path()).into_iter()
+        .filter(|result| !result.passed)
+        .map(|result| format!("{}: {}", result.name, result.detail))
+        .collect();
+
+    if !failed.is_empty() {
+        eprintln!("Skipping, Mandrake can't run here: {}", failed.join(", "));
+        return None;
+    }
+
+    Some(harness)
+}
+
+#[test]
+fn trace_10000_instructions() {
+    let harness = match harness() {
+        Some(harness) => harness,
+        None => return,
+    };
+
+    let mandrake = Mandrake::new(64, 6, None, false, false, false);
+
+    let started = Instant::now();
+    let result = mandrake.analyze_code(hex::decode(LOOP_10000).unwrap(), &harness.path(), false).unwrap();
+    let elapsed = started.elapsed();
+
+    eprintln!("Traced {} instructions in {:?} ({:.0} / second)", result.instructions_executed, elapsed, result.instructions_executed as f64 / elapsed.as_secs_f64());
+
+    assert!(matches!(result.exit_reason, Some(ExitReason::CleanExit { code: 0 })));
+    assert_eq!(10002, result.instructions_executed);
+    assert_eq!(10002, result.history.len());
+
+    // Every step has to have read the code and the stack
+    for entry in &result.history {
+        assert!(entry.registers["rip"].as_instruction.is_some(), "rip instruction at {}", entry.index);
+        assert!(entry.registers["rsp"].memory.is_some(), "rsp memory at {}", entry.index);
+    }
+}
+
+#[test]
+fn analyze_smart_does_less() {
+    let harness = match harness() {
+        Some(harness) => harness,
+        None => return,
+    };
+
+    let code = hex::decode(HELLO_WORLD).unwrap();
+    let all = Mandrake::new(64, 6, None, false, false, false)
+        .with_analyze_mode(AnalyzeMode::All)
+        .analyze_code(code.clone(), &harness.path(), false).unwrap();
+    let smart = Mandrake::new(64, 6, None, false, false, false)
+        .with_analyze_mode(AnalyzeMode::Smart)
+        .analyze_code(code, &harness.path(), false).unwrap();
+
+    eprintln!("Deep analyses: {} with all, {} with smart", all.deep_analyses, smart.deep_analyses);
+
+    // The same code ran...
+    assert_eq!(all.instructions_executed, smart.instructions_executed);
+    assert_eq!(all.stdout, smart.stdout);
+    assert_eq!(
+        all.history.iter().map(|entry| entry.registers["rip"].value).collect::<Vec<_>>(),
+        smart.history.iter().map(|entry| entry.registers["rip"].value).collect::<Vec<_>>(),
+    );
+
+    // ...but with a lot less work
+    assert!(smart.deep_analyses > 0);
+    assert!(smart.deep_analyses * 2 < all.deep_analyses);
+
+    // The syscall still gets its arguments analyzed (rsi is the string)
+    let syscall = smart.history.iter().find(|entry| entry.registers["rip"].as_instruction.as_deref() == Some("syscall")).unwrap();
+    assert_eq!(Some("Hello World!"), syscall.registers["rsi"].as_string.as_deref());
+}
+
+#[test]
+fn deterministic_runs_match() {
+    let harness = match harness() {
+        Some(harness) => harness,
+        None => return,
+    };
+
+    let mandrake = Mandrake::new(64, 6, None, false, false, false).with_deterministic(true);
+    let first = mandrake.analyze_code(hex::decode(HELLO_WORLD).unwrap(), &harness.path(), false).unwrap();
+    let second = mandrake.analyze_code(hex::decode(HELLO_WORLD).unwrap(), &harness.path(), false).unwrap();
+
+    // Where each instruction was, what it was, and where the stack was (which
+    // moves around with ASLR)
+    let steps = |output: &MandrakeOutput| -> Vec<(u64, Option<String>, u64)> {
+        output.history.iter().map(|entry| {
+            (entry.registers["rip"].value, entry.registers["rip"].as_instruction.clone(), entry.registers["rsp"].value)
+        }).collect()
+    };
+
+    assert!(!first.history.is_empty());
+    assert_eq!(steps(&first), steps(&second));
+    assert_eq!(first.stdout, second.stdout);
+}